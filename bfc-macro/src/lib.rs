@@ -0,0 +1,101 @@
+// `brainfuck!("++[>+<-].")` and `brainfuck!(file = "prog.bf")` expand to a
+// function running the optimized program, as emitted by `RustBackend`:
+//
+//     fn<R: Read, W: Write>(input: R, output: W) -> io::Result<u8>
+//
+// Files are relative to the directory of the manifest of the crate using the
+// macro.
+extern crate brainfuck;
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use proc_macro2::TokenStream;
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+use brainfuck::{backend, ir, opt};
+use brainfuck::ir::ParenError;
+
+enum Source {
+    Code(LitStr),
+    File(LitStr),
+}
+
+impl Parse for Source {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            return Ok(Source::Code(input.parse()?));
+        }
+
+        let key: Ident = input.parse()?;
+        if key != "file" {
+            return Err(syn::Error::new(key.span(), "expected a string or `file = \"...\"`"));
+        }
+        input.parse::<Token![=]>()?;
+        Ok(Source::File(input.parse()?))
+    }
+}
+
+#[proc_macro]
+pub fn brainfuck(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let source = syn::parse_macro_input!(input as Source);
+    expand(source).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+fn expand(source: Source) -> syn::Result<TokenStream> {
+    let (code, literal, dependency) = match source {
+        Source::Code(literal) => (literal.value().into_bytes(), literal, None),
+        Source::File(literal) => {
+            let root = env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+            let path = root.join(literal.value());
+            let code = fs::read(&path).map_err(|err| {
+                syn::Error::new(literal.span(), format!("{}: {}", path.display(), err))
+            })?;
+            let path = path.to_string_lossy().into_owned();
+            (code, literal, Some(path))
+        },
+    };
+
+    let ir = ir::build_ir(&code).map_err(|err| {
+        let message = match err {
+            ParenError::LeftMissing(pos) => format!("unmatched `]` at {}", location(&code, pos)),
+            ParenError::RightMissing(pos) => format!("unmatched `[` at {}", location(&code, pos)),
+        };
+        syn::Error::new(literal.span(), message)
+    })?;
+    let ir = opt::run_opts(ir);
+
+    let mut function = Vec::new();
+    let backend = backend::RustBackend::new(&mut function).with_function("brainfuck");
+    backend::use_backend(backend, &ir)
+        .map_err(|err| syn::Error::new(literal.span(), err.to_string()))?;
+    let function: TokenStream = String::from_utf8(function).unwrap().parse()
+        .map_err(|_| syn::Error::new(literal.span(), "invalid generated code"))?;
+
+    // rebuilds the crate when the file changes
+    let dependency = dependency.map(|path| quote! {
+        const _: &[u8] = include_bytes!(#path);
+    });
+    Ok(quote! {
+        {
+            #dependency
+            #function
+            brainfuck
+        }
+    })
+}
+
+// line and column of a byte of the source
+fn location(code: &[u8], pos: usize) -> String {
+    let before = &code[..pos];
+    let line = before.iter().filter(|&&byte| byte == b'\n').count() + 1;
+    let column = before.iter().rev().take_while(|&&byte| byte != b'\n').count() + 1;
+    format!("line {}, column {}", line, column)
+}
@@ -0,0 +1,25 @@
+#[macro_use]
+extern crate bfc_macro;
+
+use std::io;
+
+#[test]
+fn inline_program() {
+    let program = brainfuck!("++++++++[>++++++++<-]>+. cat ,[.,]");
+    let mut output = Vec::new();
+    let cell = program(&b"BC\0"[..], &mut output).unwrap();
+    assert_eq!(output, b"ABC");
+    assert_eq!(cell, 0);
+
+    // reading past the end of the input
+    let err = brainfuck!(",,")(&b"B"[..], io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn file_program() {
+    let hello = brainfuck!(file = "../bf_ex/hello.bf");
+    let mut output = Vec::new();
+    hello(io::empty(), &mut output).unwrap();
+    assert_eq!(output, b"Hello World!\n");
+}
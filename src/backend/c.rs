@@ -1,14 +1,280 @@
 use std::io::{self, Write};
+use std::path::PathBuf;
 
-use ir::Atom;
-use backend::Backend;
+use ir::{self, Atom, AtomSpan, Span, Value};
+use backend::{Backend, IoEncoding, IoOptions, TapeImage, Profile, EOF_STATUS, OUT_OF_FUEL_STATUS};
 
 const MEM_SIZE: usize = 30000;
+// longest brainfuck snippet in the comment of a loop
+const SNIPPET_LEN: usize = 60;
+
+const GETBYTE_FN: &str = "\
+static int bf_getbyte(void) {
+\treturn getchar();
+}
+";
+
+const GETBYTE_CRLF_FN: &str = "\
+static int bf_last_cr = 0;
+static int bf_getbyte(void) {
+\tint c = getchar();
+\tif (bf_last_cr && c == '\\n') c = getchar();
+\tbf_last_cr = c == '\\r';
+\treturn bf_last_cr ? '\\n' : c;
+}
+";
+
+const UNICODE_FNS: &str = "\
+static long bf_read_unicode(void) {
+\tlong c = bf_getbyte();
+\tint n;
+\tif (c < 0x80) return c;
+\telse if (c < 0xC0) return 0xFFFD;
+\telse if (c < 0xE0) { n = 1; c &= 0x1F; }
+\telse if (c < 0xF0) { n = 2; c &= 0x0F; }
+\telse if (c < 0xF8) { n = 3; c &= 0x07; }
+\telse return 0xFFFD;
+\twhile (n--) {
+\t\tint b = bf_getbyte();
+\t\tif ((b & 0xC0) != 0x80) return 0xFFFD;
+\t\tc = (c << 6) | (b & 0x3F);
+\t}
+\treturn c;
+}
+static void bf_print_unicode(long c) {
+\tif (c < 0 || c > 0x10FFFF || (c >= 0xD800 && c <= 0xDFFF)) c = 0xFFFD;
+\tif (c < 0x80) {
+\t\tputchar(c);
+\t} else if (c < 0x800) {
+\t\tputchar(0xC0 | (c >> 6));
+\t\tputchar(0x80 | (c & 0x3F));
+\t} else if (c < 0x10000) {
+\t\tputchar(0xE0 | (c >> 12));
+\t\tputchar(0x80 | ((c >> 6) & 0x3F));
+\t\tputchar(0x80 | (c & 0x3F));
+\t} else {
+\t\tputchar(0xF0 | (c >> 18));
+\t\tputchar(0x80 | ((c >> 12) & 0x3F));
+\t\tputchar(0x80 | ((c >> 6) & 0x3F));
+\t\tputchar(0x80 | (c & 0x3F));
+\t}
+}
+";
+
+const DECIMAL_FNS: &str = "\
+static int bf_read_decimal(long *result) {
+\tunsigned long value = 0;
+\tint negative = 0;
+\tint c = bf_getbyte();
+\twhile (c == ' ' || (c >= '\\t' && c <= '\\r')) c = bf_getbyte();
+\tif (c == EOF) return EOF;
+\tif (c == '-') {
+\t\tnegative = 1;
+\t\tc = bf_getbyte();
+\t}
+\twhile (c >= '0' && c <= '9') {
+\t\tvalue = value * 10 + (c - '0');
+\t\tc = bf_getbyte();
+\t}
+\t*result = (long)(negative ? 0 - value : value);
+\treturn 0;
+}
+static void bf_print_decimal(long value) {
+\tchar buf[24];
+\tint i = sizeof(buf);
+\tunsigned long v = value < 0 ? 0 - (unsigned long)value : (unsigned long)value;
+\tbuf[--i] = '\\n';
+\tdo {
+\t\tbuf[--i] = '0' + v % 10;
+\t\tv /= 10;
+\t} while (v);
+\tif (value < 0) buf[--i] = '-';
+\twhile (i < (int)sizeof(buf)) putchar(buf[i++]);
+}
+";
+
+// same format as `image::write_hex_dump`
+const DUMP_FN: &str = "\
+static void bf_dump_tape(const char *path) {
+\tFILE *file = fopen(path, \"w\");
+\tlong i;
+\tif (!file) {
+\t\tperror(path);
+\t\treturn;
+\t}
+\tfprintf(file, \"ptr %ld\\n\", (long)(ptr - memory));
+\tfor (i = 0; i < BF_TAPE_SIZE; i++) {
+\t\tfprintf(file, \"%02x%c\", (unsigned char)memory[i],
+\t\t\ti % 16 == 15 || i == BF_TAPE_SIZE - 1 ? '\\n' : ' ');
+\t}
+\tfclose(file);
+}
+";
+
+// Shared by every library so that their headers can be included together,
+// the tape holds cells of the type of each library.
+const STATE_TYPEDEF: &str = "\
+#ifndef BF_STATE_DEFINED
+#define BF_STATE_DEFINED
+typedef struct bf_state {
+\tvoid *tape;
+\tsize_t ptr;
+\tvoid *ctx;
+\tint (*read)(void *ctx);
+\tvoid (*write)(void *ctx, int byte);
+} bf_state;
+#endif
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CStandard {
+    // without `stdint.h`, cells get the smallest standard type of their size
+    C89,
+    C99,
+    // the tape size is checked with `_Static_assert`
+    C11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CCellType {
+    I8,
+    U8,
+    I16,
+    U16,
+    // `long` in C89, which may be wider
+    I32,
+    U32,
+}
+
+impl CCellType {
+    fn bits(self) -> u32 {
+        match self {
+            CCellType::I8 | CCellType::U8 => 8,
+            CCellType::I16 | CCellType::U16 => 16,
+            CCellType::I32 | CCellType::U32 => 32,
+        }
+    }
+
+    fn unsigned(self) -> CCellType {
+        match self {
+            CCellType::I8 | CCellType::U8 => CCellType::U8,
+            CCellType::I16 | CCellType::U16 => CCellType::U16,
+            CCellType::I32 | CCellType::U32 => CCellType::U32,
+        }
+    }
+
+    fn c_name(self, standard: CStandard) -> &'static str {
+        match (self, standard) {
+            (CCellType::I8, CStandard::C89) => "signed char",
+            (CCellType::U8, CStandard::C89) => "unsigned char",
+            (CCellType::I16, CStandard::C89) => "short",
+            (CCellType::U16, CStandard::C89) => "unsigned short",
+            (CCellType::I32, CStandard::C89) => "long",
+            (CCellType::U32, CStandard::C89) => "unsigned long",
+            (CCellType::I8, _) => "int8_t",
+            (CCellType::U8, _) => "uint8_t",
+            (CCellType::I16, _) => "int16_t",
+            (CCellType::U16, _) => "uint16_t",
+            (CCellType::I32, _) => "int32_t",
+            (CCellType::U32, _) => "uint32_t",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CBackendOptions {
+    pub tape_size: usize,
+    pub cell_type: CCellType,
+    // the tape is allocated when entering the function and freed at exit
+    // instead of being a static array
+    pub heap_tape: bool,
+    pub standard: CStandard,
+    // unit of indentation
+    pub indent: String,
+    // `int name(void)` instead of `main`, it resets the tape on every call
+    // and returns what `main` would exit with
+    pub function_name: Option<String>,
+    // `int name(bf_state *st)` running on the tape of `st` with its I/O
+    // callbacks, `st->ptr` is the index of the current cell before and after
+    // the call. It returns 0, or `EOF_STATUS` once `st->read` returns a
+    // negative value.
+    pub library: bool,
+}
+
+impl Default for CBackendOptions {
+    fn default() -> Self {
+        CBackendOptions {
+            tape_size: MEM_SIZE,
+            cell_type: CCellType::I8,
+            heap_tape: false,
+            standard: CStandard::C99,
+            indent: String::from("\t"),
+            function_name: None,
+            library: false,
+        }
+    }
+}
+
+// Locates the atoms of a freshly built IR in their source, the spans are
+// followed along the atoms pushed to the backend.
+#[derive(Debug, Clone)]
+struct SourceMap {
+    path: String,
+    source: Vec<u8>,
+    spans: Vec<AtomSpan>,
+    // indices of the atoms being pushed, from the top level down
+    indices: Vec<usize>,
+}
+
+impl SourceMap {
+    // the spans are dropped when they don't describe the whole IR, e.g. once
+    // it has been optimized
+    fn check_spans(&mut self, ir: &[Atom]) {
+        if self.indices.is_empty() && !ir::spans_match(ir, &self.spans) {
+            self.spans.clear();
+        }
+    }
+
+    fn current_span(&self) -> Option<Span> {
+        let (&last, parents) = self.indices.split_last()?;
+        let mut spans = &self.spans;
+        for &index in parents {
+            spans = &spans.get(index)?.sub;
+        }
+        spans.get(last).map(|atom_span| atom_span.span)
+    }
+
+    // the commands of the current loop, without the comments around them
+    fn snippet(&self) -> Option<String> {
+        let span = self.current_span()?;
+        let mut snippet: String = self.source[span.start..span.end].iter()
+            .filter(|byte| b"+-<>.,[]".contains(byte))
+            .map(|&byte| byte as char)
+            .collect();
+        if snippet.len() > SNIPPET_LEN {
+            snippet.truncate(SNIPPET_LEN - 3);
+            snippet.push_str("...");
+        }
+        Some(snippet)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct CBackend<W: Write> {
     writer: W,
     current_tab: usize,
+    options: CBackendOptions,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    dump: Option<PathBuf>,
+    exit_cell: bool,
+    fuel: Option<u64>,
+    profile: Option<Profile>,
+    // number of the next loop in the profile
+    loop_id: usize,
+    source: Option<SourceMap>,
+    // whether the function jumps to its `bf_exit` epilogue
+    exits_early: bool,
 }
 
 impl<W: Write> CBackend<W> {
@@ -16,11 +282,260 @@ impl<W: Write> CBackend<W> {
         CBackend {
             writer,
             current_tab: 1,
+            options: CBackendOptions::default(),
+            io: IoOptions::default(),
+            image: None,
+            dump: None,
+            exit_cell: false,
+            fuel: None,
+            profile: None,
+            loop_id: 0,
+            source: None,
+            exits_early: false,
+        }
+    }
+
+    pub fn with_options(mut self, options: CBackendOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    // the image is embedded in the initializer of the tape, or copied into
+    // it by the function
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    pub fn with_dump<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.dump = Some(path.into());
+        self
+    }
+
+    // `main` returns the current cell instead of 0
+    pub fn with_exit_cell(mut self) -> Self {
+        self.exit_cell = true;
+        self
+    }
+
+    // the function returns `OUT_OF_FUEL_STATUS` through the epilogue instead
+    // of running more than `fuel` loop iterations
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // loops get `__builtin_expect` and unroll pragmas, both understood by gcc
+    // and clang
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    // `#line` directives locate the atoms at their `spans` in `source`, as
+    // given by `ir::build_ir_with_spans`, and loops are preceded by their
+    // brainfuck. Only unoptimized IR is located but the targets of
+    // multiplications are always named after their offset.
+    pub fn with_source<P: Into<PathBuf>>(mut self, path: P, source: Vec<u8>,
+                                         spans: Vec<AtomSpan>) -> Self {
+        self.source = Some(SourceMap {
+            path: path.into().to_string_lossy().into_owned(),
+            source,
+            spans,
+            indices: Vec::new(),
+        });
+        self
+    }
+
+    fn write_line_directive(&mut self) -> io::Result<()> {
+        let location = self.source.as_ref().and_then(|source| {
+            source.current_span().map(|span| (span.line, utils::c_string(&source.path)))
+        });
+        match location {
+            Some((line, path)) => writeln!(&mut self.writer, "#line {} {}", line, path),
+            None => Ok(()),
+        }
+    }
+
+    fn cell_type(&self) -> &'static str {
+        self.options.cell_type.c_name(self.options.standard)
+    }
+
+    fn unsigned_cell_type(&self) -> &'static str {
+        self.options.cell_type.unsigned().c_name(self.options.standard)
+    }
+
+    // constants are truncated to the width of the cells, 32 bits cells are
+    // computed unsigned to stay clear of signed overflows
+    fn constant(&self, value: Value) -> String {
+        match self.options.cell_type.bits() {
+            8 => (value as i8).to_string(),
+            16 => (value as i16).to_string(),
+            _ => format!("({}){}", self.unsigned_cell_type(), value as i32),
+        }
+    }
+
+    fn write_memory(&mut self, image: &TapeImage) -> io::Result<()> {
+        let cell_type = self.cell_type();
+        let tape_size = self.options.tape_size;
+        writeln!(&mut self.writer, "#define BF_TAPE_SIZE {}", tape_size)?;
+        if self.options.heap_tape {
+            writeln!(&mut self.writer, "static {} *memory;", cell_type)?;
+        } else if image.cells.is_empty() || self.options.function_name.is_some() {
+            writeln!(&mut self.writer, "static {} memory[BF_TAPE_SIZE];", cell_type)?;
+        } else {
+            writeln!(&mut self.writer, "static {} memory[BF_TAPE_SIZE] = {{", cell_type)?;
+            self.write_cells(&image.cells)?;
+            writeln!(&mut self.writer, "}};")?;
+        }
+        writeln!(&mut self.writer, "static {} *ptr;", cell_type)?;
+        if self.options.standard == CStandard::C11 {
+            writeln!(&mut self.writer, "_Static_assert(BF_TAPE_SIZE <= SIZE_MAX / sizeof *ptr, \
+                                        \"the tape is too large\");")?;
+        }
+
+        if !image.cells.is_empty() && self.copies_image() {
+            writeln!(&mut self.writer, "static const {} bf_image[{}] = {{", cell_type,
+                     image.cells.len())?;
+            self.write_cells(&image.cells)?;
+            writeln!(&mut self.writer, "}};")?;
+        }
+        Ok(())
+    }
+
+    // the tape is either initialized with the image or gets it at every call
+    fn copies_image(&self) -> bool {
+        self.options.heap_tape || self.options.function_name.is_some()
+    }
+
+    // the bytes of the image are signed like the cells
+    fn write_cells(&mut self, cells: &[u8]) -> io::Result<()> {
+        let signed = self.options.cell_type.unsigned() != self.options.cell_type;
+        for line in cells.chunks(16) {
+            let cells: Vec<String> = line.iter().map(|&cell| {
+                if signed { (cell as i8).to_string() } else { cell.to_string() }
+            }).collect();
+            writeln!(&mut self.writer, "{}{},", self.options.indent, cells.join(", "))?;
+        }
+        Ok(())
+    }
+
+    // the fuel and the pointer are reset on every call
+    fn write_function_start(&mut self, image: &TapeImage) -> io::Result<()> {
+        match self.options.function_name {
+            Some(ref name) => writeln!(&mut self.writer, "int {}(void) {{", name)?,
+            None => writeln!(&mut self.writer, "int main(void) {{")?,
+        }
+
+        self.write_tab()?;
+        writeln!(&mut self.writer, "int bf_status;")?;
+        if self.options.heap_tape {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "memory = calloc(BF_TAPE_SIZE, sizeof *memory);")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if (!memory) return -1;")?;
+        } else if self.options.function_name.is_some() {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "memset(memory, 0, sizeof memory);")?;
+        }
+        if !image.cells.is_empty() && self.copies_image() {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "memcpy(memory, bf_image, sizeof bf_image);")?;
+        }
+        self.write_tab()?;
+        writeln!(&mut self.writer, "ptr = memory + {};", image.ptr)?;
+        if let Some(fuel) = self.fuel {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "bf_fuel = {};", fuel)?;
+        }
+        Ok(())
+    }
+
+    // the C file repeats the declarations of the header, the I/O and the tape
+    // are left to the caller
+    fn write_library_start(&mut self, image: &TapeImage) -> io::Result<()> {
+        let unsupported = if self.io != IoOptions::default() {
+            Some("I/O encodings")
+        } else if !image.cells.is_empty() || image.ptr != 0 {
+            Some("tape images")
+        } else if self.options.heap_tape {
+            Some("heap tapes")
+        } else if self.dump.is_some() {
+            Some("tape dumps")
+        } else if self.exit_cell {
+            Some("exit statuses")
+        } else if self.fuel.is_some() {
+            Some("fuel")
+        } else {
+            None
+        };
+        if let Some(feature) = unsupported {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("C libraries don't support {}", feature)));
         }
+
+        let name = library_name(&self.options)?.to_owned();
+        write_library_declarations(&mut self.writer, &self.options, &name)?;
+        writeln!(&mut self.writer, "int {}(bf_state *st) {{", name)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "{}_cell *memory = st->tape;", name)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "{}_cell *ptr = memory + st->ptr;", name)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "int bf_status;")
+    }
+
+    // sets the status and jumps to the epilogue
+    fn write_early_exit(&mut self, status: i32) -> io::Result<()> {
+        self.exits_early = true;
+        self.current_tab += 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "bf_status = {};", status)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "goto bf_exit;")?;
+        self.current_tab -= 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "}}")
+    }
+
+    fn write_exit_label(&mut self) -> io::Result<()> {
+        if self.exits_early {
+            writeln!(&mut self.writer, "bf_exit:")?;
+        }
+        Ok(())
+    }
+
+    fn write_io_functions(&mut self) -> io::Result<()> {
+        if self.io.encoding == IoEncoding::Bytes && !self.io.translate_newlines {
+            return Ok(());
+        }
+
+        if self.io.translate_newlines {
+            self.write_template(GETBYTE_CRLF_FN)?;
+        } else {
+            self.write_template(GETBYTE_FN)?;
+        }
+
+        match self.io.encoding {
+            IoEncoding::Bytes => Ok(()),
+            IoEncoding::Unicode => self.write_template(UNICODE_FNS),
+            IoEncoding::Decimal => self.write_template(DECIMAL_FNS),
+        }
+    }
+
+    // the templates are indented with tabs
+    fn write_template(&mut self, template: &str) -> io::Result<()> {
+        let code = template.replace('\t', &self.options.indent);
+        write!(&mut self.writer, "{}", code)
     }
 
     fn write_tab(&mut self) -> io::Result<()> {
-        write!(&mut self.writer, "{}", "\t".repeat(self.current_tab))
+        write!(&mut self.writer, "{}", self.options.indent.repeat(self.current_tab))
     }
 }
 
@@ -29,17 +544,77 @@ impl<W: Write> Backend for CBackend<W> {
     type Error = io::Error;
 
     fn initialize(&mut self) -> Result<(), Self::Error> {
+        let image = self.image.take().unwrap_or_default();
+        let tape_size = self.options.tape_size;
+        if tape_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        if image.cells.len() > tape_size || image.ptr >= tape_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "tape image doesn't fit in the tape"));
+        }
+        if self.options.library {
+            return self.write_library_start(&image);
+        }
+        let c89 = self.options.standard == CStandard::C89;
+        if c89 && self.fuel.is_some_and(|fuel| fuel > u64::from(u32::MAX)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "fuel doesn't fit an unsigned long in C89"));
+        }
+
         writeln!(&mut self.writer, "#include <stdlib.h>")?;
         writeln!(&mut self.writer, "#include <stdio.h>")?;
-        writeln!(&mut self.writer, "#include <stdint.h>")?;
+        if !c89 {
+            writeln!(&mut self.writer, "#include <stdint.h>")?;
+        }
+        if self.copies_image() {
+            writeln!(&mut self.writer, "#include <string.h>")?;
+        }
+        self.write_io_functions()?;
 
-        writeln!(&mut self.writer, "int8_t memory[{}];", MEM_SIZE)?;
-        writeln!(&mut self.writer, "int8_t* ptr = memory;")?;
-        writeln!(&mut self.writer, "int main() {{")?;
-        Ok(())
+        self.write_memory(&image)?;
+        if self.fuel.is_some() {
+            let fuel_type = if c89 { "unsigned long" } else { "uint64_t" };
+            writeln!(&mut self.writer, "static {} bf_fuel;", fuel_type)?;
+        }
+        if self.dump.is_some() {
+            self.write_template(DUMP_FN)?;
+        }
+        self.write_function_start(&image)
     }
 
     fn finalize(mut self) -> Result<(), Self::Error> {
+        if self.options.library {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "bf_status = 0;")?;
+            self.write_exit_label()?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "st->ptr = ptr - memory;")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "return bf_status;")?;
+            return writeln!(&mut self.writer, "}}");
+        }
+
+        // the exit status is the current cell modulo 256
+        let status = if self.exit_cell { "(unsigned char)*ptr" } else { "0" };
+        self.write_tab()?;
+        writeln!(&mut self.writer, "bf_status = {};", status)?;
+        self.write_exit_label()?;
+
+        if let Some(path) = self.dump.take() {
+            let path = path.to_str().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "dump path isn't valid UTF-8")
+            })?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "bf_dump_tape({});", utils::c_string(path))?;
+        }
+
+        if self.options.heap_tape {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "free(memory);")?;
+        }
+        self.write_tab()?;
+        writeln!(&mut self.writer, "return bf_status;")?;
         writeln!(&mut self.writer, "}}")
     }
 
@@ -48,38 +623,208 @@ impl<W: Write> Backend for CBackend<W> {
         writeln!(&mut self.writer, "ptr += {};", offset)
     }
 
-    fn push_set_value(&mut self, value: i8, offset: isize) -> Result<(), Self::Error> {
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
         self.write_tab()?;
+        let value = self.constant(value);
         writeln!(&mut self.writer, "*(ptr + {}) = {};", offset, value)
     }
 
-    fn push_inc_value(&mut self, inc: i8, offset: isize) -> Result<(), Self::Error> {
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
         self.write_tab()?;
+        let inc = self.constant(inc);
         writeln!(&mut self.writer, "*(ptr + {}) += {};", offset, inc)
     }
 
     fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
         self.write_tab()?;
-        writeln!(&mut self.writer, "putchar(*(ptr + {}));", offset)
+        let unsigned_cell_type = self.unsigned_cell_type();
+        match self.io.encoding {
+            IoEncoding::Bytes if self.options.library => {
+                writeln!(&mut self.writer, "st->write(st->ctx, ({})*(ptr + {}));",
+                         unsigned_cell_type, offset)
+            },
+            IoEncoding::Bytes => {
+                writeln!(&mut self.writer, "putchar(*(ptr + {}));", offset)
+            },
+            IoEncoding::Unicode => {
+                writeln!(&mut self.writer, "bf_print_unicode(({})*(ptr + {}));",
+                         unsigned_cell_type, offset)
+            },
+            IoEncoding::Decimal => {
+                writeln!(&mut self.writer, "bf_print_decimal(({})*(ptr + {}));",
+                         unsigned_cell_type, offset)
+            },
+        }
     }
 
+    // the function returns `EOF_STATUS` at the end of the input
     fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
         self.write_tab()?;
-        writeln!(&mut self.writer, "*(ptr + {}) = getchar();", offset)
+        writeln!(&mut self.writer, "{{")?;
+        self.current_tab += 1;
+        self.write_tab()?;
+        if self.io.encoding == IoEncoding::Decimal {
+            writeln!(&mut self.writer, "long bf_c;")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if (bf_read_decimal(&bf_c) == EOF) {{")?;
+        } else {
+            let read_call = match self.io.encoding {
+                _ if self.options.library => "st->read(st->ctx)",
+                IoEncoding::Bytes if !self.io.translate_newlines => "getchar()",
+                IoEncoding::Bytes => "bf_getbyte()",
+                _ => "bf_read_unicode()",
+            };
+            writeln!(&mut self.writer, "long bf_c = {};", read_call)?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if (bf_c < 0) {{")?;
+        }
+        self.write_early_exit(EOF_STATUS)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "*(ptr + {}) = bf_c;", offset)?;
+        self.current_tab -= 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "}}")
     }
 
-    fn push_multiply(&mut self, factor: i8, offset: isize) -> Result<(), Self::Error> {
+    // the source map follows the atoms, loops write their directive after
+    // their comment
+    fn push_atoms(&mut self, ir: &Vec<Atom>) -> Result<(), Self::Error> {
+        if let Some(ref mut source) = self.source {
+            source.check_spans(ir);
+        }
+
+        for (index, atom) in ir.iter().enumerate() {
+            if let Some(ref mut source) = self.source {
+                source.indices.push(index);
+            }
+            match *atom {
+                Atom::Loop(_) => (),
+                _ => self.write_line_directive()?,
+            }
+            self.push_atom(atom)?;
+            if let Some(ref mut source) = self.source {
+                source.indices.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        let factor = self.constant(factor);
+        let cell = if self.options.cell_type.bits() == 32 {
+            format!("({})*(ptr)", self.unsigned_cell_type())
+        } else {
+            String::from("*(ptr)")
+        };
+        if self.source.is_none() {
+            self.write_tab()?;
+            return writeln!(&mut self.writer, "*(ptr + {}) += {} * {};", offset, cell, factor);
+        }
+
+        // a block naming the target for debuggers, e.g. `cell_p2` for `ptr + 2`
+        let name = if offset < 0 {
+            format!("cell_m{}", -offset)
+        } else {
+            format!("cell_p{}", offset)
+        };
+        let cell_type = self.cell_type();
         self.write_tab()?;
-        writeln!(&mut self.writer, "*(ptr + {}) += *(ptr) * {};", offset, factor)
+        writeln!(&mut self.writer, "{{")?;
+        self.current_tab += 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "{} *{} = ptr + {};", cell_type, name, offset)?;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "*{} += {} * {};", name, cell, factor)?;
+        self.current_tab -= 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "}}")
     }
 
     fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
-        self.write_tab()?;
-        writeln!(&mut self.writer, "while(*ptr) {{")?;
+        let counts = self.profile.as_ref().and_then(|profile| profile.get(self.loop_id));
+        self.loop_id += 1;
+
+        let snippet = self.source.as_ref().and_then(SourceMap::snippet);
+        if let Some(snippet) = snippet {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "/* {} */", snippet)?;
+        }
+        match counts {
+            Some(counts) => {
+                self.write_tab()?;
+                writeln!(&mut self.writer, "#pragma GCC unroll {}", counts.unroll_count())?;
+                self.write_line_directive()?;
+                self.write_tab()?;
+                let expected = (counts.iterations > counts.entries) as u8;
+                writeln!(&mut self.writer, "while(__builtin_expect(*ptr != 0, {})) {{", expected)?;
+            },
+            None => {
+                self.write_line_directive()?;
+                self.write_tab()?;
+                writeln!(&mut self.writer, "while(*ptr) {{")?;
+            },
+        }
         self.current_tab += 1;
+        if self.fuel.is_some() {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if (!bf_fuel--) {{")?;
+            self.write_early_exit(OUT_OF_FUEL_STATUS)?;
+        }
         self.push_atoms(sub)?;
         self.current_tab -= 1;
         self.write_tab()?;
         writeln!(&mut self.writer, "}}")
     }
 }
+
+// The header declaring the function of a library, its tape must have room for
+// `NAME_TAPE_SIZE` cells of type `name_cell`.
+pub fn write_library_header<W: Write>(mut writer: W, options: &CBackendOptions)
+                                      -> io::Result<()> {
+    let name = library_name(options)?;
+    let guard = format!("{}_H", name.to_uppercase());
+    writeln!(writer, "#ifndef {}", guard)?;
+    writeln!(writer, "#define {}", guard)?;
+    write_library_declarations(&mut writer, options, name)?;
+    writeln!(writer, "#endif")
+}
+
+fn library_name(options: &CBackendOptions) -> io::Result<&str> {
+    options.function_name.as_deref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "libraries need a function name")
+    })
+}
+
+fn write_library_declarations<W: Write>(writer: &mut W, options: &CBackendOptions, name: &str)
+                                        -> io::Result<()> {
+    writeln!(writer, "#include <stddef.h>")?;
+    if options.standard != CStandard::C89 {
+        writeln!(writer, "#include <stdint.h>")?;
+    }
+    write!(writer, "{}", STATE_TYPEDEF.replace('\t', &options.indent))?;
+    writeln!(writer, "#define {}_TAPE_SIZE {}", name.to_uppercase(), options.tape_size)?;
+    writeln!(writer, "typedef {} {}_cell;", options.cell_type.c_name(options.standard), name)?;
+    if options.standard == CStandard::C11 {
+        writeln!(writer, "_Static_assert({0}_TAPE_SIZE <= SIZE_MAX / sizeof({1}_cell), \
+                          \"the tape is too large\");", name.to_uppercase(), name)?;
+    }
+    writeln!(writer, "int {}(bf_state *st);", name)
+}
+
+mod utils {
+    pub fn c_string(s: &str) -> String {
+        let mut escaped = String::from("\"");
+        for byte in s.bytes() {
+            match byte {
+                b'"' | b'\\' => {
+                    escaped.push('\\');
+                    escaped.push(byte as char);
+                },
+                b' '..=b'~' => escaped.push(byte as char),
+                _ => escaped.push_str(&format!("\\{:03o}", byte)),
+            }
+        }
+        escaped.push('"');
+        escaped
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+pub const BYTES_PER_LINE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Binary,
+    // pairs of hex digits, with optional whitespace, `#` comments and a
+    // `ptr N` line giving the starting pointer
+    Hex,
+}
+
+// Initial content of the tape, cells past the end of the image are zero.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TapeImage {
+    pub cells: Vec<u8>,
+    pub ptr: usize,
+}
+
+impl TapeImage {
+    pub fn load<P: AsRef<Path>>(path: P, format: ImageFormat) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        File::open(path)?.read_to_end(&mut buf)?;
+
+        match format {
+            ImageFormat::Binary => Ok(TapeImage { cells: buf, ptr: 0 }),
+            ImageFormat::Hex => {
+                let text = String::from_utf8(buf)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                TapeImage::parse_hex(&text)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            },
+        }
+    }
+
+    pub fn parse_hex(text: &str) -> Result<Self, String> {
+        let mut image = TapeImage::default();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+
+            if let Some(ptr) = line.strip_prefix("ptr") {
+                image.ptr = ptr.trim().parse().map_err(|_| {
+                    format!("invalid pointer on line {}", line_number + 1)
+                })?;
+                continue;
+            }
+
+            let digits: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+            for pair in digits.chunks(2) {
+                let pair: String = pair.iter().collect();
+                match u8::from_str_radix(&pair, 16) {
+                    Ok(cell) if pair.len() == 2 => image.cells.push(cell),
+                    _ => {
+                        return Err(format!("invalid hex byte `{}` on line {}", pair, line_number + 1));
+                    },
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+// Hex format of `TapeImage`, every cell of the tape is written so that the
+// dumps of all backends can be compared.
+pub fn write_hex_dump<W, I>(mut writer: W, cells: I, ptr: isize) -> io::Result<()>
+    where W: Write, I: ExactSizeIterator<Item=u8> {
+    writeln!(writer, "ptr {}", ptr)?;
+
+    let len = cells.len();
+    for (index, cell) in cells.enumerate() {
+        let separator = if index % BYTES_PER_LINE == BYTES_PER_LINE - 1 || index == len - 1 {
+            '\n'
+        } else {
+            ' '
+        };
+        write!(writer, "{:02x}{}", cell, separator)?;
+    }
+    Ok(())
+}
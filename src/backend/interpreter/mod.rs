@@ -0,0 +1,539 @@
+use std::char;
+use std::fs::File;
+use std::mem;
+use std::slice;
+use std::num::Wrapping;
+use std::io::{self, Read, Write, Bytes, BufWriter};
+use std::path::{Path, PathBuf};
+
+use num_bigint::BigInt;
+use num_traits::{Zero, Signed, ToPrimitive};
+
+use ir::{Atom, Value};
+use backend::{Backend, IoEncoding, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+use backend::image::write_hex_dump;
+use backend::profile::Profiler;
+
+pub mod tape;
+mod tiering;
+pub use self::tape::{Tape, VecTape, SparseTape, MmapTape};
+use self::tiering::Tiering;
+
+const MEM_SIZE: usize = 30_000;
+const REPLACEMENT_CHARACTER: u32 = 0xFFFD;
+// larger dumps are refused, such as the one of a sparse tape whose pointer
+// went left of the first cell and wrapped around the address space
+const MAX_DUMP_SIZE: usize = 1 << 28;
+
+pub trait Cell: Clone {
+    fn zero() -> Self;
+    fn from_value(value: Value) -> Self;
+    fn from_byte(byte: u8) -> Self;
+    fn is_zero(&self) -> bool;
+    fn add(&self, inc: Value) -> Self;
+    // self + base * factor
+    fn mul_add(&self, base: &Self, factor: Value) -> Self;
+    // value modulo 256
+    fn to_byte(&self) -> u8;
+    fn to_code_point(&self) -> Option<u32>;
+    fn to_decimal(&self) -> String;
+    fn from_decimal(digits: &str) -> Option<Self>;
+
+    // the cells as bytes for native code, if they are stored as such
+    fn as_bytes_mut(_cells: &mut [Self]) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+impl Cell for Wrapping<i8> {
+    fn zero() -> Self {
+        Wrapping(0)
+    }
+
+    fn from_value(value: Value) -> Self {
+        Wrapping(value as i8)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Wrapping(byte as i8)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn add(&self, inc: Value) -> Self {
+        *self + Wrapping(inc as i8)
+    }
+
+    fn mul_add(&self, base: &Self, factor: Value) -> Self {
+        *self + *base * Wrapping(factor as i8)
+    }
+
+    fn to_byte(&self) -> u8 {
+        self.0 as u8
+    }
+
+    fn to_code_point(&self) -> Option<u32> {
+        Some(self.to_byte() as u32)
+    }
+
+    fn to_decimal(&self) -> String {
+        self.to_byte().to_string()
+    }
+
+    fn from_decimal(digits: &str) -> Option<Self> {
+        utils::parse_wrapping(digits).map(Self::from_value)
+    }
+
+    // `Wrapping<i8>` is a transparent wrapper around a byte
+    fn as_bytes_mut(cells: &mut [Self]) -> Option<&mut [u8]> {
+        Some(unsafe { slice::from_raw_parts_mut(cells.as_mut_ptr() as *mut u8, cells.len()) })
+    }
+}
+
+impl Cell for BigInt {
+    fn zero() -> Self {
+        Zero::zero()
+    }
+
+    fn from_value(value: Value) -> Self {
+        BigInt::from(value)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        BigInt::from(byte)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+
+    fn add(&self, inc: Value) -> Self {
+        self + BigInt::from(inc)
+    }
+
+    fn mul_add(&self, base: &Self, factor: Value) -> Self {
+        self + base * BigInt::from(factor)
+    }
+
+    fn to_byte(&self) -> u8 {
+        let mut byte = self % BigInt::from(256);
+        if byte.is_negative() {
+            byte += BigInt::from(256);
+        }
+        byte.to_u8().unwrap()
+    }
+
+    fn to_code_point(&self) -> Option<u32> {
+        self.to_u32()
+    }
+
+    fn to_decimal(&self) -> String {
+        self.to_string()
+    }
+
+    fn from_decimal(digits: &str) -> Option<Self> {
+        digits.parse().ok()
+    }
+}
+
+#[derive(Debug)]
+pub enum InterpreterError {
+    IndexOutOfBounds(usize),
+    EmptyInput,
+    IOError(io::Error),
+    LoopLimit,
+    OutOfFuel,
+    // options which can't be used together
+    Unsupported(&'static str),
+}
+
+impl InterpreterError {
+    // distinct exit statuses for scripts, taken from sysexits.h
+    pub fn exit_status(&self) -> i32 {
+        match *self {
+            InterpreterError::EmptyInput => EOF_STATUS,
+            InterpreterError::IndexOutOfBounds(_) => 70,
+            InterpreterError::IOError(_) => 74,
+            InterpreterError::LoopLimit => 75,
+            InterpreterError::OutOfFuel => OUT_OF_FUEL_STATUS,
+            InterpreterError::Unsupported(_) => 64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Interpreter<R: Read, W: Write, T: Tape = VecTape<Wrapping<i8>>> {
+    tape: T,
+    ptr: usize,
+    loop_limit: Option<usize>,
+    fuel: Option<u64>,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    dump: Option<PathBuf>,
+    profile: Option<(PathBuf, Profiler)>,
+    tiering: Option<Tiering>,
+    last_was_cr: bool,
+    depth: usize,
+    reader: Bytes<R>,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Interpreter<R, W> {
+    pub fn new(reader: R, writer: W, loop_limit: Option<usize>) -> Self {
+        Interpreter::with_tape(reader, writer, VecTape::default(), loop_limit)
+    }
+}
+
+impl<R: Read, W: Write, T: Tape> Interpreter<R, W, T> {
+    pub fn with_tape(reader: R, writer: W, tape: T, loop_limit: Option<usize>) -> Self {
+        Interpreter {
+            tape,
+            ptr: 0,
+            loop_limit,
+            fuel: None,
+            io: IoOptions::default(),
+            image: None,
+            dump: None,
+            profile: None,
+            tiering: None,
+            last_was_cr: false,
+            depth: 0,
+            reader: reader.bytes(),
+            writer,
+        }
+    }
+
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    // the image is loaded on top of the tape when initializing
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    // the tape is dumped when finalizing, or when the run stops with an error
+    pub fn with_dump<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.dump = Some(path.into());
+        self
+    }
+
+    // loop counts of the run are saved to `path` when finalizing
+    pub fn with_profile<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.profile = Some((path.into(), Profiler::new()));
+        self
+    }
+
+    // the run stops after `fuel` loop iterations in total, like the
+    // compiled backends given the same fuel
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // loops entered `threshold` times are compiled with LLVM in the
+    // background, for tapes of bytes only, native loops count neither
+    // towards a loop limit nor in a profile so neither can be used with it
+    pub fn with_tiering(mut self, threshold: usize) -> Self {
+        self.tiering = Some(Tiering::new(threshold));
+        self
+    }
+
+    fn load_image(&mut self, image: &TapeImage) -> Result<(), InterpreterError> {
+        for (index, &byte) in image.cells.iter().enumerate() {
+            match self.tape.get_mut(index) {
+                Some(cell) => *cell = T::Cell::from_byte(byte),
+                None => return Err(InterpreterError::IndexOutOfBounds(index)),
+            }
+        }
+        if image.ptr >= self.tape.size() {
+            return Err(InterpreterError::IndexOutOfBounds(image.ptr));
+        }
+        self.ptr = image.ptr;
+        Ok(())
+    }
+
+    // cells are dumped modulo 256
+    fn dump_tape(&self, path: &Path) -> io::Result<()> {
+        if self.tape.used() > MAX_DUMP_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("can't dump the {} cells of the tape",
+                                              self.tape.used())));
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        let tape = &self.tape;
+        let cells = (0..tape.used()).map(|index| tape.get(index).unwrap().to_byte());
+        write_hex_dump(writer, cells, self.ptr as isize)
+    }
+
+    fn set_memory_offset(&mut self, offset: isize, value: T::Cell) -> Result<(), InterpreterError> {
+        let ptr = utils::offset_usize(self.ptr, offset, self.tape.size());
+        if let Some(cell) = self.tape.get_mut(ptr) {
+            *cell = value;
+            Ok(())
+        } else {
+            Err(InterpreterError::IndexOutOfBounds(ptr))
+        }
+    }
+
+    fn get_memory_offset(&self, offset: isize) -> Result<&T::Cell, InterpreterError> {
+        let ptr = utils::offset_usize(self.ptr, offset, self.tape.size());
+        if let Some(cell) = self.tape.get(ptr) {
+            Ok(cell)
+        } else {
+            Err(InterpreterError::IndexOutOfBounds(ptr))
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, InterpreterError> {
+        loop {
+            let byte = match self.reader.next() {
+                Some(Ok(byte)) => byte,
+                Some(Err(err)) => return Err(InterpreterError::IOError(err)),
+                None => return Ok(None),
+            };
+
+            if self.io.translate_newlines {
+                let last_was_cr = mem::replace(&mut self.last_was_cr, byte == b'\r');
+                if byte == b'\r' {
+                    return Ok(Some(b'\n'));
+                } else if byte == b'\n' && last_was_cr {
+                    continue;
+                }
+            }
+            return Ok(Some(byte));
+        }
+    }
+
+    fn read_code_point(&mut self) -> Result<Option<u32>, InterpreterError> {
+        let first = match self.read_byte()? {
+            Some(byte) => byte as u32,
+            None => return Ok(None),
+        };
+
+        let (continuation_len, mut code_point) = match first {
+            0x00..=0x7F => return Ok(Some(first)),
+            0xC0..=0xDF => (1, first & 0x1F),
+            0xE0..=0xEF => (2, first & 0x0F),
+            0xF0..=0xF7 => (3, first & 0x07),
+            _ => return Ok(Some(REPLACEMENT_CHARACTER)),
+        };
+        for _ in 0..continuation_len {
+            match self.read_byte()? {
+                Some(byte) if byte & 0xC0 == 0x80 => {
+                    code_point = (code_point << 6) | (byte & 0x3F) as u32;
+                },
+                _ => return Ok(Some(REPLACEMENT_CHARACTER)),
+            }
+        }
+        Ok(Some(code_point))
+    }
+
+    // skips leading whitespace and consumes the character following the
+    // number, anything that isn't a number reads as 0
+    fn read_decimal(&mut self) -> Result<Option<T::Cell>, InterpreterError> {
+        let mut byte = self.read_byte()?;
+        while let Some(b' ') | Some(b'\t'..=b'\r') = byte {
+            byte = self.read_byte()?;
+        }
+        if byte.is_none() {
+            return Ok(None);
+        }
+
+        let mut digits = String::new();
+        if byte == Some(b'-') {
+            digits.push('-');
+            byte = self.read_byte()?;
+        }
+        while let Some(digit @ b'0'..=b'9') = byte {
+            digits.push(digit as char);
+            byte = self.read_byte()?;
+        }
+        Ok(Some(T::Cell::from_decimal(&digits).unwrap_or_else(T::Cell::zero)))
+    }
+}
+
+impl<R: Read, W: Write, T: Tape> Backend for Interpreter<R, W, T> {
+    // current cell modulo 256 at exit
+    type Payload = u8;
+    type Error = InterpreterError;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        if self.tiering.is_some() && self.loop_limit.is_some() {
+            return Err(InterpreterError::Unsupported("tiering with a loop limit"));
+        }
+        if self.tiering.is_some() && self.profile.is_some() {
+            return Err(InterpreterError::Unsupported("tiering with a profile"));
+        }
+        if let Some(image) = self.image.take() {
+            self.load_image(&image)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(self) -> Result<Self::Payload, Self::Error> {
+        if let Some(ref path) = self.dump {
+            self.dump_tape(path).map_err(InterpreterError::IOError)?;
+        }
+        if let Some((ref path, ref profiler)) = self.profile {
+            if let Some(profile) = profiler.profile() {
+                profile.save(path).map_err(InterpreterError::IOError)?;
+            }
+        }
+        Ok(self.get_memory_offset(0)?.to_byte())
+    }
+
+    fn push_atoms(&mut self, ir: &Vec<Atom>) -> Result<(), Self::Error> {
+        if let Some((_, ref mut profiler)) = self.profile {
+            profiler.start(ir);
+        }
+        if let (Some(tiering), 0) = (self.tiering.as_mut(), self.depth) {
+            tiering.start(ir, self.io);
+        }
+        self.depth += 1;
+        let result = ir.iter().try_for_each(|atom| self.push_atom(atom));
+        self.depth -= 1;
+
+        // the program stopped with an error, the error of the run is
+        // reported rather than the one of the dump
+        if let (Err(_), 0, Some(path)) = (&result, self.depth, self.dump.as_ref()) {
+            let _ = self.dump_tape(path);
+        }
+        result
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        self.ptr = utils::offset_usize(self.ptr, offset, self.tape.size());
+        Ok(())
+    }
+
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        self.set_memory_offset(offset, T::Cell::from_value(value))
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        let new_value = self.get_memory_offset(offset)?.add(inc);
+        self.set_memory_offset(offset, new_value)
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let result = {
+            let cell = self.get_memory_offset(offset)?;
+            match self.io.encoding {
+                IoEncoding::Bytes => self.writer.write(&[cell.to_byte()]).map(|_| ()),
+                IoEncoding::Unicode => {
+                    let c = cell.to_code_point()
+                        .and_then(char::from_u32)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    write!(self.writer, "{}", c)
+                },
+                IoEncoding::Decimal => writeln!(self.writer, "{}", cell.to_decimal()),
+            }
+        };
+        result.map_err(InterpreterError::IOError)
+    }
+
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let value = match self.io.encoding {
+            IoEncoding::Bytes => self.read_byte()?.map(T::Cell::from_byte),
+            IoEncoding::Unicode => {
+                self.read_code_point()?.map(|c| T::Cell::from_value(c as Value))
+            },
+            IoEncoding::Decimal => self.read_decimal()?,
+        };
+
+        if let Some(value) = value {
+            self.set_memory_offset(offset, value)
+        } else {
+            Err(InterpreterError::EmptyInput)
+        }
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        let new_value = {
+            let zero_value = self.get_memory_offset(0)?;
+            self.get_memory_offset(offset)?.mul_add(zero_value, factor)
+        };
+        self.set_memory_offset(offset, new_value)
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        let loop_id = match self.tiering {
+            Some(ref mut tiering) => {
+                let id = tiering.next_id();
+                if let Some(tape) = self.tape.bytes_mut() {
+                    let native = tiering.run(id, sub, tape, &mut self.ptr, self.fuel.as_mut(),
+                                             &mut self.writer)?;
+                    if native {
+                        tiering.leave(id);
+                        return Ok(());
+                    }
+                }
+                Some(id)
+            },
+            None => None,
+        };
+
+        let mut loop_counter = 0;
+        while !self.get_memory_offset(0)?.is_zero() {
+            // checking the loop limiter
+            loop_counter += 1;
+            if let Some(loop_limit) = self.loop_limit {
+                if loop_counter >= loop_limit {
+                    return Err(InterpreterError::LoopLimit);
+                }
+            }
+            if let Some(ref mut fuel) = self.fuel {
+                if *fuel == 0 {
+                    return Err(InterpreterError::OutOfFuel);
+                }
+                *fuel -= 1;
+            }
+
+            // interpreting the loop
+            if let (Some(tiering), Some(id)) = (self.tiering.as_mut(), loop_id) {
+                tiering.enter(id);
+            }
+            self.push_atoms(sub)?;
+        }
+        if let (Some(tiering), Some(id)) = (self.tiering.as_mut(), loop_id) {
+            tiering.leave(id);
+        }
+
+        if let Some((_, ref mut profiler)) = self.profile {
+            profiler.record(sub, loop_counter as u64);
+        }
+        Ok(())
+    }
+}
+
+mod utils {
+    // wraps around a tape of `size` cells
+    pub fn offset_usize(base: usize, offset: isize, size: usize) -> usize {
+        (base as i128 + offset as i128).rem_euclid(size as i128) as usize
+    }
+
+    // parses an optionally negative number, wrapping on overflow
+    pub fn parse_wrapping(digits: &str) -> Option<i64> {
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, digits),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+
+        let mut value = 0u64;
+        for digit in digits.bytes() {
+            value = value.wrapping_mul(10).wrapping_add((digit - b'0') as u64);
+        }
+        if negative {
+            value = value.wrapping_neg();
+        }
+        Some(value as i64)
+    }
+}
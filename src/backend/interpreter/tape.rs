@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io;
+use std::num::Wrapping;
+use std::path::Path;
+
+use memmap::MmapMut;
+
+use super::{Cell, MEM_SIZE};
+
+// Memory of the interpreter, indexes are always lower than `size`.
+pub trait Tape {
+    type Cell: Cell;
+
+    fn size(&self) -> usize;
+    fn get(&self, index: usize) -> Option<&Self::Cell>;
+    fn get_mut(&mut self, index: usize) -> Option<&mut Self::Cell>;
+
+    // number of cells from the start of the tape that are worth dumping
+    fn used(&self) -> usize {
+        self.size()
+    }
+
+    // the whole tape as bytes for native code, if it is stored as such
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VecTape<C: Cell> {
+    cells: Vec<C>,
+}
+
+impl<C: Cell> VecTape<C> {
+    pub fn new(size: usize) -> Self {
+        VecTape {
+            cells: vec![C::zero(); size],
+        }
+    }
+}
+
+impl<C: Cell> Default for VecTape<C> {
+    fn default() -> Self {
+        VecTape::new(MEM_SIZE)
+    }
+}
+
+impl<C: Cell> Tape for VecTape<C> {
+    type Cell = C;
+
+    fn size(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn get(&self, index: usize) -> Option<&C> {
+        self.cells.get(index)
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut C> {
+        self.cells.get_mut(index)
+    }
+
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        C::as_bytes_mut(&mut self.cells)
+    }
+}
+
+// Only stores the cells that have been written to, so the tape can be as
+// large as the address space.
+#[derive(Debug, Clone)]
+pub struct SparseTape<C: Cell> {
+    cells: HashMap<usize, C>,
+    size: usize,
+    zero: C,
+}
+
+impl<C: Cell> SparseTape<C> {
+    pub fn new(size: usize) -> Self {
+        SparseTape {
+            cells: HashMap::new(),
+            size,
+            zero: C::zero(),
+        }
+    }
+}
+
+impl<C: Cell> Default for SparseTape<C> {
+    fn default() -> Self {
+        SparseTape::new(usize::MAX)
+    }
+}
+
+impl<C: Cell> Tape for SparseTape<C> {
+    type Cell = C;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn get(&self, index: usize) -> Option<&C> {
+        if index < self.size {
+            Some(self.cells.get(&index).unwrap_or(&self.zero))
+        } else {
+            None
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut C> {
+        if index < self.size {
+            Some(self.cells.entry(index).or_insert_with(C::zero))
+        } else {
+            None
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.cells.keys().max().map_or(0, |&index| index + 1)
+    }
+}
+
+// Byte cells stored in a memory-mapped file, so that the tape is kept
+// between runs. A new or empty file is grown to the default tape size.
+#[derive(Debug)]
+pub struct MmapTape {
+    map: MmapMut,
+}
+
+impl MmapTape {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.set_len(MEM_SIZE as u64)?;
+        }
+
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(MmapTape { map })
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl Tape for MmapTape {
+    type Cell = Wrapping<i8>;
+
+    fn size(&self) -> usize {
+        self.map.len()
+    }
+
+    // `Wrapping<i8>` is a transparent wrapper around a byte
+    fn get(&self, index: usize) -> Option<&Wrapping<i8>> {
+        self.map.get(index).map(|byte| unsafe {
+            &*(byte as *const u8 as *const Wrapping<i8>)
+        })
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut Wrapping<i8>> {
+        self.map.get_mut(index).map(|byte| unsafe {
+            &mut *(byte as *mut u8 as *mut Wrapping<i8>)
+        })
+    }
+
+    fn bytes_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.map[..])
+    }
+}
+
+impl Drop for MmapTape {
+    fn drop(&mut self) {
+        let _ = self.map.flush();
+    }
+}
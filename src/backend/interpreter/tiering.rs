@@ -0,0 +1,186 @@
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use ir::Atom;
+use backend::{self, IoOptions, LLVMBackend};
+use backend::llvm::{LLVMBrainfuckModule, OptLevel, OUT_OF_FUEL_ERROR};
+use backend::profile::hash_ir;
+use super::InterpreterError;
+
+const COMPILER_STACK_SIZE: usize = 64 << 20;
+
+// loops are compiled for the program of the hash
+type Compiled = (u64, usize, Result<LLVMBrainfuckModule, CString>);
+
+#[derive(Debug)]
+enum LoopState {
+    // number of entries so far
+    Counting(usize),
+    Compiling,
+    Compiled(LLVMBrainfuckModule),
+    // the loop reads input or couldn't be compiled
+    Interpreted,
+}
+
+// Loops entered `threshold` times are compiled with LLVM on a background
+// thread, their later entries run the native code on the tape of the
+// interpreter. Loops are identified like in profiles, by the order of their
+// `[` in the program, the interpreter telling when it enters and leaves them.
+// The native loops take their iterations from the fuel of the interpreter.
+#[derive(Debug)]
+pub struct Tiering {
+    threshold: usize,
+    io: IoOptions,
+    ir_hash: Option<u64>,
+    loops: Vec<LoopState>,
+    // number of loops nested in each loop
+    nested: Vec<usize>,
+    // id of the next loop to be entered
+    next_id: usize,
+    sender: Sender<Compiled>,
+    receiver: Receiver<Compiled>,
+}
+
+impl Tiering {
+    pub fn new(threshold: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Tiering {
+            threshold,
+            io: IoOptions::default(),
+            ir_hash: None,
+            loops: Vec::new(),
+            nested: Vec::new(),
+            next_id: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    // numbers the loops of the program, the compiled loops are kept as long
+    // as the same program is run
+    pub fn start(&mut self, ir: &[Atom], io: IoOptions) {
+        self.io = io;
+        let ir_hash = hash_ir(ir);
+        if self.ir_hash != Some(ir_hash) {
+            self.ir_hash = Some(ir_hash);
+            self.nested.clear();
+            count_nested(ir, &mut self.nested);
+            self.loops = self.nested.iter().map(|_| LoopState::Counting(0)).collect();
+        }
+        self.next_id = 0;
+    }
+
+    pub fn next_id(&self) -> usize {
+        self.next_id
+    }
+
+    // the loop `id` runs its body, whose first loop is the next one
+    pub fn enter(&mut self, id: usize) {
+        self.next_id = id + 1;
+    }
+
+    // the loop `id` is done, the next loop comes after its nested loops
+    pub fn leave(&mut self, id: usize) {
+        self.next_id = id + 1 + self.nested[id];
+    }
+
+    // runs the loop `id` natively if it's compiled, returns false if it has
+    // to be interpreted
+    pub fn run(&mut self, id: usize, sub: &[Atom], tape: &mut [u8], ptr: &mut usize,
+               fuel: Option<&mut u64>, writer: &mut dyn Write)
+        -> Result<bool, InterpreterError> {
+        while let Ok((ir_hash, compiled_id, result)) = self.receiver.try_recv() {
+            if Some(ir_hash) != self.ir_hash {
+                continue;
+            }
+            self.loops[compiled_id] = match result {
+                Ok(module) => LoopState::Compiled(module),
+                Err(_) => LoopState::Interpreted,
+            };
+        }
+
+        let state = match self.loops.get_mut(id) {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        match *state {
+            LoopState::Compiled(ref mut module) => {
+                return match module.run_on_tape(io::empty(), writer, tape, ptr, fuel) {
+                    Ok(_) => Ok(true),
+                    Err(ref err) if err.as_bytes() == OUT_OF_FUEL_ERROR.as_bytes() => {
+                        Err(InterpreterError::OutOfFuel)
+                    },
+                    Err(err) => Err(InterpreterError::IOError(
+                        io::Error::other(err.to_string_lossy().into_owned())
+                    )),
+                };
+            },
+            LoopState::Counting(ref mut entries) => {
+                *entries += 1;
+                if *entries < self.threshold {
+                    return Ok(false);
+                }
+            },
+            LoopState::Compiling | LoopState::Interpreted => return Ok(false),
+        }
+
+        // the end of the input is reported by the interpreter only
+        if reads_input(sub) {
+            *state = LoopState::Interpreted;
+            return Ok(false);
+        }
+        *state = LoopState::Compiling;
+        let sender = self.sender.clone();
+        let ir_hash = self.ir_hash.unwrap_or_default();
+        let sub = sub.to_vec();
+        let io = self.io;
+        let tape_size = tape.len();
+        let fueled = fuel.is_some();
+        // the backend and LLVM recurse into the nested loops
+        let spawned = thread::Builder::new().stack_size(COMPILER_STACK_SIZE).spawn(move || {
+            let _ = sender.send((ir_hash, id, compile_loop(sub, io, tape_size, fueled)));
+        });
+        if spawned.is_err() {
+            *state = LoopState::Interpreted;
+        }
+        Ok(false)
+    }
+}
+
+fn compile_loop(sub: Vec<Atom>, io: IoOptions, tape_size: usize, fueled: bool)
+    -> Result<LLVMBrainfuckModule, CString> {
+    let mut backend = LLVMBackend::new()
+        .with_io(io)
+        .with_tape_size(tape_size)
+        .with_wrap()
+        .with_external_tape();
+    if fueled {
+        backend = backend.with_external_fuel();
+    }
+    let mut module = backend::use_backend(backend, &vec![Atom::Loop(sub)])?;
+    module.optimize_level(OptLevel::O2);
+    module.compile()?;
+    Ok(module)
+}
+
+// in the order of the loop IDs
+fn count_nested(ir: &[Atom], nested: &mut Vec<usize>) {
+    for atom in ir {
+        if let Atom::Loop(ref sub) = *atom {
+            let id = nested.len();
+            nested.push(0);
+            count_nested(sub, nested);
+            nested[id] = nested.len() - id - 1;
+        }
+    }
+}
+
+fn reads_input(ir: &[Atom]) -> bool {
+    ir.iter().any(|atom| match *atom {
+        Atom::Read(_) => true,
+        Atom::Loop(ref sub) => reads_input(sub),
+        _ => false,
+    })
+}
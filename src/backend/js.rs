@@ -0,0 +1,378 @@
+use std::io::{self, Write};
+
+use ir::{Atom, Value};
+use backend::{Backend, CCellType, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+const MEM_SIZE: usize = 30000;
+// messages of the `RangeError` thrown when the fuel runs out and when reading
+// past the end of the input
+const OUT_OF_FUEL: &str = "out of fuel";
+const EOF: &str = "end of input";
+
+// An ES module exporting `run(input)`, taking and returning a `Uint8Array`,
+// `runWithCell(input)` returning `{ output, cell }` and `runWith(read, write)`
+// streaming the I/O through callbacks, `read` returning -1 at the end of the
+// input, which returns the cell. The tape is a typed array of the cell type, so
+// cells wrap like the C ones, and the pointer wraps around its ends like in
+// the interpreter. Reading past the end of the input or running out of fuel
+// throws a `RangeError`, whose `output` is the output written so far when
+// running on arrays.
+#[derive(Debug, Clone)]
+pub struct JsBackend<W: Write> {
+    writer: W,
+    current_tab: usize,
+    tape_size: usize,
+    cell_type: CCellType,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    fuel: Option<u64>,
+}
+
+impl<W: Write> JsBackend<W> {
+    pub fn new(writer: W) -> Self {
+        JsBackend {
+            writer,
+            current_tab: 1,
+            tape_size: MEM_SIZE,
+            cell_type: CCellType::I8,
+            io: IoOptions::default(),
+            image: None,
+            fuel: None,
+        }
+    }
+
+    // only byte I/O is supported for now
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    pub fn with_cell_type(mut self, cell_type: CCellType) -> Self {
+        self.cell_type = cell_type;
+        self
+    }
+
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    fn array_type(&self) -> &'static str {
+        match self.cell_type {
+            CCellType::I8 => "Int8Array",
+            CCellType::U8 => "Uint8Array",
+            CCellType::I16 => "Int16Array",
+            CCellType::U16 => "Uint16Array",
+            CCellType::I32 => "Int32Array",
+            CCellType::U32 => "Uint32Array",
+        }
+    }
+
+    // like in the Rust backend, the offsets are taken modulo the tape size
+    fn index(&self, offset: isize) -> String {
+        match offset.rem_euclid(self.tape_size as isize) {
+            0 => String::from("ptr"),
+            offset => format!("(ptr + {}) % {}", offset, self.tape_size),
+        }
+    }
+
+    fn write_tab(&mut self) -> io::Result<()> {
+        write!(&mut self.writer, "{}", "    ".repeat(self.current_tab))
+    }
+}
+
+impl<W: Write> Backend for JsBackend<W> {
+    type Payload = ();
+    type Error = io::Error;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        let image = self.image.take().unwrap_or_default();
+        if self.io != IoOptions::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the JavaScript backend only supports byte I/O"));
+        }
+        if self.tape_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        if image.cells.len() > self.tape_size || image.ptr >= self.tape_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "tape image doesn't fit in the tape"));
+        }
+
+        writeln!(&mut self.writer, "// generated by bfc")?;
+        writeln!(&mut self.writer, "export function run(input) {{")?;
+        writeln!(&mut self.writer, "    return runWithCell(input).output;")?;
+        writeln!(&mut self.writer, "}}")?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "export function runWithCell(input) {{")?;
+        writeln!(&mut self.writer, "    let output = new Uint8Array(256);")?;
+        writeln!(&mut self.writer, "    let length = 0;")?;
+        writeln!(&mut self.writer, "    let position = 0;")?;
+        writeln!(&mut self.writer, "    function read() {{")?;
+        writeln!(&mut self.writer, "        return position < input.length ? input[position++] : -1;")?;
+        writeln!(&mut self.writer, "    }}")?;
+        writeln!(&mut self.writer, "    function write(byte) {{")?;
+        writeln!(&mut self.writer, "        if (length === output.length) {{")?;
+        writeln!(&mut self.writer, "            const grown = new Uint8Array(length * 2);")?;
+        writeln!(&mut self.writer, "            grown.set(output);")?;
+        writeln!(&mut self.writer, "            output = grown;")?;
+        writeln!(&mut self.writer, "        }}")?;
+        writeln!(&mut self.writer, "        output[length++] = byte;")?;
+        writeln!(&mut self.writer, "    }}")?;
+        writeln!(&mut self.writer, "    try {{")?;
+        writeln!(&mut self.writer, "        const cell = runWith(read, write);")?;
+        writeln!(&mut self.writer, "        return {{ output: output.subarray(0, length), cell }};")?;
+        writeln!(&mut self.writer, "    }} catch (error) {{")?;
+        writeln!(&mut self.writer, "        if (error instanceof RangeError) {{")?;
+        writeln!(&mut self.writer, "            error.output = output.subarray(0, length);")?;
+        writeln!(&mut self.writer, "        }}")?;
+        writeln!(&mut self.writer, "        throw error;")?;
+        writeln!(&mut self.writer, "    }}")?;
+        writeln!(&mut self.writer, "}}")?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "export function runWith(read, write) {{")?;
+        writeln!(&mut self.writer, "    function readByte() {{")?;
+        writeln!(&mut self.writer, "        const byte = read();")?;
+        writeln!(&mut self.writer, "        if (byte < 0) {{")?;
+        writeln!(&mut self.writer, "            throw new RangeError({:?});", EOF)?;
+        writeln!(&mut self.writer, "        }}")?;
+        writeln!(&mut self.writer, "        return byte;")?;
+        writeln!(&mut self.writer, "    }}")?;
+        writeln!(&mut self.writer)?;
+        let array_type = self.array_type();
+        writeln!(&mut self.writer, "    const tape = new {}({});", array_type, self.tape_size)?;
+        if !image.cells.is_empty() {
+            writeln!(&mut self.writer, "    tape.set([")?;
+            for line in image.cells.chunks(16) {
+                let cells: Vec<String> = line.iter().map(|&cell| (cell as i8).to_string()).collect();
+                writeln!(&mut self.writer, "        {},", cells.join(", "))?;
+            }
+            writeln!(&mut self.writer, "    ]);")?;
+        }
+        writeln!(&mut self.writer, "    let ptr = {};", image.ptr)?;
+        if let Some(fuel) = self.fuel {
+            writeln!(&mut self.writer, "    let fuel = {};", fuel)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<(), Self::Error> {
+        writeln!(&mut self.writer, "    return tape[ptr];")?;
+        writeln!(&mut self.writer, "}}")
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "ptr = {};", index)
+    }
+
+    // the typed array truncates the values to the cell type
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] = {};", index, value as i32)
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] += {};", index, inc as i32)
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "write(tape[{}] & 255);", index)
+    }
+
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] = readByte();", index)
+    }
+
+    // `Math.imul` keeps the low bits of products of 32 bits cells exact
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] += Math.imul(tape[ptr], {});", index, factor as i32)
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        self.write_tab()?;
+        writeln!(&mut self.writer, "while (tape[ptr] !== 0) {{")?;
+        self.current_tab += 1;
+        if self.fuel.is_some() {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if (fuel === 0) {{")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "    throw new RangeError({:?});", OUT_OF_FUEL)?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "}}")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "fuel--;")?;
+        }
+        self.push_atoms(sub)?;
+        self.current_tab -= 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "}}")
+    }
+}
+
+// A Node script running the module imported from `module_path` on the
+// standard input and output, reading a byte at a time and flushing the output
+// before reading. Like the other backends, it exits with `OUT_OF_FUEL_STATUS`
+// once the fuel runs out, with `EOF_STATUS` at the end of the input and with
+// the current cell when `exit_cell` is set.
+pub fn write_node_cli<W: Write>(mut writer: W, module_path: &str, exit_cell: bool)
+                                -> io::Result<()> {
+    writeln!(writer, "#!/usr/bin/env node")?;
+    writeln!(writer, "// generated by bfc")?;
+    writeln!(writer, "import {{ readSync, writeSync }} from \"node:fs\";")?;
+    writeln!(writer, "import {{ runWith }} from {:?};", module_path)?;
+    writeln!(writer)?;
+    writeln!(writer, "const output = new Uint8Array(4096);")?;
+    writeln!(writer, "let length = 0;")?;
+    writeln!(writer, "function flush() {{")?;
+    writeln!(writer, "    let written = 0;")?;
+    writeln!(writer, "    while (written < length) {{")?;
+    writeln!(writer, "        written += writeSync(1, output, written, length - written);")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "    length = 0;")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer, "function write(byte) {{")?;
+    writeln!(writer, "    if (length === output.length) {{")?;
+    writeln!(writer, "        flush();")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "    output[length++] = byte;")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "const input = new Uint8Array(1);")?;
+    writeln!(writer, "function read() {{")?;
+    writeln!(writer, "    flush();")?;
+    writeln!(writer, "    try {{")?;
+    writeln!(writer, "        return readSync(0, input, 0, 1) === 1 ? input[0] : -1;")?;
+    writeln!(writer, "    }} catch (error) {{")?;
+    writeln!(writer, "        // closed pipes on Windows")?;
+    writeln!(writer, "        if (error.code === \"EOF\") {{")?;
+    writeln!(writer, "            return -1;")?;
+    writeln!(writer, "        }}")?;
+    writeln!(writer, "        throw error;")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "let cell;")?;
+    writeln!(writer, "try {{")?;
+    writeln!(writer, "    cell = runWith(read, write);")?;
+    writeln!(writer, "}} catch (error) {{")?;
+    writeln!(writer, "    flush();")?;
+    writeln!(writer, "    const statuses = {{ {:?}: {}, {:?}: {} }};",
+             OUT_OF_FUEL, OUT_OF_FUEL_STATUS, EOF, EOF_STATUS)?;
+    writeln!(writer, "    if (!(error instanceof RangeError) || !(error.message in statuses)) {{")?;
+    writeln!(writer, "        throw error;")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "    process.exit(statuses[error.message]);")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer, "flush();")?;
+    if exit_cell {
+        writeln!(writer, "process.exit(cell & 255);")
+    } else {
+        writeln!(writer, "process.exit(0);")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Output;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{env, fs, process};
+
+    use ir::{self, Atom};
+    use opt;
+    use backend::{self, CCellType, JsBackend};
+
+    static MODULES: AtomicUsize = AtomicUsize::new(0);
+
+    // runs the module with its Node CLI on `input`, or returns `None` when
+    // Node isn't installed
+    fn run(ir: &Vec<Atom>, cell_type: CCellType, fuel: Option<u64>, input: &[u8])
+        -> Option<Output> {
+        let id = MODULES.fetch_add(1, Ordering::SeqCst);
+        let module_path = env::temp_dir()
+            .join(format!("bfc-js-test-{}-{}.mjs", process::id(), id));
+        let cli_path = module_path.with_extension("cli.mjs");
+        let mut backend = JsBackend::new(fs::File::create(&module_path).unwrap())
+            .with_cell_type(cell_type);
+        if let Some(fuel) = fuel {
+            backend = backend.with_fuel(fuel);
+        }
+        backend::use_backend(backend, ir).unwrap();
+        let module_name = module_path.file_name().unwrap().to_str().unwrap();
+        let cli = fs::File::create(&cli_path).unwrap();
+        super::write_node_cli(cli, &format!("./{}", module_name), false).unwrap();
+
+        let output = backend::run_tool("node", &[cli_path.as_os_str()], input);
+        let _ = fs::remove_file(&module_path);
+        let _ = fs::remove_file(&cli_path);
+        output
+    }
+
+    #[test]
+    fn generated_code() {
+        let ir = opt::run_opts(ir::build_ir(b"<,[->++<]>.").unwrap());
+        let mut code = Vec::new();
+        let backend = JsBackend::new(&mut code)
+            .with_tape_size(100)
+            .with_cell_type(CCellType::U16)
+            .with_fuel(10);
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("export function run(input) {"));
+        assert!(code.contains("    const tape = new Uint16Array(100);"));
+        assert!(code.contains("    ptr = (ptr + 99) % 100;"));
+        assert!(code.contains("    tape[(ptr + 1) % 100] += Math.imul(tape[ptr], 2);"));
+        assert!(!code.contains("fuel--"));
+
+        let mut cli = Vec::new();
+        super::write_node_cli(&mut cli, "./prog.mjs", true).unwrap();
+        let cli = String::from_utf8(cli).unwrap();
+        assert!(cli.contains("import { runWith } from \"./prog.mjs\";"));
+        assert!(cli.contains("    const statuses = { \"out of fuel\": 152, \"end of input\": 66 };"));
+        assert!(cli.ends_with("process.exit(cell & 255);\n"));
+    }
+
+    #[test]
+    fn statuses() {
+        backend::check_programs(|ir, fuel, input| run(ir, CCellType::I8, fuel, input));
+    }
+
+    #[test]
+    fn cell_types() {
+        // 256 is only 0 in bytes, the printed cells are truncated to bytes
+        let overflow = ir::build_ir(&[&[b'+'; 256][..], b"[>+<[-]]>."].concat()).unwrap();
+        let multiply = opt::run_opts(ir::build_ir(b"-[->+++<]>.").unwrap());
+        for &(cell_type, overflowed) in &[
+            (CCellType::I8, 0), (CCellType::U8, 0), (CCellType::I16, 1),
+            (CCellType::U16, 1), (CCellType::I32, 1), (CCellType::U32, 1),
+        ] {
+            let output = match run(&overflow, cell_type, None, b"") {
+                Some(output) => output,
+                None => return,
+            };
+            assert_eq!(output.stdout, [overflowed], "{:?}", cell_type);
+            let output = run(&multiply, cell_type, None, b"").unwrap();
+            assert_eq!(output.stdout, [253], "{:?}", cell_type);
+        }
+    }
+}
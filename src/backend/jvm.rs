@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+
+use ir::{Atom, Value};
+use backend::{Backend, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+const MEM_SIZE: usize = 30000;
+// Java 5 classes are checked by type inference, without stack map frames
+const CLASS_VERSION: u16 = 49;
+const MAX_CODE_LENGTH: usize = 65535;
+// loops at least this long get their own method, HotSpot doesn't compile
+// methods longer than 8000 bytes
+const OUTLINE_LENGTH: usize = 1024;
+// cells of the image per string constant, which take at most 65535 bytes
+const IMAGE_CHUNK: usize = 16384;
+
+// descriptor of `run` and of the loops, taking the tape and the pointer and
+// returning the pointer
+const CODE_DESCRIPTOR: &str = "([BI)I";
+// locals of `main`
+const MAIN_TAPE: u8 = 1;
+const MAIN_PTR: u8 = 2;
+
+const ICONST_0: u8 = 0x03;
+const LCONST_0: u8 = 0x09;
+const LCONST_1: u8 = 0x0A;
+const BIPUSH: u8 = 0x10;
+const SIPUSH: u8 = 0x11;
+const LDC_W: u8 = 0x13;
+const LDC2_W: u8 = 0x14;
+const ILOAD: u8 = 0x15;
+const ALOAD: u8 = 0x19;
+// `tape` and `ptr` in `run` and the loops
+const ALOAD_0: u8 = 0x2A;
+const ILOAD_1: u8 = 0x1B;
+const ISTORE_1: u8 = 0x3C;
+const BALOAD: u8 = 0x33;
+const ISTORE: u8 = 0x36;
+const ASTORE: u8 = 0x3A;
+const BASTORE: u8 = 0x54;
+const DUP: u8 = 0x59;
+const DUP2: u8 = 0x5C;
+const IADD: u8 = 0x60;
+const LSUB: u8 = 0x65;
+const IMUL: u8 = 0x68;
+const IREM: u8 = 0x70;
+const IAND: u8 = 0x7E;
+const I2B: u8 = 0x91;
+const LCMP: u8 = 0x94;
+const IFNE: u8 = 0x9A;
+const IFGE: u8 = 0x9C;
+const IRETURN: u8 = 0xAC;
+const RETURN: u8 = 0xB1;
+const GETSTATIC: u8 = 0xB2;
+const PUTSTATIC: u8 = 0xB3;
+const INVOKEVIRTUAL: u8 = 0xB6;
+const INVOKESTATIC: u8 = 0xB8;
+const NEWARRAY: u8 = 0xBC;
+const GOTO_W: u8 = 0xC8;
+
+const T_BYTE: u8 = 8;
+
+const ACC_PUBLIC: u16 = 0x0001;
+const ACC_PRIVATE: u16 = 0x0002;
+const ACC_STATIC: u16 = 0x0008;
+const ACC_SUPER: u16 = 0x0020;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Constant {
+    Utf8(String),
+    Integer(i32),
+    Long(i64),
+    Class(String),
+    String(String),
+    NameAndType(String, String),
+    // class, name and descriptor
+    Fieldref(String, String, String),
+    Methodref(String, String, String),
+}
+
+#[derive(Debug, Clone)]
+struct ConstantPool {
+    bytes: Vec<u8>,
+    // index of the next entry, longs take two
+    next: u16,
+    indices: HashMap<Constant, u16>,
+}
+
+impl ConstantPool {
+    fn new() -> Self {
+        ConstantPool {
+            bytes: Vec::new(),
+            next: 1,
+            indices: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, constant: Constant) -> u16 {
+        if let Some(&index) = self.indices.get(&constant) {
+            return index;
+        }
+
+        let mut entry = Vec::new();
+        match constant {
+            Constant::Utf8(ref text) => {
+                let text = modified_utf8(text);
+                entry.push(1);
+                write_u16(&mut entry, text.len() as u16);
+                entry.extend_from_slice(&text);
+            },
+            Constant::Integer(value) => {
+                entry.push(3);
+                entry.extend_from_slice(&value.to_be_bytes());
+            },
+            Constant::Long(value) => {
+                entry.push(5);
+                entry.extend_from_slice(&value.to_be_bytes());
+            },
+            Constant::Class(ref name) => {
+                entry.push(7);
+                write_u16(&mut entry, self.add(Constant::Utf8(name.clone())));
+            },
+            Constant::String(ref text) => {
+                entry.push(8);
+                write_u16(&mut entry, self.add(Constant::Utf8(text.clone())));
+            },
+            Constant::NameAndType(ref name, ref descriptor) => {
+                entry.push(12);
+                write_u16(&mut entry, self.add(Constant::Utf8(name.clone())));
+                write_u16(&mut entry, self.add(Constant::Utf8(descriptor.clone())));
+            },
+            Constant::Fieldref(ref class, ref name, ref descriptor) |
+            Constant::Methodref(ref class, ref name, ref descriptor) => {
+                entry.push(if let Constant::Fieldref(..) = constant { 9 } else { 10 });
+                write_u16(&mut entry, self.add(Constant::Class(class.clone())));
+                write_u16(&mut entry, self.add(Constant::NameAndType(name.clone(),
+                                                                      descriptor.clone())));
+            },
+        }
+
+        let index = self.next;
+        self.next += if let Constant::Long(_) = constant { 2 } else { 1 };
+        self.bytes.extend_from_slice(&entry);
+        self.indices.insert(constant, index);
+        index
+    }
+
+    fn utf8(&mut self, text: &str) -> u16 {
+        self.add(Constant::Utf8(text.to_owned()))
+    }
+
+    fn field(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        self.add(Constant::Fieldref(class.to_owned(), name.to_owned(), descriptor.to_owned()))
+    }
+
+    fn method(&mut self, class: &str, name: &str, descriptor: &str) -> u16 {
+        self.add(Constant::Methodref(class.to_owned(), name.to_owned(), descriptor.to_owned()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Method {
+    access: u16,
+    name: String,
+    descriptor: &'static str,
+    max_stack: u16,
+    max_locals: u16,
+    code: Vec<u8>,
+}
+
+// A class file whose `public static void main(String[])` runs the program on
+// a `byte[]` tape with `System.in` and `System.out`. Like the Rust backend,
+// the pointer wraps around the ends of the tape, reading at EOF exits with
+// `EOF_STATUS`. The payload is the content of the class file.
+//
+// The program is `run(byte[] tape, int ptr)` returning the pointer, its long
+// loops are methods with the same signature so that they can be moved out of
+// their method as they are.
+#[derive(Debug, Clone)]
+pub struct JvmBackend {
+    class_name: String,
+    tape_size: usize,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    exit_cell: bool,
+    fuel: Option<u64>,
+    pool: ConstantPool,
+    // code of `run`
+    code: Vec<u8>,
+    loops: Vec<Method>,
+}
+
+impl JvmBackend {
+    // the name of a class in the default package, also the name of its file
+    pub fn new<S: Into<String>>(class_name: S) -> Self {
+        JvmBackend {
+            class_name: class_name.into(),
+            tape_size: MEM_SIZE,
+            io: IoOptions::default(),
+            image: None,
+            exit_cell: false,
+            fuel: None,
+            pool: ConstantPool::new(),
+            code: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    // only byte I/O is supported for now
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    // `main` exits with the current cell
+    pub fn with_exit_cell(mut self) -> Self {
+        self.exit_cell = true;
+        self
+    }
+
+    // `main` exits with `OUT_OF_FUEL_STATUS` instead of running more than
+    // `fuel` loop iterations
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    fn fuel_field(&mut self) -> u16 {
+        let class_name = self.class_name.clone();
+        self.pool.field(&class_name, "fuel", "J")
+    }
+
+    fn push_int(&mut self, value: i32) {
+        match value {
+            -1..=5 => self.code.push((ICONST_0 as i32 + value) as u8),
+            -128..=127 => self.code.extend_from_slice(&[BIPUSH, value as u8]),
+            -32768..=32767 => {
+                self.code.push(SIPUSH);
+                write_u16(&mut self.code, value as u16);
+            },
+            _ => {
+                let index = self.pool.add(Constant::Integer(value));
+                self.code.push(LDC_W);
+                write_u16(&mut self.code, index);
+            },
+        }
+    }
+
+    fn push_ref(&mut self, op: u8, index: u16) {
+        self.code.push(op);
+        write_u16(&mut self.code, index);
+    }
+
+    // pushes the index of a cell, the offsets are taken modulo the tape size
+    // like in the Rust backend
+    fn push_index(&mut self, offset: isize) {
+        self.code.push(ILOAD_1);
+        let offset = offset.rem_euclid(self.tape_size as isize);
+        if offset != 0 {
+            self.push_int(offset as i32);
+            self.code.push(IADD);
+            self.push_int(self.tape_size as i32);
+            self.code.push(IREM);
+        }
+    }
+
+    // pushes the tape and the index of a cell
+    fn push_cell(&mut self, offset: isize) {
+        self.code.push(ALOAD_0);
+        self.push_index(offset);
+    }
+
+    fn push_flush(&mut self) {
+        let out = self.pool.field("java/lang/System", "out", "Ljava/io/PrintStream;");
+        let flush = self.pool.method("java/io/PrintStream", "flush", "()V");
+        self.push_ref(GETSTATIC, out);
+        self.push_ref(INVOKEVIRTUAL, flush);
+    }
+
+    // `System.exit` with the int on the stack, the verifier doesn't know that
+    // it never returns
+    fn push_exit(&mut self) {
+        let exit = self.pool.method("java/lang/System", "exit", "(I)V");
+        self.push_ref(INVOKESTATIC, exit);
+    }
+
+    // a `goto_w` to patch once its target is known
+    fn push_goto(&mut self) -> usize {
+        let position = self.code.len();
+        self.code.extend_from_slice(&[GOTO_W, 0, 0, 0, 0]);
+        position
+    }
+
+    fn patch_goto(&mut self, position: usize, target: usize) {
+        let offset = target as i32 - position as i32;
+        self.code[position + 1..position + 5].copy_from_slice(&offset.to_be_bytes());
+    }
+
+    // creates the tape, copies the image from strings, calls `run` and exits
+    fn main_method(&mut self, image: &TapeImage) -> Method {
+        let code = mem::take(&mut self.code);
+        let class_name = self.class_name.clone();
+
+        self.push_int(self.tape_size as i32);
+        self.code.extend_from_slice(&[NEWARRAY, T_BYTE, ASTORE, MAIN_TAPE]);
+        if !image.cells.is_empty() {
+            let charset = self.pool.field("java/nio/charset/StandardCharsets", "ISO_8859_1",
+                                          "Ljava/nio/charset/Charset;");
+            let get_bytes = self.pool.method("java/lang/String", "getBytes",
+                                             "(Ljava/nio/charset/Charset;)[B");
+            let copy = self.pool.method("java/lang/System", "arraycopy",
+                                        "(Ljava/lang/Object;ILjava/lang/Object;II)V");
+            for (index, chunk) in image.cells.chunks(IMAGE_CHUNK).enumerate() {
+                let text = chunk.iter().map(|&cell| cell as char).collect();
+                let text = self.pool.add(Constant::String(text));
+                self.push_ref(LDC_W, text);
+                self.push_ref(GETSTATIC, charset);
+                self.push_ref(INVOKEVIRTUAL, get_bytes);
+                self.code.push(ICONST_0);
+                self.code.extend_from_slice(&[ALOAD, MAIN_TAPE]);
+                self.push_int((index * IMAGE_CHUNK) as i32);
+                self.push_int(chunk.len() as i32);
+                self.push_ref(INVOKESTATIC, copy);
+            }
+        }
+        if let Some(fuel) = self.fuel {
+            let fuel = self.pool.add(Constant::Long(fuel.min(i64::MAX as u64) as i64));
+            let field = self.fuel_field();
+            self.push_ref(LDC2_W, fuel);
+            self.push_ref(PUTSTATIC, field);
+        }
+
+        let run = self.pool.method(&class_name, "run", CODE_DESCRIPTOR);
+        self.code.extend_from_slice(&[ALOAD, MAIN_TAPE]);
+        self.push_int(image.ptr as i32);
+        self.push_ref(INVOKESTATIC, run);
+        self.code.extend_from_slice(&[ISTORE, MAIN_PTR]);
+        self.push_flush();
+        if self.exit_cell {
+            self.code.extend_from_slice(&[ALOAD, MAIN_TAPE, ILOAD, MAIN_PTR, BALOAD]);
+            self.push_int(255);
+            self.code.push(IAND);
+            self.push_exit();
+        }
+        self.code.push(RETURN);
+
+        Method {
+            access: ACC_PUBLIC | ACC_STATIC,
+            name: String::from("main"),
+            descriptor: "([Ljava/lang/String;)V",
+            max_stack: 5,
+            max_locals: 3,
+            code: mem::replace(&mut self.code, code),
+        }
+    }
+
+    fn class_file(&mut self, methods: &[Method]) -> Vec<u8> {
+        let class_name = self.class_name.clone();
+        let this_class = self.pool.add(Constant::Class(class_name));
+        let super_class = self.pool.add(Constant::Class(String::from("java/lang/Object")));
+        let code_name = self.pool.utf8("Code");
+        let fuel_name = self.pool.utf8("fuel");
+        let fuel_descriptor = self.pool.utf8("J");
+        let methods: Vec<(&Method, u16, u16)> = methods.iter()
+            .map(|method| (method, self.pool.utf8(&method.name), self.pool.utf8(method.descriptor)))
+            .collect();
+
+        let mut out = vec![0xCA, 0xFE, 0xBA, 0xBE];
+        write_u16(&mut out, 0);
+        write_u16(&mut out, CLASS_VERSION);
+        write_u16(&mut out, self.pool.next);
+        out.extend_from_slice(&self.pool.bytes);
+        write_u16(&mut out, ACC_PUBLIC | ACC_SUPER);
+        write_u16(&mut out, this_class);
+        write_u16(&mut out, super_class);
+        // no interfaces
+        write_u16(&mut out, 0);
+
+        // `private static long fuel`, shared by the methods
+        if self.fuel.is_some() {
+            write_u16(&mut out, 1);
+            write_u16(&mut out, ACC_PRIVATE | ACC_STATIC);
+            write_u16(&mut out, fuel_name);
+            write_u16(&mut out, fuel_descriptor);
+            write_u16(&mut out, 0);
+        } else {
+            write_u16(&mut out, 0);
+        }
+
+        write_u16(&mut out, methods.len() as u16);
+        for (method, name, descriptor) in methods {
+            write_u16(&mut out, method.access);
+            write_u16(&mut out, name);
+            write_u16(&mut out, descriptor);
+            write_u16(&mut out, 1);
+            write_u16(&mut out, code_name);
+            write_u32(&mut out, 12 + method.code.len() as u32);
+            write_u16(&mut out, method.max_stack);
+            write_u16(&mut out, method.max_locals);
+            write_u32(&mut out, method.code.len() as u32);
+            out.extend_from_slice(&method.code);
+            // no exception handlers nor attributes
+            write_u16(&mut out, 0);
+            write_u16(&mut out, 0);
+        }
+
+        // no class attributes
+        write_u16(&mut out, 0);
+        out
+    }
+}
+
+impl Backend for JvmBackend {
+    type Payload = Vec<u8>;
+    type Error = io::Error;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        if self.io != IoOptions::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the JVM backend only supports byte I/O"));
+        }
+        let valid_name = self.class_name.chars().enumerate().all(|(index, c)| {
+            c == '_' || c == '$' || c.is_ascii_alphabetic() || (index > 0 && c.is_ascii_digit())
+        });
+        if self.class_name.is_empty() || !valid_name {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid class name"));
+        }
+        // the pointer plus an offset must fit in an int
+        if self.tape_size == 0 || self.tape_size > i32::MAX as usize / 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        if let Some(ref image) = self.image {
+            if image.cells.len() > self.tape_size || image.ptr >= self.tape_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "tape image doesn't fit in the tape"));
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<Self::Payload, Self::Error> {
+        self.code.extend_from_slice(&[ILOAD_1, IRETURN]);
+        let image = self.image.take().unwrap_or_default();
+        let main = self.main_method(&image);
+        let run = Method {
+            access: ACC_PRIVATE | ACC_STATIC,
+            name: String::from("run"),
+            descriptor: CODE_DESCRIPTOR,
+            max_stack: 6,
+            max_locals: 2,
+            code: mem::take(&mut self.code),
+        };
+
+        let mut methods = vec![main, run];
+        methods.append(&mut self.loops);
+        if methods.iter().any(|method| method.code.len() > MAX_CODE_LENGTH) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the program doesn't fit in JVM methods"));
+        }
+        Ok(self.class_file(&methods))
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        self.push_index(offset);
+        self.code.push(ISTORE_1);
+        Ok(())
+    }
+
+    // the cells are truncated by `bastore`
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        self.push_cell(offset);
+        self.push_int(value as i8 as i32);
+        self.code.push(BASTORE);
+        Ok(())
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        self.push_cell(offset);
+        self.code.extend_from_slice(&[DUP2, BALOAD]);
+        self.push_int(inc as i8 as i32);
+        self.code.extend_from_slice(&[IADD, BASTORE]);
+        Ok(())
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let out = self.pool.field("java/lang/System", "out", "Ljava/io/PrintStream;");
+        let write = self.pool.method("java/io/PrintStream", "write", "(I)V");
+        self.push_ref(GETSTATIC, out);
+        self.push_cell(offset);
+        self.code.push(BALOAD);
+        self.push_ref(INVOKEVIRTUAL, write);
+        Ok(())
+    }
+
+    // output is flushed before reading
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let input = self.pool.field("java/lang/System", "in", "Ljava/io/InputStream;");
+        let read = self.pool.method("java/io/InputStream", "read", "()I");
+        self.push_flush();
+        self.push_cell(offset);
+        self.push_ref(GETSTATIC, input);
+        self.push_ref(INVOKEVIRTUAL, read);
+
+        // `read` returns -1 at EOF
+        let check = self.code.len();
+        self.code.extend_from_slice(&[DUP, IFGE, 0, 0]);
+        self.push_flush();
+        self.push_int(EOF_STATUS);
+        self.push_exit();
+        let skip = (self.code.len() - check - 1) as u16;
+        self.code[check + 2..check + 4].copy_from_slice(&skip.to_be_bytes());
+
+        self.code.extend_from_slice(&[I2B, BASTORE]);
+        Ok(())
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        self.push_cell(offset);
+        self.code.extend_from_slice(&[DUP2, BALOAD]);
+        self.push_cell(0);
+        self.code.push(BALOAD);
+        self.push_int(factor as i8 as i32);
+        self.code.extend_from_slice(&[IMUL, IADD, BASTORE]);
+        Ok(())
+    }
+
+    // `ifne` skips the `goto_w` leaving the loop, which may be too far for
+    // the 16 bits offsets of the conditional branches
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        let start = self.code.len();
+        self.push_cell(0);
+        self.code.push(BALOAD);
+        self.code.extend_from_slice(&[IFNE, 0, 8]);
+        let exit = self.push_goto();
+
+        if self.fuel.is_some() {
+            let field = self.fuel_field();
+            self.push_ref(GETSTATIC, field);
+            self.code.extend_from_slice(&[LCONST_0, LCMP]);
+            let check = self.code.len();
+            self.code.extend_from_slice(&[IFNE, 0, 0]);
+            self.push_flush();
+            self.push_int(OUT_OF_FUEL_STATUS);
+            self.push_exit();
+            let skip = (self.code.len() - check) as u16;
+            self.code[check + 1..check + 3].copy_from_slice(&skip.to_be_bytes());
+            self.push_ref(GETSTATIC, field);
+            self.code.extend_from_slice(&[LCONST_1, LSUB]);
+            self.push_ref(PUTSTATIC, field);
+        }
+
+        self.push_atoms(sub)?;
+        let back = self.push_goto();
+        self.patch_goto(back, start);
+        let end = self.code.len();
+        self.patch_goto(exit, end);
+
+        // the jumps are relative, the loop is moved as it is
+        if end - start >= OUTLINE_LENGTH {
+            let mut code = self.code.split_off(start);
+            code.extend_from_slice(&[ILOAD_1, IRETURN]);
+            let name = format!("loop{}", self.loops.len());
+            let class_name = self.class_name.clone();
+            let method = self.pool.method(&class_name, &name, CODE_DESCRIPTOR);
+            self.loops.push(Method {
+                access: ACC_PRIVATE | ACC_STATIC,
+                name,
+                descriptor: CODE_DESCRIPTOR,
+                max_stack: 6,
+                max_locals: 2,
+                code,
+            });
+            self.code.extend_from_slice(&[ALOAD_0, ILOAD_1]);
+            self.push_ref(INVOKESTATIC, method);
+            self.code.push(ISTORE_1);
+        }
+        Ok(())
+    }
+}
+
+// the encoding of the constants, which are Latin-1, where U+0000 takes two
+// bytes
+fn modified_utf8(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for code in text.chars().map(|c| c as u32) {
+        if (1..=0x7F).contains(&code) {
+            out.push(code as u8);
+        } else {
+            out.push(0xC0 | (code >> 6) as u8);
+            out.push(0x80 | (code & 0x3F) as u8);
+        }
+    }
+    out
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::process::Output;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{env, fs, process};
+
+    use ir::{self, Atom};
+    use backend::{self, Interpreter, JvmBackend};
+
+    static CLASSES: AtomicUsize = AtomicUsize::new(0);
+
+    fn contains(class: &[u8], name: &[u8]) -> bool {
+        class.windows(name.len()).any(|window| window == name)
+    }
+
+    fn build(ir: &Vec<Atom>, fuel: Option<u64>) -> Vec<u8> {
+        let mut backend = JvmBackend::new("Program");
+        if let Some(fuel) = fuel {
+            backend = backend.with_fuel(fuel);
+        }
+        backend::use_backend(backend, ir).unwrap()
+    }
+
+    // runs the class on `input`, or returns `None` when Java isn't installed
+    fn run(class: &[u8], input: &[u8]) -> Option<Output> {
+        let id = CLASSES.fetch_add(1, Ordering::SeqCst);
+        let class_dir = env::temp_dir().join(format!("bfc-jvm-test-{}-{}", process::id(), id));
+        fs::create_dir_all(&class_dir).unwrap();
+        fs::write(class_dir.join("Program.class"), class).unwrap();
+        let args = ["-cp".as_ref(), class_dir.as_os_str(), "Program".as_ref()];
+        let output = backend::run_tool("java", &args, input);
+        let _ = fs::remove_dir_all(&class_dir);
+        output
+    }
+
+    // the output and exit status of the interpreter
+    fn interpret(ir: &Vec<Atom>, fuel: Option<u64>, input: &[u8]) -> (Vec<u8>, i32) {
+        let mut output = Vec::new();
+        let result = {
+            let mut interpreter = Interpreter::new(Cursor::new(input), &mut output, None);
+            if let Some(fuel) = fuel {
+                interpreter = interpreter.with_fuel(fuel);
+            }
+            backend::use_backend(interpreter, ir)
+        };
+        (output, result.err().map_or(0, |err| err.exit_status()))
+    }
+
+    #[test]
+    fn class_file() {
+        let ir = ir::build_ir(b"+[>,.<-]").unwrap();
+        let class = backend::use_backend(JvmBackend::new("Echo"), &ir).unwrap();
+        assert_eq!(&class[..8], &[0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 49]);
+        assert!(contains(&class, b"Echo"));
+        assert!(!contains(&class, b"loop0"));
+
+        assert!(backend::use_backend(JvmBackend::new("1st"), &ir).is_err());
+    }
+
+    #[test]
+    fn statuses() {
+        backend::check_programs(|ir, fuel, input| run(&build(ir, fuel), input));
+    }
+
+    #[test]
+    fn outlined_loops() {
+        // both loops are longer than `OUTLINE_LENGTH`, the outer one once the
+        // inner one is a call, the pointer moves in and out of them
+        let inner = format!(">+++[-<.>{}]", ">+<".repeat(300));
+        let program = format!("++[>+{}<<{}-]>>>.,[>,{}.]", inner, ">>>-<<<".repeat(200),
+                              ">+<".repeat(300));
+        let ir = ir::build_ir(program.as_bytes()).unwrap();
+        let class = build(&ir, None);
+        assert!(contains(&class, b"loop0") && contains(&class, b"loop1"));
+
+        // reading past the end of the input and the fuel running out exit
+        // from the outlined loops like from `run`
+        for &(fuel, input) in &[(None, &b"ab"[..]), (Some(4), b""), (Some(40), b"ab")] {
+            let output = match run(&build(&ir, fuel), input) {
+                Some(output) => output,
+                None => return,
+            };
+            let (expected, status) = interpret(&ir, fuel, input);
+            assert_eq!(output.stdout, expected, "{}", String::from_utf8_lossy(&output.stderr));
+            assert_eq!(output.status.code(), Some(status));
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::fs;
+use std::os::raw::c_char;
+use std::path::Path;
+
+use llvm;
+use llvm::prelude::*;
+
+use ir::{Atom, AtomSpan, Span};
+
+// The C API of LLVM 5 has no debug info builder, the nodes are parsed from
+// their textual form in a module of the same context instead, and listed in
+// `!bfc.debug` to be taken from it: compile unit, `ptr`, `memory`, the empty
+// expression, then the locations.
+const LISTED_NODES: usize = 4;
+
+// Debug info of the `brainfuck` function, the spans are followed along the
+// atoms pushed to the backend to give them a location.
+#[derive(Debug)]
+pub struct DebugInfo {
+    context: LLVMContextRef,
+    index_variable: LLVMValueRef,
+    memory_variable: LLVMValueRef,
+    expression: LLVMValueRef,
+    // by line and column
+    locations: HashMap<(usize, usize), LLVMValueRef>,
+    spans: Vec<AtomSpan>,
+    // indices of the atoms being pushed, from the top level down
+    path: Vec<usize>,
+}
+
+impl DebugInfo {
+    pub unsafe fn new(module: LLVMModuleRef, source: &Path, spans: Vec<AtomSpan>)
+        -> Result<DebugInfo, CString> {
+        let source = fs::canonicalize(source).unwrap_or_else(|_| source.to_owned());
+        let name = source.file_name().map_or_else(String::new, |name| {
+            name.to_string_lossy().into_owned()
+        });
+        let directory = source.parent().map_or_else(String::new, |dir| {
+            dir.to_string_lossy().into_owned()
+        });
+        let data_layout = llvm::target::LLVMGetModuleDataLayout(module);
+        let pointer_bits = llvm::target::LLVMPointerSize(data_layout) * 8;
+
+        let mut positions = vec![(1, 1)];
+        add_positions(&spans, &mut positions);
+        positions.sort();
+        positions.dedup();
+
+        let context = llvm::core::LLVMGetModuleContext(module);
+        let ir = debug_info_ir(&name, &directory, pointer_bits, &positions);
+        let nodes = parse_nodes(context, &ir)?;
+
+        llvm::core::LLVMAddNamedMetadataOperand(
+            module,
+            b"llvm.dbg.cu\0".as_ptr() as *const _,
+            nodes[0]
+        );
+        add_module_flag(module, "Debug Info Version", 3);
+        add_module_flag(module, "Dwarf Version", 4);
+
+        Ok(DebugInfo {
+            context,
+            index_variable: nodes[1],
+            memory_variable: nodes[2],
+            expression: nodes[3],
+            locations: positions.into_iter().zip(nodes[LISTED_NODES..].iter().cloned()).collect(),
+            spans,
+            path: Vec::new(),
+        })
+    }
+
+    // the spans are dropped when they don't describe the whole IR, e.g. once
+    // it has been optimized, the code is then located at the start of the
+    // function
+    pub fn check_spans(&mut self, ir: &[Atom]) {
+        if self.path.is_empty() && !::ir::spans_match(ir, &self.spans) {
+            self.spans.clear();
+        }
+    }
+
+    pub unsafe fn set_location(&self, builder: LLVMBuilderRef, line: usize, column: usize) {
+        llvm::core::LLVMSetCurrentDebugLocation(builder, self.locations[&(line, column)]);
+    }
+
+    // sets the location of the `index`th atom of the current loop, which is
+    // entered when the atom is a loop
+    pub unsafe fn enter(&mut self, builder: LLVMBuilderRef, index: usize) {
+        self.path.push(index);
+        if let Some(span) = self.current_span() {
+            self.set_location(builder, span.line, span.column);
+        }
+    }
+
+    pub fn leave(&mut self) {
+        self.path.pop();
+    }
+
+    fn current_span(&self) -> Option<Span> {
+        let (&last, parents) = self.path.split_last()?;
+        let mut spans = &self.spans;
+        for &index in parents {
+            spans = &spans.get(index)?.sub;
+        }
+        spans.get(last).map(|atom_span| atom_span.span)
+    }
+
+    // `storage` holds the index of the current cell
+    pub unsafe fn declare_index(&self, module: LLVMModuleRef, builder: LLVMBuilderRef,
+                                storage: LLVMValueRef) {
+        self.declare(module, builder, storage, self.index_variable);
+    }
+
+    // `value` points to the first cell, it's kept in an alloca of its own so
+    // that it can be declared like `ptr`
+    pub unsafe fn describe_tape(&self, module: LLVMModuleRef, builder: LLVMBuilderRef,
+                                value: LLVMValueRef) {
+        let storage = llvm::core::LLVMBuildAlloca(
+            builder,
+            llvm::core::LLVMTypeOf(value),
+            b"memory\0".as_ptr() as *const _
+        );
+        llvm::core::LLVMBuildStore(builder, value, storage);
+        self.declare(module, builder, storage, self.memory_variable);
+    }
+
+    unsafe fn declare(&self, module: LLVMModuleRef, builder: LLVMBuilderRef,
+                      storage: LLVMValueRef, variable: LLVMValueRef) {
+        let name = b"llvm.dbg.declare\0".as_ptr() as *const _;
+        let mut declare_fn = llvm::core::LLVMGetNamedFunction(module, name);
+        if declare_fn.is_null() {
+            let metadata_ty = llvm::core::LLVMTypeOf(self.expression);
+            let mut args = [metadata_ty, metadata_ty, metadata_ty];
+            let fn_ty = llvm::core::LLVMFunctionType(
+                llvm::core::LLVMVoidTypeInContext(self.context),
+                args.as_mut_ptr(),
+                args.len() as _,
+                0
+            );
+            declare_fn = llvm::core::LLVMAddFunction(module, name, fn_ty);
+        }
+
+        // a single local value becomes its metadata rather than a node
+        let storage = llvm::core::LLVMMDNodeInContext(self.context, [storage].as_mut_ptr(), 1);
+        llvm::core::LLVMBuildCall(
+            builder,
+            declare_fn,
+            [storage, variable, self.expression].as_mut_ptr(),
+            3,
+            b"\0".as_ptr() as *const _
+        );
+    }
+
+    // LLVM 5 can't attach metadata to functions either, the module is printed
+    // and parsed back with the subprogram attached to `brainfuck`, whose
+    // definition is the only one
+    pub unsafe fn finalize(&self, module: LLVMModuleRef) -> Result<LLVMModuleRef, CString> {
+        let text = llvm::core::LLVMPrintModuleToString(module);
+        let ir = CStr::from_ptr(text).to_string_lossy().into_owned();
+        llvm::core::LLVMDisposeMessage(text);
+
+        let subprogram = ir.lines()
+            .find(|line| line.contains("= distinct !DISubprogram("))
+            .and_then(|line| line.split(' ').next())
+            .ok_or_else(|| CString::new("the subprogram wasn't printed").unwrap())?;
+        let ir = ir.lines().map(|line| {
+            if line.starts_with("define ") && line.contains(" @brainfuck(") && line.ends_with('{') {
+                format!("{} !dbg {} {{\n", line.trim_end_matches('{').trim_end(), subprogram)
+            } else {
+                format!("{}\n", line)
+            }
+        }).collect::<String>();
+
+        let module_with_sp = parse_ir(self.context, &ir)?;
+        llvm::core::LLVMDisposeModule(module);
+        Ok(module_with_sp)
+    }
+}
+
+fn add_positions(spans: &[AtomSpan], positions: &mut Vec<(usize, usize)>) {
+    for atom_span in spans {
+        positions.push((atom_span.span.line, atom_span.span.column));
+        add_positions(&atom_span.sub, positions);
+    }
+}
+
+// characters other than printable ASCII are escaped in strings
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for &byte in s.as_bytes() {
+        if (byte.is_ascii_graphic() && byte != b'"' && byte != b'\\') || byte == b' ' {
+            escaped.push(byte as char);
+        } else {
+            write!(escaped, "\\{:02X}", byte).unwrap();
+        }
+    }
+    escaped
+}
+
+fn debug_info_ir(name: &str, directory: &str, pointer_bits: u32,
+                 positions: &[(usize, usize)]) -> String {
+    let mut ir = String::new();
+    writeln!(ir, "!0 = !DIFile(filename: \"{}\", directory: \"{}\")",
+             escape(name), escape(directory)).unwrap();
+    ir.push_str("!1 = distinct !DICompileUnit(language: DW_LANG_C, file: !0, producer: \"bfc\", \
+                 isOptimized: false, runtimeVersion: 0, emissionKind: FullDebug)\n\
+                 !2 = !{}\n\
+                 !3 = !DISubroutineType(types: !2)\n\
+                 !4 = distinct !DISubprogram(name: \"brainfuck\", scope: !0, file: !0, line: 1, \
+                 type: !3, isLocal: false, isDefinition: true, scopeLine: 1, \
+                 isOptimized: false, unit: !1)\n\
+                 !5 = !DIBasicType(name: \"cell\", size: 8, encoding: DW_ATE_unsigned_char)\n");
+    writeln!(ir, "!6 = !DIDerivedType(tag: DW_TAG_pointer_type, baseType: !5, size: {})",
+             pointer_bits).unwrap();
+    writeln!(ir, "!7 = !DIBasicType(name: \"size_t\", size: {}, encoding: DW_ATE_unsigned)",
+             pointer_bits).unwrap();
+    ir.push_str("!8 = !DILocalVariable(name: \"ptr\", scope: !4, file: !0, line: 1, type: !7)\n\
+                 !9 = !DILocalVariable(name: \"memory\", scope: !4, file: !0, line: 1, type: !6)\n\
+                 !10 = !DIExpression()\n");
+
+    let first_location = 11;
+    for (id, &(line, column)) in positions.iter().enumerate() {
+        writeln!(ir, "!{} = !DILocation(line: {}, column: {}, scope: !4)",
+                 first_location + id, line, column).unwrap();
+    }
+    let locations = (0..positions.len())
+        .map(|id| format!(", !{}", first_location + id))
+        .collect::<String>();
+    writeln!(ir, "!bfc.debug = !{{!1, !8, !9, !10{}}}", locations).unwrap();
+    ir
+}
+
+unsafe fn parse_ir(ctx: LLVMContextRef, ir: &str) -> Result<LLVMModuleRef, CString> {
+    // the buffer is owned by the parser
+    let buffer = llvm::core::LLVMCreateMemoryBufferWithMemoryRangeCopy(
+        ir.as_ptr() as *const c_char,
+        ir.len(),
+        b"main_module\0".as_ptr() as *const _
+    );
+    let mut module = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
+    if llvm::ir_reader::LLVMParseIRInContext(ctx, buffer, &mut module, &mut error) != 0 {
+        return Err(CString::from_raw(error));
+    }
+    Ok(module)
+}
+
+// metadata belongs to the context, so the nodes outlive the parsed module
+unsafe fn parse_nodes(ctx: LLVMContextRef, ir: &str) -> Result<Vec<LLVMValueRef>, CString> {
+    let module = parse_ir(ctx, ir)?;
+    let name = b"bfc.debug\0".as_ptr() as *const _;
+    let count = llvm::core::LLVMGetNamedMetadataNumOperands(module, name);
+    let mut nodes = vec![std::ptr::null_mut(); count as usize];
+    llvm::core::LLVMGetNamedMetadataOperands(module, name, nodes.as_mut_ptr());
+    llvm::core::LLVMDisposeModule(module);
+    Ok(nodes)
+}
+
+unsafe fn add_module_flag(module: LLVMModuleRef, key: &str, value: u64) {
+    let context = llvm::core::LLVMGetModuleContext(module);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(context);
+    // modules linked with other values only get a warning
+    let warning = llvm::core::LLVMConstInt(i32_ty, 2, 0);
+    let key = llvm::core::LLVMMDStringInContext(context, key.as_ptr() as *const _, key.len() as _);
+    let mut operands = [warning, key, llvm::core::LLVMConstInt(i32_ty, value, 0)];
+    let flag = llvm::core::LLVMMDNodeInContext(context, operands.as_mut_ptr(), 3);
+    llvm::core::LLVMAddNamedMetadataOperand(
+        module,
+        b"llvm.module.flags\0".as_ptr() as *const _,
+        flag
+    );
+}
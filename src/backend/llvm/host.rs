@@ -0,0 +1,65 @@
+use std::io::{self, Read, Write};
+use std::os::raw::c_void;
+
+// Context given to the JIT'd code, the first I/O error stops the reads and
+// writes and is reported once the program returns.
+pub struct HostContext<'a> {
+    reader: &'a mut dyn Read,
+    writer: &'a mut dyn Write,
+    pub error: Option<io::Error>,
+}
+
+pub type ReadCallback = extern "C" fn(*mut c_void) -> i32;
+pub type WriteCallback = extern "C" fn(*mut c_void, i32) -> i32;
+pub type BrainfuckFn = extern "C" fn(*mut c_void, ReadCallback, WriteCallback) -> i32;
+// with the tape and index of the caller
+pub type TapeFn = extern "C" fn(*mut c_void, ReadCallback, WriteCallback, *mut u8, *mut usize)
+                               -> i32;
+// and the fuel of the caller
+pub type FueledTapeFn = extern "C" fn(*mut c_void, ReadCallback, WriteCallback, *mut u8,
+                                     *mut usize, *mut u64) -> i32;
+
+impl<'a> HostContext<'a> {
+    pub fn new(reader: &'a mut dyn Read, writer: &'a mut dyn Write) -> Self {
+        HostContext {
+            reader,
+            writer,
+            error: None,
+        }
+    }
+}
+
+pub extern "C" fn host_read(ctx: *mut c_void) -> i32 {
+    let ctx = unsafe { &mut *(ctx as *mut HostContext) };
+    if ctx.error.is_some() {
+        return -1;
+    }
+
+    let mut byte = [0];
+    loop {
+        match ctx.reader.read(&mut byte) {
+            Ok(0) => return -1,
+            Ok(_) => return byte[0] as i32,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                ctx.error = Some(err);
+                return -1;
+            },
+        }
+    }
+}
+
+pub extern "C" fn host_write(ctx: *mut c_void, c: i32) -> i32 {
+    let ctx = unsafe { &mut *(ctx as *mut HostContext) };
+    if ctx.error.is_some() {
+        return -1;
+    }
+
+    match ctx.writer.write_all(&[c as u8]) {
+        Ok(()) => c,
+        Err(err) => {
+            ctx.error = Some(err);
+            -1
+        },
+    }
+}
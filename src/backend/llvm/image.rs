@@ -0,0 +1,191 @@
+use std::ffi::CStr;
+
+use llvm;
+use llvm::prelude::*;
+use llvm::LLVMIntPredicate::*;
+
+use super::io::{append_bb, call, icmp};
+use super::utils;
+
+unsafe fn declare(module: LLVMModuleRef, name: &[u8], ret_ty: LLVMTypeRef,
+                  args: &mut [LLVMTypeRef], var_arg: bool) -> LLVMValueRef {
+    let fn_ty = llvm::core::LLVMFunctionType(
+        ret_ty,
+        args.as_mut_ptr(),
+        args.len() as _,
+        var_arg as _
+    );
+    llvm::core::LLVMAddFunction(module, name.as_ptr() as *const _, fn_ty)
+}
+
+// Constant global holding the cells of a tape image, as an `i8*`.
+pub unsafe fn add_image_global(module: LLVMModuleRef, builder: LLVMBuilderRef, cells: &[u8])
+    -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let data = llvm::core::LLVMConstStringInContext(ctx, cells.as_ptr() as *const _, cells.len() as _, 1);
+    let global = llvm::core::LLVMAddGlobal(
+        module,
+        llvm::core::LLVMTypeOf(data),
+        b"bf_image\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMSetLinkage(global, llvm::LLVMLinkage::LLVMInternalLinkage);
+    llvm::core::LLVMSetGlobalConstant(global, 1);
+    llvm::core::LLVMSetInitializer(global, data);
+
+    llvm::core::LLVMBuildBitCast(
+        builder,
+        global,
+        llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0),
+        b"image\0".as_ptr() as *const _
+    )
+}
+
+pub unsafe fn build_memcpy(module: LLVMModuleRef, builder: LLVMBuilderRef,
+                           dest: LLVMValueRef, src: LLVMValueRef, len: usize) {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    // `size_t` of the target
+    let size_ty = llvm::target::LLVMIntPtrTypeInContext(
+        ctx,
+        llvm::target::LLVMGetModuleDataLayout(module)
+    );
+    let memcpy_fn = declare(
+        module,
+        b"memcpy\0",
+        i8_ptr_ty,
+        &mut [i8_ptr_ty, i8_ptr_ty, size_ty],
+        false
+    );
+    call(builder, memcpy_fn, &mut [dest, src, llvm::core::LLVMConstInt(size_ty, len as _, 0)]);
+}
+
+pub unsafe fn build_memset(module: LLVMModuleRef, builder: LLVMBuilderRef,
+                           dest: LLVMValueRef, len: usize) {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+    let size_ty = llvm::target::LLVMIntPtrTypeInContext(
+        ctx,
+        llvm::target::LLVMGetModuleDataLayout(module)
+    );
+    let memset_fn = declare(
+        module,
+        b"memset\0",
+        i8_ptr_ty,
+        &mut [i8_ptr_ty, i32_ty, size_ty],
+        false
+    );
+    call(builder, memset_fn, &mut [
+        dest,
+        utils::get_int32_const(ctx, 0),
+        llvm::core::LLVMConstInt(size_ty, len as _, 0),
+    ]);
+}
+
+// `void bf_dump_tape(i8* memory, i8* ptr)`, writes the tape to `path` in the
+// same format as `image::write_hex_dump`.
+pub unsafe fn build_dump_fn(module: LLVMModuleRef, path: &CStr, mem_size: usize) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+    let i64_ty = llvm::core::LLVMInt64TypeInContext(ctx);
+    let void_ty = llvm::core::LLVMVoidTypeInContext(ctx);
+
+    let fopen_fn = declare(module, b"fopen\0", i8_ptr_ty, &mut [i8_ptr_ty, i8_ptr_ty], false);
+    let fprintf_fn = declare(module, b"fprintf\0", i32_ty, &mut [i8_ptr_ty, i8_ptr_ty], true);
+    let fclose_fn = declare(module, b"fclose\0", i32_ty, &mut [i8_ptr_ty], false);
+    let perror_fn = declare(module, b"perror\0", void_ty, &mut [i8_ptr_ty], false);
+
+    let function = declare(module, b"bf_dump_tape\0", void_ty, &mut [i8_ptr_ty, i8_ptr_ty], false);
+    llvm::core::LLVMSetLinkage(function, llvm::LLVMLinkage::LLVMInternalLinkage);
+    let memory = llvm::core::LLVMGetParam(function, 0);
+    let ptr = llvm::core::LLVMGetParam(function, 1);
+
+    let builder = llvm::core::LLVMCreateBuilderInContext(ctx);
+    let entry_bb = append_bb(function, b"entry\0");
+    let fail_bb = append_bb(function, b"fail\0");
+    let header_bb = append_bb(function, b"header\0");
+    let cells_bb = append_bb(function, b"cells\0");
+    let done_bb = append_bb(function, b"done\0");
+
+    macro_rules! name {
+        ($name:expr) => {
+            concat!($name, "\0").as_ptr() as *const _
+        }
+    }
+    macro_rules! string {
+        ($s:expr, $name:expr) => {
+            llvm::core::LLVMBuildGlobalStringPtr(
+                builder,
+                $s.as_ptr() as *const _,
+                $name.as_ptr() as *const _
+            )
+        }
+    }
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let path = string!(path, b"bf_dump_path\0");
+    let file = call(builder, fopen_fn, &mut [path, string!(b"w\0", b"\0")]);
+    let is_null = llvm::core::LLVMBuildIsNull(builder, file, name!("is_null"));
+    llvm::core::LLVMBuildCondBr(builder, is_null, fail_bb, header_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, fail_bb);
+    llvm::core::LLVMBuildCall(
+        builder,
+        perror_fn,
+        [path].as_mut_ptr(),
+        1,
+        b"\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildRetVoid(builder);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, header_bb);
+    let ptr_int = llvm::core::LLVMBuildPtrToInt(builder, ptr, i64_ty, name!("ptr"));
+    let memory_int = llvm::core::LLVMBuildPtrToInt(builder, memory, i64_ty, name!("memory"));
+    let index = llvm::core::LLVMBuildSub(builder, ptr_int, memory_int, name!("index"));
+    call(builder, fprintf_fn, &mut [file, string!(b"ptr %lld\n\0", b"\0"), index]);
+    let cell_fmt = string!(b"%02x%c\0", b"\0");
+    llvm::core::LLVMBuildBr(builder, cells_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, cells_bb);
+    let i = llvm::core::LLVMBuildPhi(builder, i32_ty, name!("i"));
+    let cell_ptr = llvm::core::LLVMBuildGEP(builder, memory, [i].as_mut_ptr(), 1, name!("cell"));
+    let cell = llvm::core::LLVMBuildLoad(builder, cell_ptr, name!("cell"));
+    let cell = llvm::core::LLVMBuildZExt(builder, cell, i32_ty, name!("cell"));
+    let column = llvm::core::LLVMBuildURem(
+        builder,
+        i,
+        utils::get_int32_const(ctx, 16),
+        name!("column")
+    );
+    let end_of_line = llvm::core::LLVMBuildOr(
+        builder,
+        icmp(builder, LLVMIntEQ, column, 15),
+        icmp(builder, LLVMIntEQ, i, (mem_size - 1) as u32),
+        name!("end_of_line")
+    );
+    let separator = llvm::core::LLVMBuildSelect(
+        builder,
+        end_of_line,
+        utils::get_int32_const(ctx, b'\n' as isize),
+        utils::get_int32_const(ctx, b' ' as isize),
+        name!("separator")
+    );
+    call(builder, fprintf_fn, &mut [file, cell_fmt, cell, separator]);
+    let next = llvm::core::LLVMBuildAdd(builder, i, utils::get_int32_const(ctx, 1), name!("next"));
+    llvm::core::LLVMAddIncoming(
+        i,
+        [utils::get_int32_const(ctx, 0), next].as_mut_ptr(),
+        [header_bb, cells_bb].as_mut_ptr(),
+        2
+    );
+    let finished = icmp(builder, LLVMIntEQ, next, mem_size as u32);
+    llvm::core::LLVMBuildCondBr(builder, finished, done_bb, cells_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, done_bb);
+    call(builder, fclose_fn, &mut [file]);
+    llvm::core::LLVMBuildRetVoid(builder);
+
+    llvm::core::LLVMDisposeBuilder(builder);
+    function
+}
@@ -0,0 +1,721 @@
+use llvm;
+use llvm::prelude::*;
+use llvm::LLVMIntPredicate::*;
+
+use backend::{IoEncoding, IoOptions};
+use super::utils;
+
+// Helpers with the same signatures as `bf_getchar` and `bf_putchar`, built on
+// top of them to implement the non raw I/O modes.
+pub struct IoFunctions {
+    pub read_fn: LLVMValueRef,
+    pub print_fn: LLVMValueRef,
+}
+
+pub unsafe fn build_io_functions(
+    module: LLVMModuleRef,
+    getchar_fn: LLVMValueRef,
+    putchar_fn: LLVMValueRef,
+    io: IoOptions
+) -> IoFunctions {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let builder = llvm::core::LLVMCreateBuilderInContext(ctx);
+
+    let getbyte_fn = if io.translate_newlines {
+        build_getbyte_crlf(module, builder, getchar_fn)
+    } else {
+        getchar_fn
+    };
+
+    let functions = match io.encoding {
+        IoEncoding::Bytes => IoFunctions {
+            read_fn: getbyte_fn,
+            print_fn: putchar_fn,
+        },
+        IoEncoding::Unicode => IoFunctions {
+            read_fn: build_read_unicode(module, builder, getbyte_fn),
+            print_fn: build_print_unicode(module, builder, putchar_fn),
+        },
+        IoEncoding::Decimal => IoFunctions {
+            read_fn: build_read_decimal(module, builder, getbyte_fn),
+            print_fn: build_print_decimal(module, builder, putchar_fn),
+        },
+    };
+
+    llvm::core::LLVMDisposeBuilder(builder);
+    functions
+}
+
+// The helpers take the parameters of `brainfuck`, `(ctx, read, write)`, then
+// the readers take the `i1*` telling whether the last byte read was a CR, local
+// to the run, and the writers take the byte or the value to print.
+unsafe fn add_helper(module: LLVMModuleRef, name: &[u8], writer: bool) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+    let mut args = [
+        i8_ptr_ty,
+        llvm::core::LLVMPointerType(read_callback_type(ctx), 0),
+        llvm::core::LLVMPointerType(write_callback_type(ctx), 0),
+        if writer {
+            i32_ty
+        } else {
+            llvm::core::LLVMPointerType(llvm::core::LLVMInt1TypeInContext(ctx), 0)
+        },
+    ];
+    let fn_ty = llvm::core::LLVMFunctionType(i32_ty, args.as_mut_ptr(), 4, 0);
+    let function = llvm::core::LLVMAddFunction(module, name.as_ptr() as *const _, fn_ty);
+    llvm::core::LLVMSetLinkage(function, llvm::LLVMLinkage::LLVMInternalLinkage);
+    function
+}
+
+// arguments of a reader called from `function`, which takes the parameters of
+// `brainfuck` first
+pub unsafe fn read_args(function: LLVMValueRef, last_cr: LLVMValueRef) -> Vec<LLVMValueRef> {
+    let mut args = brainfuck_args(function);
+    args.push(last_cr);
+    args
+}
+
+pub unsafe fn write_args(function: LLVMValueRef, c: LLVMValueRef) -> Vec<LLVMValueRef> {
+    let mut args = brainfuck_args(function);
+    args.push(c);
+    args
+}
+
+unsafe fn brainfuck_args(function: LLVMValueRef) -> Vec<LLVMValueRef> {
+    (0..3).map(|index| llvm::core::LLVMGetParam(function, index)).collect()
+}
+
+// arguments of a reader called from the reader `function`
+unsafe fn forward_read_args(function: LLVMValueRef) -> Vec<LLVMValueRef> {
+    read_args(function, llvm::core::LLVMGetParam(function, 3))
+}
+
+// Types of the callbacks given to `brainfuck`, `read(ctx)` returns a byte or
+// -1 at EOF like `getchar` and `write(ctx, c)` behaves like `putchar`.
+pub unsafe fn read_callback_type(ctx: LLVMContextRef) -> LLVMTypeRef {
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    let mut args = [i8_ptr_ty];
+    llvm::core::LLVMFunctionType(llvm::core::LLVMInt32TypeInContext(ctx), args.as_mut_ptr(), 1, 0)
+}
+
+pub unsafe fn write_callback_type(ctx: LLVMContextRef) -> LLVMTypeRef {
+    let i8_ptr_ty = llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(ctx), 0);
+    let mut args = [i8_ptr_ty, llvm::core::LLVMInt32TypeInContext(ctx)];
+    llvm::core::LLVMFunctionType(llvm::core::LLVMInt32TypeInContext(ctx), args.as_mut_ptr(), 2, 0)
+}
+
+// `bf_getchar` and `bf_putchar` forward to the callbacks given to `brainfuck`,
+// which are passed along to every helper so that `brainfuck` is reentrant.
+pub struct HostIo {
+    pub getchar_fn: LLVMValueRef,
+    pub putchar_fn: LLVMValueRef,
+}
+
+pub unsafe fn build_host_io(module: LLVMModuleRef) -> HostIo {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let builder = llvm::core::LLVMCreateBuilderInContext(ctx);
+
+    let getchar_fn = add_helper(module, b"bf_getchar\0", false);
+    llvm::core::LLVMPositionBuilderAtEnd(builder, append_bb(getchar_fn, b"entry\0"));
+    let ctx_value = llvm::core::LLVMGetParam(getchar_fn, 0);
+    let read_fn = llvm::core::LLVMGetParam(getchar_fn, 1);
+    let c = call(builder, read_fn, &mut [ctx_value]);
+    llvm::core::LLVMBuildRet(builder, c);
+
+    let putchar_fn = add_helper(module, b"bf_putchar\0", true);
+    llvm::core::LLVMPositionBuilderAtEnd(builder, append_bb(putchar_fn, b"entry\0"));
+    let ctx_value = llvm::core::LLVMGetParam(putchar_fn, 0);
+    let write_fn = llvm::core::LLVMGetParam(putchar_fn, 2);
+    let c = llvm::core::LLVMGetParam(putchar_fn, 3);
+    let result = call(builder, write_fn, &mut [ctx_value, c]);
+    llvm::core::LLVMBuildRet(builder, result);
+
+    llvm::core::LLVMDisposeBuilder(builder);
+    HostIo { getchar_fn, putchar_fn }
+}
+
+// Callbacks on top of libc for standalone executables.
+pub unsafe fn build_libc_callbacks(module: LLVMModuleRef) -> (LLVMValueRef, LLVMValueRef) {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+    let getchar_ty = llvm::core::LLVMFunctionType(i32_ty, [].as_mut_ptr(), 0, 0);
+    let getchar_fn = llvm::core::LLVMAddFunction(module, b"getchar\0".as_ptr() as *const _, getchar_ty);
+    let putchar_ty = llvm::core::LLVMFunctionType(i32_ty, [i32_ty].as_mut_ptr(), 1, 0);
+    let putchar_fn = llvm::core::LLVMAddFunction(module, b"putchar\0".as_ptr() as *const _, putchar_ty);
+
+    let builder = llvm::core::LLVMCreateBuilderInContext(ctx);
+
+    let read_fn = llvm::core::LLVMAddFunction(
+        module,
+        b"bf_libc_read\0".as_ptr() as *const _,
+        read_callback_type(ctx)
+    );
+    llvm::core::LLVMSetLinkage(read_fn, llvm::LLVMLinkage::LLVMInternalLinkage);
+    llvm::core::LLVMPositionBuilderAtEnd(builder, append_bb(read_fn, b"entry\0"));
+    let c = call(builder, getchar_fn, &mut []);
+    llvm::core::LLVMBuildRet(builder, c);
+
+    let write_fn = llvm::core::LLVMAddFunction(
+        module,
+        b"bf_libc_write\0".as_ptr() as *const _,
+        write_callback_type(ctx)
+    );
+    llvm::core::LLVMSetLinkage(write_fn, llvm::LLVMLinkage::LLVMInternalLinkage);
+    llvm::core::LLVMPositionBuilderAtEnd(builder, append_bb(write_fn, b"entry\0"));
+    let c = call(builder, putchar_fn, &mut [llvm::core::LLVMGetParam(write_fn, 1)]);
+    llvm::core::LLVMBuildRet(builder, c);
+
+    llvm::core::LLVMDisposeBuilder(builder);
+    (read_fn, write_fn)
+}
+
+pub unsafe fn append_bb(function: LLVMValueRef, name: &[u8]) -> LLVMBasicBlockRef {
+    let ctx = llvm::core::LLVMGetModuleContext(llvm::core::LLVMGetGlobalParent(function));
+    llvm::core::LLVMAppendBasicBlockInContext(ctx, function, name.as_ptr() as *const _)
+}
+
+pub unsafe fn call(builder: LLVMBuilderRef, function: LLVMValueRef, args: &mut [LLVMValueRef])
+    -> LLVMValueRef {
+    llvm::core::LLVMBuildCall(
+        builder,
+        function,
+        args.as_mut_ptr(),
+        args.len() as _,
+        b"call\0".as_ptr() as *const _
+    )
+}
+
+pub unsafe fn icmp(builder: LLVMBuilderRef, pred: llvm::LLVMIntPredicate, lhs: LLVMValueRef, rhs: u32)
+    -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetTypeContext(llvm::core::LLVMTypeOf(lhs));
+    llvm::core::LLVMBuildICmp(
+        builder,
+        pred,
+        lhs,
+        utils::get_int32_const(ctx, rhs as isize),
+        b"cmp\0".as_ptr() as *const _
+    )
+}
+
+unsafe fn build_getbyte_crlf(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    getchar_fn: LLVMValueRef
+) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let function = add_helper(module, b"bf_getbyte\0", false);
+
+    let last_cr = llvm::core::LLVMGetParam(function, 3);
+
+    let entry_bb = append_bb(function, b"entry\0");
+    let reread_bb = append_bb(function, b"reread\0");
+    let done_bb = append_bb(function, b"done\0");
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let c = call(builder, getchar_fn, &mut forward_read_args(function));
+    let was_cr = llvm::core::LLVMBuildLoad(builder, last_cr, b"was_cr\0".as_ptr() as *const _);
+    let is_lf = icmp(builder, LLVMIntEQ, c, b'\n' as u32);
+    let skip = llvm::core::LLVMBuildAnd(builder, was_cr, is_lf, b"skip\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildCondBr(builder, skip, reread_bb, done_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, reread_bb);
+    let reread_c = call(builder, getchar_fn, &mut forward_read_args(function));
+    llvm::core::LLVMBuildBr(builder, done_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, done_bb);
+    let c_phi = llvm::core::LLVMBuildPhi(
+        builder,
+        llvm::core::LLVMInt32TypeInContext(ctx),
+        b"c\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMAddIncoming(
+        c_phi,
+        [c, reread_c].as_mut_ptr(),
+        [entry_bb, reread_bb].as_mut_ptr(),
+        2
+    );
+    let is_cr = icmp(builder, LLVMIntEQ, c_phi, b'\r' as u32);
+    llvm::core::LLVMBuildStore(builder, is_cr, last_cr);
+    let result = llvm::core::LLVMBuildSelect(
+        builder,
+        is_cr,
+        utils::get_int32_const(ctx, b'\n' as isize),
+        c_phi,
+        b"result\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildRet(builder, result);
+
+    function
+}
+
+unsafe fn build_read_unicode(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    getbyte_fn: LLVMValueRef
+) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let function = add_helper(module, b"bf_read_unicode\0", false);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+
+    let entry_bb = append_bb(function, b"entry\0");
+    let ascii_bb = append_bb(function, b"ascii\0");
+    let lead_bb = append_bb(function, b"lead\0");
+    let multi_bb = append_bb(function, b"multi\0");
+    let loop_bb = append_bb(function, b"loop\0");
+    let body_bb = append_bb(function, b"body\0");
+    let next_bb = append_bb(function, b"next\0");
+    let done_bb = append_bb(function, b"done\0");
+    let invalid_bb = append_bb(function, b"invalid\0");
+
+    // ASCII and EOF are returned as is
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let value_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"value_slot\0".as_ptr() as *const _);
+    let count_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"count_slot\0".as_ptr() as *const _);
+    let c = call(builder, getbyte_fn, &mut forward_read_args(function));
+    let is_ascii = icmp(builder, LLVMIntSLT, c, 0x80);
+    llvm::core::LLVMBuildCondBr(builder, is_ascii, ascii_bb, lead_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, ascii_bb);
+    llvm::core::LLVMBuildRet(builder, c);
+
+    // 0x80..0xBF are continuation bytes and 0xF8.. are never valid
+    llvm::core::LLVMPositionBuilderAtEnd(builder, lead_bb);
+    let is_continuation = icmp(builder, LLVMIntULT, c, 0xC0);
+    let is_too_long = icmp(builder, LLVMIntUGE, c, 0xF8);
+    let is_invalid = llvm::core::LLVMBuildOr(
+        builder,
+        is_continuation,
+        is_too_long,
+        b"is_invalid\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildCondBr(builder, is_invalid, invalid_bb, multi_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, multi_bb);
+    let is_two = icmp(builder, LLVMIntULT, c, 0xE0);
+    let is_three = icmp(builder, LLVMIntULT, c, 0xF0);
+    let count = llvm::core::LLVMBuildSelect(
+        builder,
+        is_three,
+        utils::get_int32_const(ctx, 2),
+        utils::get_int32_const(ctx, 3),
+        b"count\0".as_ptr() as *const _
+    );
+    let count = llvm::core::LLVMBuildSelect(
+        builder,
+        is_two,
+        utils::get_int32_const(ctx, 1),
+        count,
+        b"count\0".as_ptr() as *const _
+    );
+    let mask = llvm::core::LLVMBuildSelect(
+        builder,
+        is_three,
+        utils::get_int32_const(ctx, 0x0F),
+        utils::get_int32_const(ctx, 0x07),
+        b"mask\0".as_ptr() as *const _
+    );
+    let mask = llvm::core::LLVMBuildSelect(
+        builder,
+        is_two,
+        utils::get_int32_const(ctx, 0x1F),
+        mask,
+        b"mask\0".as_ptr() as *const _
+    );
+    let value = llvm::core::LLVMBuildAnd(builder, c, mask, b"value\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildStore(builder, value, value_slot);
+    llvm::core::LLVMBuildStore(builder, count, count_slot);
+    llvm::core::LLVMBuildBr(builder, loop_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, loop_bb);
+    let count = llvm::core::LLVMBuildLoad(builder, count_slot, b"count\0".as_ptr() as *const _);
+    let is_done = icmp(builder, LLVMIntEQ, count, 0);
+    llvm::core::LLVMBuildCondBr(builder, is_done, done_bb, body_bb);
+
+    // EOF (-1) is rejected by the continuation byte check
+    llvm::core::LLVMPositionBuilderAtEnd(builder, body_bb);
+    let b = call(builder, getbyte_fn, &mut forward_read_args(function));
+    let tag = llvm::core::LLVMBuildAnd(
+        builder,
+        b,
+        utils::get_int32_const(ctx, 0xC0),
+        b"tag\0".as_ptr() as *const _
+    );
+    let is_continuation = icmp(builder, LLVMIntEQ, tag, 0x80);
+    llvm::core::LLVMBuildCondBr(builder, is_continuation, next_bb, invalid_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, next_bb);
+    let value = llvm::core::LLVMBuildLoad(builder, value_slot, b"value\0".as_ptr() as *const _);
+    let value = llvm::core::LLVMBuildShl(
+        builder,
+        value,
+        utils::get_int32_const(ctx, 6),
+        b"value\0".as_ptr() as *const _
+    );
+    let bits = llvm::core::LLVMBuildAnd(
+        builder,
+        b,
+        utils::get_int32_const(ctx, 0x3F),
+        b"bits\0".as_ptr() as *const _
+    );
+    let value = llvm::core::LLVMBuildOr(builder, value, bits, b"value\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildStore(builder, value, value_slot);
+    let count = llvm::core::LLVMBuildSub(
+        builder,
+        count,
+        utils::get_int32_const(ctx, 1),
+        b"count\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, count, count_slot);
+    llvm::core::LLVMBuildBr(builder, loop_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, done_bb);
+    let value = llvm::core::LLVMBuildLoad(builder, value_slot, b"value\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildRet(builder, value);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, invalid_bb);
+    llvm::core::LLVMBuildRet(builder, utils::get_int32_const(ctx, 0xFFFD));
+
+    function
+}
+
+unsafe fn build_print_unicode(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    putchar_fn: LLVMValueRef
+) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let function = add_helper(module, b"bf_print_unicode\0", true);
+
+    let entry_bb = append_bb(function, b"entry\0");
+    let check_two_bb = append_bb(function, b"check_two\0");
+    let check_three_bb = append_bb(function, b"check_three\0");
+    let encode_bbs = [
+        append_bb(function, b"one\0"),
+        append_bb(function, b"two\0"),
+        append_bb(function, b"three\0"),
+        append_bb(function, b"four\0"),
+    ];
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let c = llvm::core::LLVMGetParam(function, 3);
+    let is_too_big = icmp(builder, LLVMIntUGT, c, 0x10FFFF);
+    let surrogate_index = llvm::core::LLVMBuildSub(
+        builder,
+        c,
+        utils::get_int32_const(ctx, 0xD800),
+        b"surrogate_index\0".as_ptr() as *const _
+    );
+    let is_surrogate = icmp(builder, LLVMIntULT, surrogate_index, 0x800);
+    let is_invalid = llvm::core::LLVMBuildOr(
+        builder,
+        is_too_big,
+        is_surrogate,
+        b"is_invalid\0".as_ptr() as *const _
+    );
+    let c = llvm::core::LLVMBuildSelect(
+        builder,
+        is_invalid,
+        utils::get_int32_const(ctx, 0xFFFD),
+        c,
+        b"c\0".as_ptr() as *const _
+    );
+    let is_one = icmp(builder, LLVMIntULT, c, 0x80);
+    llvm::core::LLVMBuildCondBr(builder, is_one, encode_bbs[0], check_two_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, check_two_bb);
+    let is_two = icmp(builder, LLVMIntULT, c, 0x800);
+    llvm::core::LLVMBuildCondBr(builder, is_two, encode_bbs[1], check_three_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, check_three_bb);
+    let is_three = icmp(builder, LLVMIntULT, c, 0x10000);
+    llvm::core::LLVMBuildCondBr(builder, is_three, encode_bbs[2], encode_bbs[3]);
+
+    let leads = [0x00, 0xC0, 0xE0, 0xF0];
+    for (continuation_len, (&bb, &lead)) in encode_bbs.iter().zip(leads.iter()).enumerate() {
+        llvm::core::LLVMPositionBuilderAtEnd(builder, bb);
+
+        let first = llvm::core::LLVMBuildLShr(
+            builder,
+            c,
+            utils::get_int32_const(ctx, 6 * continuation_len as isize),
+            b"byte\0".as_ptr() as *const _
+        );
+        let first = llvm::core::LLVMBuildOr(
+            builder,
+            first,
+            utils::get_int32_const(ctx, lead),
+            b"byte\0".as_ptr() as *const _
+        );
+        call(builder, putchar_fn, &mut write_args(function, first));
+
+        for shift in (0..continuation_len).rev() {
+            let byte = llvm::core::LLVMBuildLShr(
+                builder,
+                c,
+                utils::get_int32_const(ctx, 6 * shift as isize),
+                b"byte\0".as_ptr() as *const _
+            );
+            let byte = llvm::core::LLVMBuildAnd(
+                builder,
+                byte,
+                utils::get_int32_const(ctx, 0x3F),
+                b"byte\0".as_ptr() as *const _
+            );
+            let byte = llvm::core::LLVMBuildOr(
+                builder,
+                byte,
+                utils::get_int32_const(ctx, 0x80),
+                b"byte\0".as_ptr() as *const _
+            );
+            call(builder, putchar_fn, &mut write_args(function, byte));
+        }
+        llvm::core::LLVMBuildRet(builder, c);
+    }
+
+    function
+}
+
+unsafe fn build_read_decimal(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    getbyte_fn: LLVMValueRef
+) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    let function = add_helper(module, b"bf_read_decimal\0", false);
+    let i1_ty = llvm::core::LLVMInt1TypeInContext(ctx);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+
+    let entry_bb = append_bb(function, b"entry\0");
+    let skip_bb = append_bb(function, b"skip_whitespace\0");
+    let skip_next_bb = append_bb(function, b"skip_next\0");
+    let check_eof_bb = append_bb(function, b"check_eof\0");
+    let eof_bb = append_bb(function, b"eof\0");
+    let sign_bb = append_bb(function, b"sign\0");
+    let sign_next_bb = append_bb(function, b"sign_next\0");
+    let digits_bb = append_bb(function, b"digits\0");
+    let digit_bb = append_bb(function, b"digit\0");
+    let done_bb = append_bb(function, b"done\0");
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let c_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"c_slot\0".as_ptr() as *const _);
+    let value_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"value_slot\0".as_ptr() as *const _);
+    let negative_slot = llvm::core::LLVMBuildAlloca(builder, i1_ty, b"negative_slot\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildStore(builder, utils::get_int32_const(ctx, 0), value_slot);
+    let c = call(builder, getbyte_fn, &mut forward_read_args(function));
+    llvm::core::LLVMBuildStore(builder, c, c_slot);
+    llvm::core::LLVMBuildBr(builder, skip_bb);
+
+    // ' ' and '\t'..='\r'
+    llvm::core::LLVMPositionBuilderAtEnd(builder, skip_bb);
+    let c = llvm::core::LLVMBuildLoad(builder, c_slot, b"c\0".as_ptr() as *const _);
+    let is_space = icmp(builder, LLVMIntEQ, c, b' ' as u32);
+    let control_index = llvm::core::LLVMBuildSub(
+        builder,
+        c,
+        utils::get_int32_const(ctx, b'\t' as isize),
+        b"control_index\0".as_ptr() as *const _
+    );
+    let is_control = icmp(builder, LLVMIntULT, control_index, 5);
+    let is_whitespace = llvm::core::LLVMBuildOr(
+        builder,
+        is_space,
+        is_control,
+        b"is_whitespace\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildCondBr(builder, is_whitespace, skip_next_bb, check_eof_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, skip_next_bb);
+    let c = call(builder, getbyte_fn, &mut forward_read_args(function));
+    llvm::core::LLVMBuildStore(builder, c, c_slot);
+    llvm::core::LLVMBuildBr(builder, skip_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, check_eof_bb);
+    let c = llvm::core::LLVMBuildLoad(builder, c_slot, b"c\0".as_ptr() as *const _);
+    let is_eof = icmp(builder, LLVMIntSLT, c, 0);
+    llvm::core::LLVMBuildCondBr(builder, is_eof, eof_bb, sign_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, eof_bb);
+    llvm::core::LLVMBuildRet(builder, c);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, sign_bb);
+    let is_negative = icmp(builder, LLVMIntEQ, c, b'-' as u32);
+    llvm::core::LLVMBuildStore(builder, is_negative, negative_slot);
+    llvm::core::LLVMBuildCondBr(builder, is_negative, sign_next_bb, digits_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, sign_next_bb);
+    let c = call(builder, getbyte_fn, &mut forward_read_args(function));
+    llvm::core::LLVMBuildStore(builder, c, c_slot);
+    llvm::core::LLVMBuildBr(builder, digits_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, digits_bb);
+    let c = llvm::core::LLVMBuildLoad(builder, c_slot, b"c\0".as_ptr() as *const _);
+    let digit = llvm::core::LLVMBuildSub(
+        builder,
+        c,
+        utils::get_int32_const(ctx, b'0' as isize),
+        b"digit\0".as_ptr() as *const _
+    );
+    let is_digit = icmp(builder, LLVMIntULT, digit, 10);
+    llvm::core::LLVMBuildCondBr(builder, is_digit, digit_bb, done_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, digit_bb);
+    let value = llvm::core::LLVMBuildLoad(builder, value_slot, b"value\0".as_ptr() as *const _);
+    let value = llvm::core::LLVMBuildMul(
+        builder,
+        value,
+        utils::get_int32_const(ctx, 10),
+        b"value\0".as_ptr() as *const _
+    );
+    let value = llvm::core::LLVMBuildAdd(builder, value, digit, b"value\0".as_ptr() as *const _);
+    llvm::core::LLVMBuildStore(builder, value, value_slot);
+    let c = call(builder, getbyte_fn, &mut forward_read_args(function));
+    llvm::core::LLVMBuildStore(builder, c, c_slot);
+    llvm::core::LLVMBuildBr(builder, digits_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, done_bb);
+    let value = llvm::core::LLVMBuildLoad(builder, value_slot, b"value\0".as_ptr() as *const _);
+    let negated = llvm::core::LLVMBuildNeg(builder, value, b"negated\0".as_ptr() as *const _);
+    let is_negative = llvm::core::LLVMBuildLoad(
+        builder,
+        negative_slot,
+        b"is_negative\0".as_ptr() as *const _
+    );
+    let value = llvm::core::LLVMBuildSelect(
+        builder,
+        is_negative,
+        negated,
+        value,
+        b"value\0".as_ptr() as *const _
+    );
+    // the cells are bytes, which keeps the value apart from EOF
+    let value = llvm::core::LLVMBuildAnd(
+        builder,
+        value,
+        utils::get_int32_const(ctx, 0xFF),
+        b"value\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildRet(builder, value);
+
+    function
+}
+
+unsafe fn build_print_decimal(
+    module: LLVMModuleRef,
+    builder: LLVMBuilderRef,
+    putchar_fn: LLVMValueRef
+) -> LLVMValueRef {
+    let ctx = llvm::core::LLVMGetModuleContext(module);
+    const BUF_SIZE: u32 = 12;
+
+    let function = add_helper(module, b"bf_print_decimal\0", true);
+    let i8_ty = llvm::core::LLVMInt8TypeInContext(ctx);
+    let i32_ty = llvm::core::LLVMInt32TypeInContext(ctx);
+
+    let entry_bb = append_bb(function, b"entry\0");
+    let minus_bb = append_bb(function, b"minus\0");
+    let digits_bb = append_bb(function, b"digits\0");
+    let print_bb = append_bb(function, b"print\0");
+    let print_digit_bb = append_bb(function, b"print_digit\0");
+    let end_bb = append_bb(function, b"end\0");
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+    let buf = llvm::core::LLVMBuildAlloca(
+        builder,
+        llvm::core::LLVMArrayType(i8_ty, BUF_SIZE),
+        b"buf\0".as_ptr() as *const _
+    );
+    let index_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"index_slot\0".as_ptr() as *const _);
+    let rest_slot = llvm::core::LLVMBuildAlloca(builder, i32_ty, b"rest_slot\0".as_ptr() as *const _);
+    let value = llvm::core::LLVMGetParam(function, 3);
+    let is_negative = icmp(builder, LLVMIntSLT, value, 0);
+    let negated = llvm::core::LLVMBuildNeg(builder, value, b"negated\0".as_ptr() as *const _);
+    let rest = llvm::core::LLVMBuildSelect(
+        builder,
+        is_negative,
+        negated,
+        value,
+        b"rest\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, rest, rest_slot);
+    llvm::core::LLVMBuildStore(builder, utils::get_int32_const(ctx, BUF_SIZE as isize), index_slot);
+    llvm::core::LLVMBuildCondBr(builder, is_negative, minus_bb, digits_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, minus_bb);
+    call(builder, putchar_fn, &mut write_args(function, utils::get_int32_const(ctx, b'-' as isize)));
+    llvm::core::LLVMBuildBr(builder, digits_bb);
+
+    // digits are stored from the end of the buffer
+    llvm::core::LLVMPositionBuilderAtEnd(builder, digits_bb);
+    let index = llvm::core::LLVMBuildLoad(builder, index_slot, b"index\0".as_ptr() as *const _);
+    let index = llvm::core::LLVMBuildSub(
+        builder,
+        index,
+        utils::get_int32_const(ctx, 1),
+        b"index\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, index, index_slot);
+    let rest = llvm::core::LLVMBuildLoad(builder, rest_slot, b"rest\0".as_ptr() as *const _);
+    let digit = llvm::core::LLVMBuildURem(
+        builder,
+        rest,
+        utils::get_int32_const(ctx, 10),
+        b"digit\0".as_ptr() as *const _
+    );
+    let digit = llvm::core::LLVMBuildAdd(
+        builder,
+        digit,
+        utils::get_int32_const(ctx, b'0' as isize),
+        b"digit\0".as_ptr() as *const _
+    );
+    let digit = llvm::core::LLVMBuildTrunc(builder, digit, i8_ty, b"digit\0".as_ptr() as *const _);
+    let digit_ptr = llvm::core::LLVMBuildGEP(
+        builder,
+        buf,
+        [utils::get_int32_const(ctx, 0), index].as_mut_ptr(),
+        2,
+        b"digit_ptr\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, digit, digit_ptr);
+    let rest = llvm::core::LLVMBuildUDiv(
+        builder,
+        rest,
+        utils::get_int32_const(ctx, 10),
+        b"rest\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, rest, rest_slot);
+    let has_more = icmp(builder, LLVMIntNE, rest, 0);
+    llvm::core::LLVMBuildCondBr(builder, has_more, digits_bb, print_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, print_bb);
+    let index = llvm::core::LLVMBuildLoad(builder, index_slot, b"index\0".as_ptr() as *const _);
+    let in_buf = icmp(builder, LLVMIntULT, index, BUF_SIZE);
+    llvm::core::LLVMBuildCondBr(builder, in_buf, print_digit_bb, end_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, print_digit_bb);
+    let digit_ptr = llvm::core::LLVMBuildGEP(
+        builder,
+        buf,
+        [utils::get_int32_const(ctx, 0), index].as_mut_ptr(),
+        2,
+        b"digit_ptr\0".as_ptr() as *const _
+    );
+    let digit = llvm::core::LLVMBuildLoad(builder, digit_ptr, b"digit\0".as_ptr() as *const _);
+    let digit = llvm::core::LLVMBuildZExt(builder, digit, i32_ty, b"digit\0".as_ptr() as *const _);
+    call(builder, putchar_fn, &mut write_args(function, digit));
+    let index = llvm::core::LLVMBuildAdd(
+        builder,
+        index,
+        utils::get_int32_const(ctx, 1),
+        b"index\0".as_ptr() as *const _
+    );
+    llvm::core::LLVMBuildStore(builder, index, index_slot);
+    llvm::core::LLVMBuildBr(builder, print_bb);
+
+    llvm::core::LLVMPositionBuilderAtEnd(builder, end_bb);
+    call(builder, putchar_fn, &mut write_args(function, utils::get_int32_const(ctx, b'\n' as isize)));
+    llvm::core::LLVMBuildRet(builder, value);
+
+    function
+}
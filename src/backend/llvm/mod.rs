@@ -0,0 +1,1258 @@
+use llvm;
+use llvm::prelude::*;
+use llvm::execution_engine::LLVMExecutionEngineRef;
+use llvm::target_machine::LLVMCodeGenFileType;
+use std;
+use std::os::raw::c_char;
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use ir::{Atom, AtomSpan, Value};
+use backend::{Backend, IoOptions, TapeImage, Profile, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+mod debug;
+mod host;
+mod image;
+mod io;
+mod passes;
+mod profile;
+mod target;
+pub use self::passes::{OptLevel, PassTiming};
+pub use self::target::TargetOptions;
+
+const MEM_SIZE: usize = 30000;
+// error of the runs stopped by `LLVMBackend::with_fuel`
+pub const OUT_OF_FUEL_ERROR: &str = "out of fuel";
+// error of the runs reading past the end of the input
+pub const EOF_ERROR: &str = "end of input";
+// what `brainfuck` returns instead of the current cell when stopped early
+const OUT_OF_FUEL_RESULT: i32 = -1;
+const EOF_RESULT: i32 = -2;
+
+// Every backend has its own context, so that separate backends can be used
+// from separate threads.
+#[derive(Debug)]
+pub struct LLVMBackend {
+    context: LLVMContextRef,
+    module: LLVMModuleRef,
+    brainfuck_fn: LLVMValueRef,
+    builder: LLVMBuilderRef,
+    // `[tape_size x i8]` global, or the tape of the caller
+    memory: LLVMValueRef,
+    // alloca holding the index of the current cell
+    index: LLVMValueRef,
+    // alloca holding the value returned by `brainfuck`
+    result: LLVMValueRef,
+    // alloca telling whether the last byte read was a CR, see `IoOptions`
+    last_cr: LLVMValueRef,
+    // dumps the tape, writes the index back and returns the result
+    return_bb: LLVMBasicBlockRef,
+    // returns `EOF_RESULT`, built on the first read
+    eof_bb: LLVMBasicBlockRef,
+    index_ty: LLVMTypeRef,
+    putchar_fn: LLVMValueRef,
+    getchar_fn: LLVMValueRef,
+    print_fn: LLVMValueRef,
+    read_fn: LLVMValueRef,
+    dump_fn: LLVMValueRef,
+    // `i64` global counting down the loop iterations left, or the pointer to
+    // the fuel of the caller
+    fuel_global: LLVMValueRef,
+    // returns -1 from `brainfuck`
+    out_of_fuel_bb: LLVMBasicBlockRef,
+    io: IoOptions,
+    tape_size: usize,
+    wrap: bool,
+    external_tape: bool,
+    external_fuel: bool,
+    fuel: Option<u64>,
+    image: Option<TapeImage>,
+    dump: Option<PathBuf>,
+    target: TargetOptions,
+    debug_source: Option<(PathBuf, Vec<AtomSpan>)>,
+    debug: Option<debug::DebugInfo>,
+    profile: Option<Profile>,
+    // number of the next loop in the profile
+    loop_id: usize,
+}
+
+impl LLVMBackend {
+    pub fn new() -> LLVMBackend {
+        let context = unsafe { llvm::core::LLVMContextCreate() };
+        LLVMBackend {
+            context,
+            module: unsafe {
+                llvm::core::LLVMModuleCreateWithNameInContext(
+                    b"main_module\0".as_ptr() as *const _,
+                    context
+                )
+            },
+            brainfuck_fn: std::ptr::null_mut(),
+            builder: unsafe {
+                llvm::core::LLVMCreateBuilderInContext(context)
+            },
+            memory: std::ptr::null_mut(),
+            index: std::ptr::null_mut(),
+            result: std::ptr::null_mut(),
+            last_cr: std::ptr::null_mut(),
+            return_bb: std::ptr::null_mut(),
+            eof_bb: std::ptr::null_mut(),
+            index_ty: std::ptr::null_mut(),
+            putchar_fn: std::ptr::null_mut(),
+            getchar_fn: std::ptr::null_mut(),
+            print_fn: std::ptr::null_mut(),
+            read_fn: std::ptr::null_mut(),
+            dump_fn: std::ptr::null_mut(),
+            fuel_global: std::ptr::null_mut(),
+            out_of_fuel_bb: std::ptr::null_mut(),
+            io: IoOptions::default(),
+            tape_size: MEM_SIZE,
+            wrap: false,
+            external_tape: false,
+            external_fuel: false,
+            fuel: None,
+            image: None,
+            dump: None,
+            target: TargetOptions::default(),
+            debug_source: None,
+            debug: None,
+            profile: None,
+            loop_id: 0,
+        }
+    }
+
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    // moving past an end of the tape wraps around to the other end, like the
+    // interpreter, instead of being undefined behavior
+    pub fn with_wrap(mut self) -> Self {
+        self.wrap = true;
+        self
+    }
+
+    // `brainfuck` also takes `i8* tape, size_t* index`, the `tape_size` cells
+    // belong to the caller and the index is read at entry and written back
+    // at exit, see `LLVMBrainfuckModule::run_on_tape`
+    pub fn with_external_tape(mut self) -> Self {
+        self.external_tape = true;
+        self
+    }
+
+    // `brainfuck` returns -1 instead of running more than `fuel` loop
+    // iterations, which the JIT reports as `OUT_OF_FUEL_ERROR` and `main`
+    // as `OUT_OF_FUEL_STATUS`. Likewise, it returns -2 at the end of the
+    // input, reported as `EOF_ERROR` and `EOF_STATUS`.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // `brainfuck` also takes the `u64* fuel` of the caller, after the tape and
+    // index, and takes the iterations from it, so that the fuel is shared by
+    // the runs of the caller
+    pub fn with_external_fuel(mut self) -> Self {
+        self.external_fuel = true;
+        self
+    }
+
+    // the image is copied into the tape when entering `brainfuck`
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    pub fn with_dump<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.dump = Some(path.into());
+        self
+    }
+
+    // the triple and data layout of the module are set when initializing
+    pub fn with_target(mut self, target: TargetOptions) -> Self {
+        self.target = target;
+        self
+    }
+
+    // emits DWARF debug info locating the atoms at their `spans` in `source`,
+    // as given by `ir::build_ir_with_spans`
+    pub fn with_debug_info<P: Into<PathBuf>>(mut self, source: P, spans: Vec<AtomSpan>) -> Self {
+        self.debug_source = Some((source.into(), spans));
+        self
+    }
+
+    // loops get branch weights and unrolling hints from a profile recorded
+    // on the same IR
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+}
+
+impl LLVMBackend {
+    // takes one iteration from the fuel, or leaves if there is none
+    unsafe fn build_fuel_check(&mut self) {
+        let fuel = llvm::core::LLVMBuildLoad(
+            self.builder,
+            self.fuel_global,
+            b"fuel\0".as_ptr() as *const _
+        );
+        let fuel_ty = llvm::core::LLVMTypeOf(fuel);
+        let empty = llvm::core::LLVMBuildICmp(
+            self.builder,
+            llvm::LLVMIntPredicate::LLVMIntEQ,
+            fuel,
+            llvm::core::LLVMConstNull(fuel_ty),
+            b"no_fuel\0".as_ptr() as *const _
+        );
+        let body_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context,
+            self.brainfuck_fn,
+            b"body\0".as_ptr() as *const _
+        );
+        let out_of_fuel_bb = self.out_of_fuel_bb();
+        llvm::core::LLVMBuildCondBr(self.builder, empty, out_of_fuel_bb, body_bb);
+
+        llvm::core::LLVMPositionBuilderAtEnd(self.builder, body_bb);
+        let fuel = llvm::core::LLVMBuildSub(
+            self.builder,
+            fuel,
+            llvm::core::LLVMConstInt(fuel_ty, 1, 0),
+            b"fuel\0".as_ptr() as *const _
+        );
+        llvm::core::LLVMBuildStore(self.builder, fuel, self.fuel_global);
+    }
+
+    // like the end of the input, running out of fuel goes through the
+    // epilogue, which dumps the tape and writes back the index
+    unsafe fn out_of_fuel_bb(&mut self) -> LLVMBasicBlockRef {
+        if self.out_of_fuel_bb.is_null() {
+            self.out_of_fuel_bb = self.build_early_return(b"out_of_fuel\0", OUT_OF_FUEL_RESULT);
+        }
+        self.out_of_fuel_bb
+    }
+
+    unsafe fn eof_bb(&mut self) -> LLVMBasicBlockRef {
+        if self.eof_bb.is_null() {
+            self.eof_bb = self.build_early_return(b"eof\0", EOF_RESULT);
+        }
+        self.eof_bb
+    }
+
+    // a block storing `result` and jumping to the return block, the builder
+    // stays where it was
+    unsafe fn build_early_return(&self, name: &[u8], result: i32) -> LLVMBasicBlockRef {
+        let current_bb = llvm::core::LLVMGetInsertBlock(self.builder);
+        let bb = llvm::core::LLVMAppendBasicBlockInContext(self.context,
+            self.brainfuck_fn,
+            name.as_ptr() as *const _
+        );
+        llvm::core::LLVMPositionBuilderAtEnd(self.builder, bb);
+        llvm::core::LLVMBuildStore(
+            self.builder,
+            utils::get_int32_const(self.context, result as isize),
+            self.result
+        );
+        llvm::core::LLVMBuildBr(self.builder, self.return_bb);
+        llvm::core::LLVMPositionBuilderAtEnd(self.builder, current_bb);
+        bb
+    }
+
+    unsafe fn index_const(&self, value: isize) -> LLVMValueRef {
+        llvm::core::LLVMConstInt(self.index_ty, value as _, 1)
+    }
+
+    // index of the cell at `offset` from the current one
+    unsafe fn index_at(&self, offset: isize) -> LLVMValueRef {
+        let index = llvm::core::LLVMBuildLoad(
+            self.builder,
+            self.index,
+            b"index\0".as_ptr() as *const _
+        );
+        let offset = if self.wrap {
+            (offset as i128).rem_euclid(self.tape_size as i128) as isize
+        } else {
+            offset
+        };
+        if offset == 0 {
+            return index;
+        }
+
+        let index = llvm::core::LLVMBuildAdd(
+            self.builder,
+            index,
+            self.index_const(offset),
+            b"index\0".as_ptr() as *const _
+        );
+        if !self.wrap {
+            index
+        } else if self.tape_size.is_power_of_two() {
+            llvm::core::LLVMBuildAnd(
+                self.builder,
+                index,
+                self.index_const(self.tape_size as isize - 1),
+                b"index\0".as_ptr() as *const _
+            )
+        } else {
+            // the index and the offset are both lower than the size
+            let size = self.index_const(self.tape_size as isize);
+            let past_end = llvm::core::LLVMBuildICmp(
+                self.builder,
+                llvm::LLVMIntPredicate::LLVMIntUGE,
+                index,
+                size,
+                b"past_end\0".as_ptr() as *const _
+            );
+            let wrapped = llvm::core::LLVMBuildSub(
+                self.builder,
+                index,
+                size,
+                b"wrapped\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildSelect(
+                self.builder,
+                past_end,
+                wrapped,
+                index,
+                b"index\0".as_ptr() as *const _
+            )
+        }
+    }
+
+    unsafe fn cell_ptr_at_index(&self, index: LLVMValueRef) -> LLVMValueRef {
+        llvm::core::LLVMBuildInBoundsGEP(
+            self.builder,
+            self.memory,
+            [self.index_const(0), index].as_mut_ptr(),
+            2,
+            b"cell\0".as_ptr() as *const _
+        )
+    }
+
+    unsafe fn cell_ptr(&self, offset: isize) -> LLVMValueRef {
+        self.cell_ptr_at_index(self.index_at(offset))
+    }
+}
+
+impl Backend for LLVMBackend {
+    type Payload = LLVMBrainfuckModule;
+    type Error = CString;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        macro_rules! add_function {
+            ($module:expr, $name:expr, $ret_ty:expr, $args:expr) => {
+                {
+                    let mut args = $args;
+                    let args_len = args.len();
+                    let fn_ty = llvm::core::LLVMFunctionType(
+                        $ret_ty,
+                        args.as_mut_ptr(),
+                        args_len as _,
+                        0
+                    );
+                    llvm::core::LLVMAddFunction(
+                        $module,
+                        $name.as_ptr() as *const _,
+                        fn_ty
+                    )
+                }
+            };
+            ($module:expr, $name:expr, $ret_ty:expr, []) => {
+                {
+                    let fn_ty = llvm::core::LLVMFunctionType(
+                        $ret_ty,
+                        std::ptr::null_mut(),
+                        0,
+                        0
+                    );
+                    llvm::core::LLVMAddFunction(
+                        $module,
+                        $name.as_ptr() as *const _,
+                        fn_ty
+                    )
+                }
+            }
+        }
+
+        let tape_image = self.image.take().unwrap_or_default();
+        if self.tape_size == 0 || self.tape_size > u32::MAX as usize {
+            return Err(CString::new("invalid tape size").unwrap());
+        }
+        if tape_image.cells.len() > self.tape_size || tape_image.ptr >= self.tape_size {
+            return Err(CString::new("tape image doesn't fit in the tape").unwrap());
+        }
+        if self.external_tape && tape_image != TapeImage::default() {
+            return Err(CString::new("tape images need the tape of the module").unwrap());
+        }
+        if self.external_fuel && (!self.external_tape || self.fuel.is_some()) {
+            let message = "the fuel of the caller needs its tape and no fuel of the module";
+            return Err(CString::new(message).unwrap());
+        }
+        let dump_path = match self.dump {
+            Some(ref path) => path.to_str()
+                .and_then(|path| CString::new(path).ok())
+                .map(Some)
+                .ok_or_else(|| CString::new("invalid dump path").unwrap())?,
+            None => None,
+        };
+
+        unsafe {
+            let target_machine = target::create_target_machine(&self.target, 2)?;
+            target::set_module_target(self.module, target_machine);
+            llvm::target_machine::LLVMDisposeTargetMachine(target_machine);
+
+            let i8_ty = llvm::core::LLVMInt8TypeInContext(self.context);
+            let i32_ty = llvm::core::LLVMInt32TypeInContext(self.context);
+            let i8_ptr_ty = llvm::core::LLVMPointerType(i8_ty, 0);
+
+            let data_layout = llvm::target::LLVMGetModuleDataLayout(self.module);
+            self.index_ty = llvm::target::LLVMIntPtrTypeInContext(self.context, data_layout);
+
+            let host_io = io::build_host_io(self.module);
+            self.putchar_fn = host_io.putchar_fn;
+            self.getchar_fn = host_io.getchar_fn;
+            // `i32 brainfuck(i8* ctx, read, write)`, returns the current cell at exit
+            let read_ptr_ty = llvm::core::LLVMPointerType(io::read_callback_type(self.context), 0);
+            let write_ptr_ty = llvm::core::LLVMPointerType(io::write_callback_type(self.context), 0);
+            self.brainfuck_fn = if self.external_fuel {
+                let index_ptr_ty = llvm::core::LLVMPointerType(self.index_ty, 0);
+                let fuel_ptr_ty = llvm::core::LLVMPointerType(
+                    llvm::core::LLVMInt64TypeInContext(self.context),
+                    0
+                );
+                add_function!(
+                    self.module,
+                    b"brainfuck\0",
+                    i32_ty,
+                    [i8_ptr_ty, read_ptr_ty, write_ptr_ty, i8_ptr_ty, index_ptr_ty, fuel_ptr_ty]
+                )
+            } else if self.external_tape {
+                let index_ptr_ty = llvm::core::LLVMPointerType(self.index_ty, 0);
+                add_function!(
+                    self.module,
+                    b"brainfuck\0",
+                    i32_ty,
+                    [i8_ptr_ty, read_ptr_ty, write_ptr_ty, i8_ptr_ty, index_ptr_ty]
+                )
+            } else {
+                add_function!(
+                    self.module,
+                    b"brainfuck\0",
+                    i32_ty,
+                    [i8_ptr_ty, read_ptr_ty, write_ptr_ty]
+                )
+            };
+
+            let io_functions = io::build_io_functions(
+                self.module,
+                self.getchar_fn,
+                self.putchar_fn,
+                self.io
+            );
+            self.print_fn = io_functions.print_fn;
+            self.read_fn = io_functions.read_fn;
+
+            if let Some(path) = dump_path {
+                self.dump_fn = image::build_dump_fn(self.module, &path, self.tape_size);
+            }
+            if let Some((source, spans)) = self.debug_source.take() {
+                self.debug = Some(debug::DebugInfo::new(self.module, &source, spans)?);
+            }
+
+            let entry_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context, 
+                self.brainfuck_fn,
+                b"entry\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, entry_bb);
+            if let Some(ref debug) = self.debug {
+                debug.set_location(self.builder, 1, 1);
+            }
+
+            if let Some(fuel) = self.fuel {
+                let i64_ty = llvm::core::LLVMInt64TypeInContext(self.context);
+                self.fuel_global = llvm::core::LLVMAddGlobal(
+                    self.module,
+                    i64_ty,
+                    b"bf_fuel\0".as_ptr() as *const _
+                );
+                llvm::core::LLVMSetLinkage(self.fuel_global, llvm::LLVMLinkage::LLVMInternalLinkage);
+                llvm::core::LLVMSetInitializer(self.fuel_global, llvm::core::LLVMConstNull(i64_ty));
+                llvm::core::LLVMBuildStore(
+                    self.builder,
+                    llvm::core::LLVMConstInt(i64_ty, fuel, 0),
+                    self.fuel_global
+                );
+            } else if self.external_fuel {
+                self.fuel_global = llvm::core::LLVMGetParam(self.brainfuck_fn, 5);
+            }
+
+            let tape_ty = llvm::core::LLVMArrayType(i8_ty, self.tape_size as _);
+            if self.external_tape {
+                self.memory = llvm::core::LLVMBuildBitCast(
+                    self.builder,
+                    llvm::core::LLVMGetParam(self.brainfuck_fn, 3),
+                    llvm::core::LLVMPointerType(tape_ty, 0),
+                    b"memory\0".as_ptr() as *const _
+                );
+            } else {
+                self.memory = llvm::core::LLVMAddGlobal(
+                    self.module,
+                    tape_ty,
+                    b"bf_tape\0".as_ptr() as *const _
+                );
+                llvm::core::LLVMSetLinkage(self.memory, llvm::LLVMLinkage::LLVMInternalLinkage);
+                llvm::core::LLVMSetInitializer(self.memory, llvm::core::LLVMConstNull(tape_ty));
+            }
+
+            // the tape is reset so that `brainfuck` can be called again
+            let tape_start = self.cell_ptr_at_index(self.index_const(0));
+            if !self.external_tape {
+                image::build_memset(self.module, self.builder, tape_start, self.tape_size);
+            }
+            if !tape_image.cells.is_empty() {
+                let image_data = image::add_image_global(
+                    self.module,
+                    self.builder,
+                    &tape_image.cells
+                );
+                image::build_memcpy(
+                    self.module,
+                    self.builder,
+                    tape_start,
+                    image_data,
+                    tape_image.cells.len()
+                );
+            }
+
+            self.index = llvm::core::LLVMBuildAlloca(
+                self.builder,
+                self.index_ty,
+                b"index_cell\0".as_ptr() as *const _
+            );
+            let start_index = if self.external_tape {
+                llvm::core::LLVMBuildLoad(
+                    self.builder,
+                    llvm::core::LLVMGetParam(self.brainfuck_fn, 4),
+                    b"start_index\0".as_ptr() as *const _
+                )
+            } else {
+                self.index_const(tape_image.ptr as isize)
+            };
+            llvm::core::LLVMBuildStore(self.builder, start_index, self.index);
+            self.result = llvm::core::LLVMBuildAlloca(
+                self.builder,
+                i32_ty,
+                b"result\0".as_ptr() as *const _
+            );
+            let i1_ty = llvm::core::LLVMInt1TypeInContext(self.context);
+            self.last_cr = llvm::core::LLVMBuildAlloca(
+                self.builder,
+                i1_ty,
+                b"last_cr\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildStore(
+                self.builder,
+                llvm::core::LLVMConstInt(i1_ty, 0, 0),
+                self.last_cr
+            );
+            self.return_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context,
+                self.brainfuck_fn,
+                b"return\0".as_ptr() as *const _
+            );
+
+            if let Some(ref debug) = self.debug {
+                debug.describe_tape(self.module, self.builder, tape_start);
+                debug.declare_index(self.module, self.builder, self.index);
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<Self::Payload, Self::Error> {
+        unsafe {
+            let exit_cell = self.cell_ptr(0);
+            let exit_cell = llvm::core::LLVMBuildLoad(
+                self.builder,
+                exit_cell,
+                b"exit_cell\0".as_ptr() as *const _
+            );
+            let exit_cell = llvm::core::LLVMBuildZExt(
+                self.builder,
+                exit_cell,
+                llvm::core::LLVMInt32TypeInContext(self.context),
+                b"exit_cell\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildStore(self.builder, exit_cell, self.result);
+            llvm::core::LLVMBuildBr(self.builder, self.return_bb);
+
+            let last_bb = llvm::core::LLVMGetLastBasicBlock(self.brainfuck_fn);
+            llvm::core::LLVMMoveBasicBlockAfter(self.return_bb, last_bb);
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, self.return_bb);
+            if !self.dump_fn.is_null() {
+                let tape_start = self.cell_ptr_at_index(self.index_const(0));
+                let ptr_value = self.cell_ptr(0);
+                llvm::core::LLVMBuildCall(
+                    self.builder,
+                    self.dump_fn,
+                    [tape_start, ptr_value].as_mut_ptr(),
+                    2,
+                    b"\0".as_ptr() as *const _
+                );
+            }
+            if self.external_tape {
+                let index = llvm::core::LLVMBuildLoad(
+                    self.builder,
+                    self.index,
+                    b"index\0".as_ptr() as *const _
+                );
+                llvm::core::LLVMBuildStore(
+                    self.builder,
+                    index,
+                    llvm::core::LLVMGetParam(self.brainfuck_fn, 4)
+                );
+            }
+            let result = llvm::core::LLVMBuildLoad(
+                self.builder,
+                self.result,
+                b"result\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildRet(self.builder, result);
+
+            verify_module(self.module)?;
+            if let Some(debug) = self.debug.take() {
+                self.module = debug.finalize(self.module)?;
+                self.brainfuck_fn = llvm::core::LLVMGetNamedFunction(
+                    self.module,
+                    b"brainfuck\0".as_ptr() as *const _
+                );
+                verify_module(self.module)?;
+            }
+
+
+            // the context and the module now belong to the returned module
+            let mut module = LLVMBrainfuckModule::new(
+                self.context,
+                self.module,
+                self.brainfuck_fn,
+                self.target.clone()
+            );
+            if self.external_tape {
+                module.external_tape = Some(self.tape_size);
+            }
+            module.external_fuel = self.external_fuel;
+            self.context = std::ptr::null_mut();
+            self.module = std::ptr::null_mut();
+            Ok(module)
+        }
+    }
+
+    // the debug info follows the atoms to locate them
+    fn push_atoms(&mut self, ir: &Vec<Atom>) -> Result<(), Self::Error> {
+        if let Some(ref mut debug) = self.debug {
+            debug.check_spans(ir);
+        }
+
+        for (index, atom) in ir.iter().enumerate() {
+            if let Some(ref mut debug) = self.debug {
+                unsafe {
+                    debug.enter(self.builder, index);
+                }
+            }
+            self.push_atom(atom)?;
+            if let Some(ref mut debug) = self.debug {
+                debug.leave();
+            }
+        }
+        Ok(())
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let index = self.index_at(offset);
+            llvm::core::LLVMBuildStore(self.builder, index, self.index);
+        }
+        Ok(())
+    }
+
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let real_ptr = self.cell_ptr(offset);
+            llvm::core::LLVMBuildStore(
+                self.builder,
+                utils::get_int8_const(self.context, value as i8),
+                real_ptr
+            );
+        }
+        Ok(())
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let real_ptr = self.cell_ptr(offset);
+
+            let value = llvm::core::LLVMBuildLoad(
+                self.builder,
+                real_ptr,
+                b"value\0".as_ptr() as *const _
+            );
+
+            let value = llvm::core::LLVMBuildAdd(
+                self.builder,
+                value,
+                utils::get_int8_const(self.context, inc as i8),
+                b"value\0".as_ptr() as *const _
+            );
+
+            llvm::core::LLVMBuildStore(self.builder, value, real_ptr);
+        }
+        Ok(())
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let real_ptr = self.cell_ptr(offset);
+            let value = llvm::core::LLVMBuildLoad(
+                self.builder,
+                real_ptr,
+                b"value\0".as_ptr() as *const _
+            );
+
+            let value = llvm::core::LLVMBuildZExt(
+                self.builder,
+                value,
+                llvm::core::LLVMInt32TypeInContext(self.context),
+                b"value\0".as_ptr() as *const _
+            );
+
+            let mut args = io::write_args(self.brainfuck_fn, value);
+            llvm::core::LLVMBuildCall(
+                self.builder,
+                self.print_fn,
+                args.as_mut_ptr(),
+                args.len() as _,
+                b"value\0".as_ptr() as *const _
+            );
+        }
+        Ok(())
+    }
+
+    // negative values are the end of the input
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let mut args = io::read_args(self.brainfuck_fn, self.last_cr);
+            let c = llvm::core::LLVMBuildCall(
+                self.builder,
+                self.read_fn,
+                args.as_mut_ptr(),
+                args.len() as _,
+                b"value\0".as_ptr() as *const _
+            );
+            let is_eof = llvm::core::LLVMBuildICmp(
+                self.builder,
+                llvm::LLVMIntPredicate::LLVMIntSLT,
+                c,
+                utils::get_int32_const(self.context, 0),
+                b"is_eof\0".as_ptr() as *const _
+            );
+            let read_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context,
+                self.brainfuck_fn,
+                b"read\0".as_ptr() as *const _
+            );
+            let eof_bb = self.eof_bb();
+            llvm::core::LLVMBuildCondBr(self.builder, is_eof, eof_bb, read_bb);
+
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, read_bb);
+            let real_ptr = self.cell_ptr(offset);
+            let value = llvm::core::LLVMBuildTrunc(
+                self.builder,
+                c,
+                llvm::core::LLVMInt8TypeInContext(self.context),
+                b"value\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildStore(
+                self.builder,
+                value,
+                real_ptr
+            );
+        }
+        Ok(())
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        unsafe {
+            let base_ptr = self.cell_ptr(0);
+            let offset_ptr = self.cell_ptr(offset);
+
+            let base_value = llvm::core::LLVMBuildLoad(
+                self.builder,
+                base_ptr,
+                b"offset_value\0".as_ptr() as *const _
+            );
+            let base_value = llvm::core::LLVMBuildMul(
+                self.builder,
+                base_value,
+                utils::get_int8_const(self.context, factor as i8),
+                b"factored_value\0".as_ptr() as *const _
+            );
+            let offset_value = llvm::core::LLVMBuildLoad(
+                self.builder,
+                offset_ptr,
+                b"base_value\0".as_ptr() as *const _
+            );
+            let value = llvm::core::LLVMBuildAdd(
+                self.builder,
+                base_value,
+                offset_value,
+                b"value\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildStore(
+                self.builder,
+                value,
+                offset_ptr
+            );
+        }
+        Ok(())
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        unsafe {
+            let loop_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context, 
+                self.brainfuck_fn,
+                b"loop\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMBuildBr(self.builder, loop_bb);
+            let then_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context, 
+                self.brainfuck_fn,
+                b"then\0".as_ptr() as *const _
+            );
+            let exit_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context, 
+                self.brainfuck_fn,
+                b"exit\0".as_ptr() as *const _
+            );
+
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, loop_bb);
+
+            let ptr = self.cell_ptr(0);
+            let value = llvm::core::LLVMBuildLoad(
+                self.builder,
+                ptr,
+                b"value\0".as_ptr() as *const _
+            );
+            let cond = llvm::core::LLVMBuildIsNotNull(
+                self.builder,
+                value,
+                b"cond\0".as_ptr() as *const _
+            );
+            let cond_br = llvm::core::LLVMBuildCondBr(
+                self.builder,
+                cond,
+                then_bb,
+                exit_bb
+            );
+
+            let counts = self.profile.as_ref().and_then(|profile| profile.get(self.loop_id));
+            self.loop_id += 1;
+
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, then_bb);
+            if !self.fuel_global.is_null() {
+                self.build_fuel_check();
+            }
+            self.push_atoms(sub)?;
+            let latch_br = llvm::core::LLVMBuildBr(self.builder, loop_bb);
+
+            if let Some(counts) = counts {
+                profile::set_branch_weights(cond_br, counts);
+                profile::set_loop_metadata(latch_br, counts);
+            }
+
+            let last_bb = llvm::core::LLVMGetLastBasicBlock(self.brainfuck_fn);
+            llvm::core::LLVMMoveBasicBlockAfter(exit_bb, last_bb);
+            llvm::core::LLVMPositionBuilderAtEnd(self.builder, exit_bb);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LLVMBackend {
+    fn drop(&mut self) {
+        unsafe {
+            llvm::core::LLVMDisposeBuilder(self.builder);
+            if !self.context.is_null() {
+                llvm::core::LLVMDisposeModule(self.module);
+                llvm::core::LLVMContextDispose(self.context);
+            }
+        }
+    }
+}
+
+// The context is only used through `&mut self`.
+unsafe impl Send for LLVMBackend {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Object,
+    Assembly,
+    Bitcode,
+    LlvmIr,
+}
+
+#[derive(Debug)]
+pub struct LLVMBrainfuckModule {
+    context: LLVMContextRef,
+    // created on the first JIT run, it then owns the module
+    exec_engine: LLVMExecutionEngineRef,
+    module: LLVMModuleRef,
+    brainfuck_fn: LLVMValueRef,
+    // JIT'd code of `brainfuck`, once resolved
+    brainfuck_address: usize,
+    // size of the tape given by the caller, see `LLVMBackend::with_external_tape`
+    external_tape: Option<usize>,
+    // see `LLVMBackend::with_external_fuel`
+    external_fuel: bool,
+    target: TargetOptions,
+    codegen_level: u32,
+}
+
+impl LLVMBrainfuckModule {
+    fn new(context: LLVMContextRef, module: LLVMModuleRef, brainfuck_fn: LLVMValueRef,
+           target: TargetOptions) -> LLVMBrainfuckModule {
+        LLVMBrainfuckModule {
+            context,
+            exec_engine: std::ptr::null_mut(),
+            module,
+            brainfuck_fn,
+            brainfuck_address: 0,
+            external_tape: None,
+            external_fuel: false,
+            target,
+            codegen_level: 3,
+        }
+    }
+
+    // the CPU and features of the target are ignored by the JIT
+    fn create_exec_engine(&mut self) -> Result<(), CString> {
+        use llvm::execution_engine::LLVMMCJITCompilerOptions;
+
+        if !self.target.is_host() {
+            return Err(CString::new("can't JIT code for a foreign target").unwrap());
+        }
+
+        unsafe {
+            let mut error: *mut c_char = std::ptr::null_mut();
+            let mut options: LLVMMCJITCompilerOptions = std::mem::zeroed();
+            let options_size = 
+                std::mem::size_of::<LLVMMCJITCompilerOptions>();
+            llvm::execution_engine::LLVMInitializeMCJITCompilerOptions(
+                &mut options,
+                options_size
+            );
+
+            options.OptLevel = self.codegen_level;
+
+            target::initialize_native();
+            if llvm::execution_engine::LLVMCreateMCJITCompilerForModule(
+                &mut self.exec_engine,
+                self.module,
+                &mut options,
+                options_size,
+                &mut error
+                ) != 0 {
+                return Err(CString::from_raw(error));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn optimize(&mut self) {
+        self.optimize_level(OptLevel::O1);
+    }
+
+    pub fn optimize_level(&mut self, level: OptLevel) -> Vec<PassTiming> {
+        self.run_passes(level.passes()).unwrap()
+    }
+
+    // each pass runs in its own pass manager so that it can be timed
+    pub fn run_passes<S: AsRef<str>>(&mut self, passes: &[S]) -> Result<Vec<PassTiming>, CString> {
+        unsafe {
+            let pass_manager = llvm::core::LLVMCreatePassManager();
+            let unknown = passes.iter().find(|pass| !passes::add_pass(pass_manager, pass.as_ref()));
+            llvm::core::LLVMDisposePassManager(pass_manager);
+            if let Some(pass) = unknown {
+                return Err(CString::new(format!("unknown pass `{}`", pass.as_ref())).unwrap());
+            }
+        }
+
+        let mut timings = Vec::new();
+        for pass in passes {
+            let pass = pass.as_ref();
+            unsafe {
+                let pass_manager = llvm::core::LLVMCreatePassManager();
+                passes::add_pass(pass_manager, pass);
+
+                let start = Instant::now();
+                llvm::core::LLVMRunPassManager(pass_manager, self.module);
+                timings.push(PassTiming {
+                    pass: pass.to_owned(),
+                    duration: start.elapsed(),
+                });
+                llvm::core::LLVMDisposePassManager(pass_manager);
+            }
+        }
+        Ok(timings)
+    }
+
+    // from 0 (none) to 3 (aggressive), used by the JIT and the target machine
+    pub fn set_codegen_level(&mut self, level: u32) {
+        self.codegen_level = level;
+    }
+
+    // runs on the standard streams, returns the current cell at exit
+    pub fn jit_run(&mut self) -> Result<u8, CString> {
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        self.run_with(stdin.lock(), stdout.lock())
+    }
+
+    // generates the native code ahead of the first run
+    pub fn compile(&mut self) -> Result<(), CString> {
+        if self.exec_engine.is_null() {
+            self.create_exec_engine()?;
+        }
+        if self.brainfuck_address == 0 {
+            self.brainfuck_address = unsafe {
+                llvm::execution_engine::LLVMGetFunctionAddress(
+                    self.exec_engine,
+                    b"brainfuck\0".as_ptr() as *const _
+                ) as usize
+            };
+        }
+        // the address is 0 when the code couldn't be generated
+        if self.brainfuck_address == 0 {
+            return Err(CString::new("could not generate the code of `brainfuck`").unwrap());
+        }
+        Ok(())
+    }
+
+    pub fn run_with<R: Read, W: Write>(&mut self, mut reader: R, mut writer: W)
+        -> Result<u8, CString> {
+        if self.external_tape.is_some() {
+            return Err(CString::new("the module runs on the tape of the caller").unwrap());
+        }
+        self.compile()?;
+        let brainfuck_fn: host::BrainfuckFn = unsafe {
+            std::mem::transmute(self.brainfuck_address)
+        };
+
+        let mut ctx = host::HostContext::new(&mut reader, &mut writer);
+        let exit_cell = brainfuck_fn(
+            &mut ctx as *mut host::HostContext as *mut _,
+            host::host_read,
+            host::host_write
+        );
+        let result = run_result(&mut ctx, exit_cell);
+        writer.flush().map_err(|err| CString::new(err.to_string()).unwrap())?;
+        result
+    }
+
+    // runs a module built `with_external_tape` on `tape`, starting at and
+    // updating `index`, and `fuel` if it was built `with_external_fuel`, the
+    // writer isn't flushed
+    pub fn run_on_tape<R: Read, W: Write>(&mut self, mut reader: R, mut writer: W,
+                                          tape: &mut [u8], index: &mut usize,
+                                          fuel: Option<&mut u64>)
+        -> Result<u8, CString> {
+        if self.external_tape != Some(tape.len()) || *index >= tape.len() {
+            return Err(CString::new("the tape doesn't fit the module").unwrap());
+        }
+        if self.external_fuel != fuel.is_some() {
+            return Err(CString::new("the fuel doesn't fit the module").unwrap());
+        }
+        self.compile()?;
+
+        let mut ctx = host::HostContext::new(&mut reader, &mut writer);
+        let ctx_ptr = &mut ctx as *mut host::HostContext as *mut _;
+        let exit_cell = match fuel {
+            Some(fuel) => {
+                let brainfuck_fn: host::FueledTapeFn = unsafe {
+                    std::mem::transmute(self.brainfuck_address)
+                };
+                brainfuck_fn(ctx_ptr, host::host_read, host::host_write, tape.as_mut_ptr(),
+                             index, fuel)
+            },
+            None => {
+                let brainfuck_fn: host::TapeFn = unsafe {
+                    std::mem::transmute(self.brainfuck_address)
+                };
+                brainfuck_fn(ctx_ptr, host::host_read, host::host_write, tape.as_mut_ptr(), index)
+            },
+        };
+        run_result(&mut ctx, exit_cell)
+    }
+
+    // entry point for standalone executables, exiting with 0 or the
+    // current cell, the module must have its own tape
+    pub fn add_main(&mut self, exit_cell: bool) {
+        unsafe {
+            let i32_ty = llvm::core::LLVMInt32TypeInContext(self.context);
+            let main_fn = llvm::core::LLVMAddFunction(
+                self.module,
+                b"main\0".as_ptr() as *const _,
+                llvm::core::LLVMFunctionType(i32_ty, std::ptr::null_mut(), 0, 0)
+            );
+            let builder = llvm::core::LLVMCreateBuilderInContext(self.context);
+            let entry_bb = llvm::core::LLVMAppendBasicBlockInContext(self.context, 
+                main_fn,
+                b"entry\0".as_ptr() as *const _
+            );
+            llvm::core::LLVMPositionBuilderAtEnd(builder, entry_bb);
+
+            let (read_fn, write_fn) = io::build_libc_callbacks(self.module);
+            let ctx = llvm::core::LLVMConstNull(
+                llvm::core::LLVMPointerType(llvm::core::LLVMInt8TypeInContext(self.context), 0)
+            );
+            let cell = llvm::core::LLVMBuildCall(
+                builder,
+                self.brainfuck_fn,
+                [ctx, read_fn, write_fn].as_mut_ptr(),
+                3,
+                b"cell\0".as_ptr() as *const _
+            );
+            let status = if exit_cell {
+                cell
+            } else {
+                utils::get_int32_const(self.context, 0)
+            };
+            let mut status = status;
+            for &(result, result_status) in &[(OUT_OF_FUEL_RESULT, OUT_OF_FUEL_STATUS),
+                                               (EOF_RESULT, EOF_STATUS)] {
+                let stopped = llvm::core::LLVMBuildICmp(
+                    builder,
+                    llvm::LLVMIntPredicate::LLVMIntEQ,
+                    cell,
+                    utils::get_int32_const(self.context, result as isize),
+                    b"stopped\0".as_ptr() as *const _
+                );
+                status = llvm::core::LLVMBuildSelect(
+                    builder,
+                    stopped,
+                    utils::get_int32_const(self.context, result_status as isize),
+                    status,
+                    b"status\0".as_ptr() as *const _
+                );
+            }
+            llvm::core::LLVMBuildRet(builder, status);
+            llvm::core::LLVMDisposeBuilder(builder);
+        }
+    }
+
+    pub fn write_output<P: AsRef<Path>>(&mut self, path: P, format: OutputFormat)
+        -> Result<(), CString> {
+        let path = path.as_ref().to_str()
+            .and_then(|path| CString::new(path).ok())
+            .ok_or_else(|| CString::new("invalid output path").unwrap())?;
+
+        unsafe {
+            let target_machine = target::create_target_machine(&self.target, self.codegen_level)?;
+
+            let mut error: *mut c_char = std::ptr::null_mut();
+            let failed = match format {
+                OutputFormat::Object | OutputFormat::Assembly => {
+                    let file_type = if format == OutputFormat::Object {
+                        LLVMCodeGenFileType::LLVMObjectFile
+                    } else {
+                        LLVMCodeGenFileType::LLVMAssemblyFile
+                    };
+                    llvm::target_machine::LLVMTargetMachineEmitToFile(
+                        target_machine,
+                        self.module,
+                        path.as_ptr() as *mut _,
+                        file_type,
+                        &mut error
+                    ) != 0
+                },
+                OutputFormat::Bitcode => {
+                    if llvm::bit_writer::LLVMWriteBitcodeToFile(self.module, path.as_ptr()) != 0 {
+                        error = CString::new("could not write bitcode").unwrap().into_raw();
+                    }
+                    !error.is_null()
+                },
+                OutputFormat::LlvmIr => {
+                    llvm::core::LLVMPrintModuleToFile(self.module, path.as_ptr(), &mut error) != 0
+                },
+            };
+            llvm::target_machine::LLVMDisposeTargetMachine(target_machine);
+
+            if failed {
+                return Err(CString::from_raw(error));
+            }
+        }
+        Ok(())
+    }
+}
+
+unsafe fn verify_module(module: LLVMModuleRef) -> Result<(), CString> {
+    let mut error: *mut c_char = std::ptr::null_mut();
+    if llvm::analysis::LLVMVerifyModule(
+        module,
+        llvm::analysis::LLVMVerifierFailureAction::LLVMReturnStatusAction,
+        &mut error
+        ) != 0 {
+        return Err(CString::from_raw(error))
+    }
+    Ok(())
+}
+
+// the first I/O error comes first, then the early returns of `brainfuck`
+fn run_result(ctx: &mut host::HostContext, result: i32) -> Result<u8, CString> {
+    if let Some(err) = ctx.error.take() {
+        return Err(CString::new(err.to_string()).unwrap());
+    }
+    match result {
+        OUT_OF_FUEL_RESULT => Err(CString::new(OUT_OF_FUEL_ERROR).unwrap()),
+        EOF_RESULT => Err(CString::new(EOF_ERROR).unwrap()),
+        cell => Ok(cell as u8),
+    }
+}
+
+impl Drop for LLVMBrainfuckModule {
+    fn drop(&mut self) {
+        unsafe {
+            if self.exec_engine.is_null() {
+                llvm::core::LLVMDisposeModule(self.module);
+            } else {
+                llvm::execution_engine::LLVMDisposeExecutionEngine(self.exec_engine);
+            }
+            llvm::core::LLVMContextDispose(self.context);
+        }
+    }
+}
+
+unsafe impl Send for LLVMBrainfuckModule {}
+
+mod utils {
+    use llvm;
+    use llvm::prelude::{LLVMContextRef, LLVMValueRef};
+
+    pub unsafe fn get_int8_const(context: LLVMContextRef, c: i8) -> LLVMValueRef {
+        llvm::core::LLVMConstInt(
+            llvm::core::LLVMInt8TypeInContext(context),
+            c as _,
+            false as _
+        )
+    }
+
+    pub unsafe fn get_int32_const(context: LLVMContextRef, c: isize) -> LLVMValueRef {
+        llvm::core::LLVMConstInt(
+            llvm::core::LLVMInt32TypeInContext(context),
+            c as _,
+            false as _
+        )
+    }
+}
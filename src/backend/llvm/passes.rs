@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use llvm::prelude::*;
+use llvm::transforms::{ipo, scalar, vectorize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    Size,
+}
+
+// the pipeline used before the levels existed
+const O1_PASSES: &[&str] = &["constprop", "instcombine", "mem2reg", "gvn", "simplifycfg"];
+
+const O2_PASSES: &[&str] = &[
+    "globalopt", "inline", "mem2reg", "sroa", "early-cse", "instcombine", "simplifycfg",
+    "reassociate", "sccp", "correlated-propagation", "jump-threading", "gvn", "licm",
+    "loop-deletion", "indvars", "loop-idiom", "memcpyopt", "dse", "adce", "instcombine",
+    "simplifycfg", "globaldce",
+];
+
+const O3_PASSES: &[&str] = &[
+    "globalopt", "inline", "mem2reg", "sroa", "early-cse", "instcombine", "simplifycfg",
+    "reassociate", "sccp", "correlated-propagation", "jump-threading", "gvn", "loop-rotate",
+    "licm", "loop-deletion", "indvars", "loop-idiom", "loop-unroll", "memcpyopt", "dse",
+    "adce", "loop-vectorize", "slp-vectorizer", "instcombine", "simplifycfg", "globaldce",
+];
+
+// O2 without inlining, which mostly grows code
+const SIZE_PASSES: &[&str] = &[
+    "globalopt", "mem2reg", "sroa", "early-cse", "instcombine", "simplifycfg", "reassociate",
+    "sccp", "correlated-propagation", "jump-threading", "gvn", "licm", "loop-deletion",
+    "indvars", "loop-idiom", "memcpyopt", "dse", "adce", "instcombine", "simplifycfg",
+    "globaldce",
+];
+
+impl OptLevel {
+    pub fn passes(self) -> &'static [&'static str] {
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 => O1_PASSES,
+            OptLevel::O2 => O2_PASSES,
+            OptLevel::O3 => O3_PASSES,
+            OptLevel::Size => SIZE_PASSES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassTiming {
+    pub pass: String,
+    pub duration: Duration,
+}
+
+// returns false for unknown passes, names are the ones of `opt`
+pub unsafe fn add_pass(pass_manager: LLVMPassManagerRef, name: &str) -> bool {
+    let add: unsafe extern "C" fn(LLVMPassManagerRef) = match name {
+        "adce" => scalar::LLVMAddAggressiveDCEPass,
+        "constprop" => scalar::LLVMAddConstantPropagationPass,
+        "correlated-propagation" => scalar::LLVMAddCorrelatedValuePropagationPass,
+        "dse" => scalar::LLVMAddDeadStoreEliminationPass,
+        "early-cse" => scalar::LLVMAddEarlyCSEPass,
+        "globaldce" => ipo::LLVMAddGlobalDCEPass,
+        "globalopt" => ipo::LLVMAddGlobalOptimizerPass,
+        "gvn" => scalar::LLVMAddGVNPass,
+        "indvars" => scalar::LLVMAddIndVarSimplifyPass,
+        "inline" => ipo::LLVMAddFunctionInliningPass,
+        "instcombine" => scalar::LLVMAddInstructionCombiningPass,
+        "jump-threading" => scalar::LLVMAddJumpThreadingPass,
+        "licm" => scalar::LLVMAddLICMPass,
+        "loop-deletion" => scalar::LLVMAddLoopDeletionPass,
+        "loop-idiom" => scalar::LLVMAddLoopIdiomPass,
+        "loop-rotate" => scalar::LLVMAddLoopRotatePass,
+        "loop-unroll" => scalar::LLVMAddLoopUnrollPass,
+        "loop-vectorize" => vectorize::LLVMAddLoopVectorizePass,
+        "mem2reg" => scalar::LLVMAddPromoteMemoryToRegisterPass,
+        "memcpyopt" => scalar::LLVMAddMemCpyOptPass,
+        "reassociate" => scalar::LLVMAddReassociatePass,
+        "sccp" => scalar::LLVMAddSCCPPass,
+        "simplifycfg" => scalar::LLVMAddCFGSimplificationPass,
+        "slp-vectorizer" => vectorize::LLVMAddSLPVectorizePass,
+        "sroa" => scalar::LLVMAddScalarReplAggregatesPass,
+        "tailcallelim" => scalar::LLVMAddTailCallEliminationPass,
+        _ => return false,
+    };
+    add(pass_manager);
+    true
+}
@@ -0,0 +1,76 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+
+use llvm;
+use llvm::prelude::*;
+
+use backend::profile::LoopCounts;
+use super::utils;
+
+unsafe fn md_kind_id(ctx: LLVMContextRef, kind: &str) -> u32 {
+    llvm::core::LLVMGetMDKindIDInContext(ctx, kind.as_ptr() as *const c_char, kind.len() as _)
+}
+
+unsafe fn md_string(ctx: LLVMContextRef, s: &str) -> LLVMValueRef {
+    llvm::core::LLVMMDStringInContext(ctx, s.as_ptr() as *const c_char, s.len() as _)
+}
+
+// `!prof` of the branch checking the loop condition, `then` being the body
+pub unsafe fn set_branch_weights(cond_br: LLVMValueRef, counts: LoopCounts) {
+    let ctx = llvm::core::LLVMGetTypeContext(llvm::core::LLVMTypeOf(cond_br));
+    // weights are 32 bits, only their ratio matters
+    let scale = (counts.entries.max(counts.iterations) >> 32) + 1;
+    let mut operands = vec![
+        md_string(ctx, "branch_weights"),
+        utils::get_int32_const(ctx, (counts.iterations / scale) as isize),
+        utils::get_int32_const(ctx, (counts.entries / scale) as isize),
+    ];
+    let node = llvm::core::LLVMMDNodeInContext(ctx, operands.as_mut_ptr(), operands.len() as _);
+    llvm::core::LLVMSetMetadata(cond_br, md_kind_id(ctx, "prof"), node);
+}
+
+// The first operand of a loop ID is the ID itself, a cycle which the C API of
+// LLVM 5 can't build from nodes. The ID is parsed instead, in a module of the
+// same context, and taken from the branch it's attached to: metadata belongs
+// to the context, so it outlives the module.
+unsafe fn parse_loop_id(ctx: LLVMContextRef, property: &str) -> LLVMValueRef {
+    let ir = format!(
+        "define void @loop() {{\n\
+         entry:\n  br label %entry, !llvm.loop !0\n}}\n\
+         !0 = distinct !{{!0, !1}}\n!1 = !{{{}}}\n",
+        property
+    );
+    // the buffer is owned by the parser
+    let buffer = llvm::core::LLVMCreateMemoryBufferWithMemoryRangeCopy(
+        ir.as_ptr() as *const c_char,
+        ir.len(),
+        b"loop_id\0".as_ptr() as *const _
+    );
+    let mut module = ptr::null_mut();
+    let mut error = ptr::null_mut();
+    if llvm::ir_reader::LLVMParseIRInContext(ctx, buffer, &mut module, &mut error) != 0 {
+        let message = CStr::from_ptr(error).to_string_lossy().into_owned();
+        llvm::core::LLVMDisposeMessage(error);
+        panic!("invalid loop ID `{}`: {}", property, message);
+    }
+
+    let function = llvm::core::LLVMGetFirstFunction(module);
+    let branch = llvm::core::LLVMGetFirstInstruction(llvm::core::LLVMGetEntryBasicBlock(function));
+    let loop_id = llvm::core::LLVMGetMetadata(branch, md_kind_id(ctx, "llvm.loop"));
+    llvm::core::LLVMDisposeModule(module);
+    loop_id
+}
+
+// `!llvm.loop` of the back edge, loops are only unrolled as much as
+// `LoopCounts::unroll_count` says
+pub unsafe fn set_loop_metadata(latch_br: LLVMValueRef, counts: LoopCounts) {
+    let ctx = llvm::core::LLVMGetTypeContext(llvm::core::LLVMTypeOf(latch_br));
+    let property = if counts.unroll_count() > 1 {
+        format!("!\"llvm.loop.unroll.count\", i32 {}", counts.unroll_count())
+    } else {
+        String::from("!\"llvm.loop.unroll.disable\"")
+    };
+    let loop_id = parse_loop_id(ctx, &property);
+    llvm::core::LLVMSetMetadata(latch_br, md_kind_id(ctx, "llvm.loop"), loop_id);
+}
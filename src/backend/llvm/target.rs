@@ -0,0 +1,108 @@
+use std;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::sync::Once;
+
+use llvm;
+use llvm::prelude::*;
+use llvm::target_machine::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TargetOptions {
+    // the host when unset
+    pub triple: Option<String>,
+    pub cpu: String,
+    pub features: String,
+}
+
+impl TargetOptions {
+    // the JIT can only run code for the host
+    pub fn is_host(&self) -> bool {
+        match self.triple {
+            Some(ref triple) => unsafe {
+                let host = LLVMGetDefaultTargetTriple();
+                let is_host = CStr::from_ptr(host).to_bytes() == triple.as_bytes();
+                llvm::core::LLVMDisposeMessage(host);
+                is_host
+            },
+            None => true,
+        }
+    }
+}
+
+static NATIVE_INIT: Once = Once::new();
+static ALL_INIT: Once = Once::new();
+
+// LLVM's registries are global, initialize them once for all threads
+pub fn initialize_native() {
+    NATIVE_INIT.call_once(|| unsafe {
+        llvm::target::LLVM_InitializeNativeTarget();
+        llvm::target::LLVM_InitializeNativeAsmPrinter();
+        llvm::target::LLVM_InitializeNativeAsmParser();
+        llvm::execution_engine::LLVMLinkInMCJIT();
+    });
+}
+
+fn initialize_all() {
+    ALL_INIT.call_once(|| unsafe {
+        llvm::target::LLVM_InitializeAllTargetInfos();
+        llvm::target::LLVM_InitializeAllTargets();
+        llvm::target::LLVM_InitializeAllTargetMCs();
+        llvm::target::LLVM_InitializeAllAsmPrinters();
+    });
+}
+
+pub unsafe fn create_target_machine(options: &TargetOptions, codegen_level: u32)
+    -> Result<LLVMTargetMachineRef, CString> {
+    let triple = match options.triple {
+        Some(ref triple) => {
+            initialize_all();
+            CString::new(triple.as_str()).map_err(|_| CString::new("invalid triple").unwrap())?
+        },
+        None => {
+            initialize_native();
+            let host = LLVMGetDefaultTargetTriple();
+            let triple = CStr::from_ptr(host).to_owned();
+            llvm::core::LLVMDisposeMessage(host);
+            triple
+        },
+    };
+    let cpu = CString::new(options.cpu.as_str())
+        .map_err(|_| CString::new("invalid CPU").unwrap())?;
+    let features = CString::new(options.features.as_str())
+        .map_err(|_| CString::new("invalid features").unwrap())?;
+
+    let mut target: LLVMTargetRef = std::ptr::null_mut();
+    let mut error: *mut c_char = std::ptr::null_mut();
+    if LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut error) != 0 {
+        return Err(CString::from_raw(error));
+    }
+
+    let codegen_level = match codegen_level {
+        0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+        1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+        2 => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+        _ => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+    };
+
+    // position independent code, so that the object can be linked into a PIE
+    Ok(LLVMCreateTargetMachine(
+        target,
+        triple.as_ptr(),
+        cpu.as_ptr(),
+        features.as_ptr(),
+        codegen_level,
+        LLVMRelocMode::LLVMRelocPIC,
+        LLVMCodeModel::LLVMCodeModelDefault
+    ))
+}
+
+pub unsafe fn set_module_target(module: LLVMModuleRef, target_machine: LLVMTargetMachineRef) {
+    let triple = LLVMGetTargetMachineTriple(target_machine);
+    llvm::core::LLVMSetTarget(module, triple);
+    llvm::core::LLVMDisposeMessage(triple);
+
+    let data_layout = LLVMCreateTargetDataLayout(target_machine);
+    llvm::target::LLVMSetModuleDataLayout(module, data_layout);
+    llvm::target::LLVMDisposeTargetData(data_layout);
+}
@@ -1,11 +1,104 @@
-use ir::Atom;
+use ir::{Atom, Value};
 
 pub mod c;
+pub mod image;
 pub mod interpreter;
+pub mod js;
+pub mod jvm;
 pub mod llvm;
-pub use self::c::CBackend;
+pub mod profile;
+pub mod python;
+pub mod rust;
+pub mod wasm;
+pub use self::c::{CBackend, CBackendOptions, CCellType, CStandard};
+pub use self::image::{TapeImage, ImageFormat};
 pub use self::interpreter::Interpreter;
+pub use self::js::JsBackend;
+pub use self::jvm::JvmBackend;
 pub use self::llvm::LLVMBackend;
+pub use self::profile::{Profile, LoopCounts};
+pub use self::python::PythonBackend;
+pub use self::rust::RustBackend;
+pub use self::wasm::{WasmBackend, WasmIo, WasmModule};
+
+// Exit status of every backend once its fuel, the number of loop iterations
+// it may run, is exhausted. It is the status of a process killed by SIGXCPU.
+pub const OUT_OF_FUEL_STATUS: i32 = 152;
+// Exit status of every backend reading past the end of the input, EX_NOINPUT.
+pub const EOF_STATUS: i32 = 66;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoEncoding {
+    Bytes,
+    // UTF-8 encoded code points, invalid ones are replaced by U+FFFD
+    Unicode,
+    // whitespace separated integers, printed one per line
+    Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoOptions {
+    pub encoding: IoEncoding,
+    // read "\r\n" and lone '\r' as '\n'
+    pub translate_newlines: bool,
+}
+
+impl Default for IoOptions {
+    fn default() -> Self {
+        IoOptions {
+            encoding: IoEncoding::Bytes,
+            translate_newlines: false,
+        }
+    }
+}
+
+// runs `program` on `input`, or returns `None` when it isn't installed
+#[cfg(test)]
+pub fn run_tool(program: &str, args: &[&::std::ffi::OsStr], input: &[u8])
+    -> Option<::std::process::Output> {
+    use std::io::{self, Write};
+    use std::process::{Command, Stdio};
+
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => panic!("could not run {}: {}", program, err),
+    };
+    // the program may exit before reading all of it
+    let _ = child.stdin.take().unwrap().write_all(input);
+    Some(child.wait_with_output().unwrap())
+}
+
+#[cfg(test)]
+type ProgramRun = (&'static [u8], Option<u64>, &'static [u8], &'static [u8], i32);
+
+// `run` builds the program with the fuel, runs it on the input and returns
+// None if its tools are missing, the statuses are the ones of the interpreter
+#[cfg(test)]
+pub fn check_programs<F>(mut run: F)
+    where F: FnMut(&Vec<Atom>, Option<u64>, &[u8]) -> Option<::std::process::Output> {
+    // program, fuel, input, output and exit status
+    let programs: [ProgramRun; 3] = [
+        (b"++++++++[>++++++++<-]>+.", None, b"", b"A", 0),
+        (b",[.,]", None, b"ab", b"ab", EOF_STATUS),
+        (b"+.[]", Some(5), b"", &[1], OUT_OF_FUEL_STATUS),
+    ];
+    for &(program, fuel, input, expected, status) in &programs {
+        let ir = ::ir::build_ir(program).unwrap();
+        let output = match run(&ir, fuel, input) {
+            Some(output) => output,
+            None => return,
+        };
+        assert_eq!(output.stdout, expected, "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(output.status.code(), Some(status));
+    }
+}
 
 pub fn use_backend<B: Backend>(mut backend: B, ir: &Vec<Atom>)
     -> Result<B::Payload, B::Error> {
@@ -40,10 +133,10 @@ pub trait Backend {
     }
 
     fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error>;
-    fn push_set_value(&mut self, value: i8, offset: isize) -> Result<(), Self::Error>;
-    fn push_inc_value(&mut self, inc: i8, offset: isize) -> Result<(), Self::Error>;
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error>;
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error>;
     fn push_print(&mut self, offset: isize) -> Result<(), Self::Error>;
     fn push_read(&mut self, offset: isize) -> Result<(), Self::Error>;
-    fn push_multiply(&mut self, factor: i8, offset: isize) -> Result<(), Self::Error>;
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error>;
     fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error>;
 }
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write, BufWriter};
+use std::path::Path;
+
+use ir::Atom;
+
+// loops iterating less than this over a run aren't worth unrolling
+pub const HOT_ITERATIONS: u64 = 1000;
+// largest unroll count given to hot loops
+pub const MAX_UNROLL: u64 = 8;
+
+const HEADER: &str = "bfc-profile 1";
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// The condition of a loop is checked `entries + iterations` times, it is
+// true `iterations` times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoopCounts {
+    pub entries: u64,
+    pub iterations: u64,
+}
+
+impl LoopCounts {
+    pub fn is_hot(&self) -> bool {
+        self.iterations >= HOT_ITERATIONS
+    }
+
+    // the iterations per entry of hot loops, rounded down to a power of two,
+    // and 1 for the others
+    pub fn unroll_count(&self) -> u64 {
+        if !self.is_hot() || self.entries == 0 {
+            return 1;
+        }
+        let average = (self.iterations / self.entries).clamp(1, MAX_UNROLL);
+        1 << (63 - average.leading_zeros())
+    }
+}
+
+// Loop counts of a run, the loops are numbered in the order their `[`
+// appears, nested loops coming after their parent. The profile only applies
+// to the IR it was recorded on, which is identified by `hash_ir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub ir_hash: u64,
+    pub loops: Vec<LoopCounts>,
+}
+
+impl Profile {
+    pub fn new(ir: &[Atom]) -> Self {
+        Profile {
+            ir_hash: hash_ir(ir),
+            loops: vec![LoopCounts::default(); count_loops(ir)],
+        }
+    }
+
+    pub fn matches(&self, ir: &[Atom]) -> bool {
+        self.ir_hash == hash_ir(ir) && self.loops.len() == count_loops(ir)
+    }
+
+    pub fn get(&self, id: usize) -> Option<LoopCounts> {
+        self.loops.get(id).cloned()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Profile::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        writeln!(writer, "ir {:016x}", self.ir_hash)?;
+        for (id, counts) in self.loops.iter().enumerate() {
+            writeln!(writer, "loop {} {} {}", id, counts.entries, counts.iterations)?;
+        }
+        writer.flush()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim() == HEADER => (),
+            _ => return Err("not a bfc profile".to_owned()),
+        }
+
+        let mut profile = Profile { ir_hash: 0, loops: Vec::new() };
+        let mut has_hash = false;
+        for (line_number, line) in lines {
+            let invalid = || format!("invalid profile line {}", line_number + 1);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => (),
+                ["ir", hash] => {
+                    profile.ir_hash = u64::from_str_radix(hash, 16).map_err(|_| invalid())?;
+                    has_hash = true;
+                },
+                ["loop", id, entries, iterations] => {
+                    if id.parse::<usize>().ok() != Some(profile.loops.len()) {
+                        return Err(invalid());
+                    }
+                    profile.loops.push(LoopCounts {
+                        entries: entries.parse().map_err(|_| invalid())?,
+                        iterations: iterations.parse().map_err(|_| invalid())?,
+                    });
+                },
+                _ => return Err(invalid()),
+            }
+        }
+
+        if !has_hash {
+            return Err("missing IR hash in profile".to_owned());
+        }
+        Ok(profile)
+    }
+}
+
+// Records a profile while interpreting, loops are recognized by the address
+// of their body so the IR must not move during the run.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    profile: Option<Profile>,
+    ids: HashMap<usize, usize>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            profile: None,
+            ids: HashMap::new(),
+        }
+    }
+
+    // numbers the loops the first time the top level is pushed
+    pub fn start(&mut self, ir: &[Atom]) {
+        if self.profile.is_none() {
+            number_loops(ir, &mut self.ids);
+            self.profile = Some(Profile::new(ir));
+        }
+    }
+
+    pub fn record(&mut self, sub: &Vec<Atom>, iterations: u64) {
+        let id = self.ids.get(&(sub as *const _ as usize));
+        if let (Some(&id), Some(ref mut profile)) = (id, self.profile.as_mut()) {
+            let counts = &mut profile.loops[id];
+            counts.entries += 1;
+            counts.iterations += iterations;
+        }
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+fn number_loops(ir: &[Atom], ids: &mut HashMap<usize, usize>) {
+    for atom in ir {
+        if let Atom::Loop(ref sub) = *atom {
+            let id = ids.len();
+            ids.insert(sub as *const _ as usize, id);
+            number_loops(sub, ids);
+        }
+    }
+}
+
+pub fn count_loops(ir: &[Atom]) -> usize {
+    ir.iter().map(|atom| match *atom {
+        Atom::Loop(ref sub) => 1 + count_loops(sub),
+        _ => 0,
+    }).sum()
+}
+
+// FNV-1a of the atoms, stable across platforms and compiler versions so that
+// profiles can be kept around
+pub fn hash_ir(ir: &[Atom]) -> u64 {
+    fn write(hash: &mut u64, tag: u8, values: &[i64]) {
+        let bytes = values.iter().flat_map(|value| value.to_le_bytes());
+        for byte in Some(tag).into_iter().chain(bytes) {
+            *hash ^= byte as u64;
+            *hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn hash_atoms(hash: &mut u64, ir: &[Atom]) {
+        for atom in ir {
+            match *atom {
+                Atom::MovePtr(offset) => write(hash, 0, &[offset as i64]),
+                Atom::SetValue(value, offset) => write(hash, 1, &[value, offset as i64]),
+                Atom::IncValue(inc, offset) => write(hash, 2, &[inc, offset as i64]),
+                Atom::Print(offset) => write(hash, 3, &[offset as i64]),
+                Atom::Read(offset) => write(hash, 4, &[offset as i64]),
+                Atom::Multiply(factor, offset) => write(hash, 5, &[factor, offset as i64]),
+                Atom::Loop(ref sub) => {
+                    write(hash, 6, &[]);
+                    hash_atoms(hash, sub);
+                    write(hash, 7, &[]);
+                },
+            }
+        }
+    }
+
+    let mut hash = FNV_OFFSET;
+    hash_atoms(&mut hash, ir);
+    hash
+}
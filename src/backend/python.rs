@@ -0,0 +1,342 @@
+use std::io::{self, Write};
+
+use ir::{Atom, Value};
+use backend::{Backend, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+const MEM_SIZE: usize = 30000;
+// Python allows 20 statically nested blocks in a function
+const MAX_NESTING: usize = 20;
+
+// A Python 3 script which is also a module: `run(reader, writer)` runs the
+// program on binary streams, such as `io.BytesIO`, and returns the current
+// cell. Like the interpreter, the `bytearray` cells wrap around, the pointer
+// wraps around the ends of the tape and reading past the end of the input
+// raises `EOFError`. Run as a script, it uses the standard input and output
+// and exits with the statuses of the interpreter. Loops nested too deeply
+// are moved to functions of their own, defined in `run` before its code.
+#[derive(Debug, Clone)]
+pub struct PythonBackend<W: Write> {
+    writer: W,
+    current_tab: usize,
+    // the code of `run` then of the loops being moved to functions
+    code: Vec<Vec<u8>>,
+    functions: Vec<u8>,
+    function_count: usize,
+    // loops nested in the current function
+    nesting: usize,
+    tape_size: usize,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    exit_cell: bool,
+    fuel: Option<u64>,
+}
+
+impl<W: Write> PythonBackend<W> {
+    pub fn new(writer: W) -> Self {
+        PythonBackend {
+            writer,
+            current_tab: 1,
+            code: vec![Vec::new()],
+            functions: Vec::new(),
+            function_count: 0,
+            nesting: 0,
+            tape_size: MEM_SIZE,
+            io: IoOptions::default(),
+            image: None,
+            exit_cell: false,
+            fuel: None,
+        }
+    }
+
+    // only byte I/O is supported for now
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    // the script exits with the current cell instead of 0
+    pub fn with_exit_cell(mut self) -> Self {
+        self.exit_cell = true;
+        self
+    }
+
+    // running more than `fuel` loop iterations raises `OutOfFuel`, the script
+    // exits with `OUT_OF_FUEL_STATUS`
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // like in the Rust backend, the offsets are taken modulo the tape size
+    fn index(&self, offset: isize) -> String {
+        match offset.rem_euclid(self.tape_size as isize) {
+            0 => String::from("ptr"),
+            offset => format!("(ptr + {}) % {}", offset, self.tape_size),
+        }
+    }
+
+    fn write_main(&mut self) -> io::Result<()> {
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "def main():")?;
+        writeln!(&mut self.writer, "    try:")?;
+        writeln!(&mut self.writer, "        cell = run(sys.stdin.buffer, sys.stdout.buffer)")?;
+        writeln!(&mut self.writer, "    except EOFError:")?;
+        writeln!(&mut self.writer, "        return {}", EOF_STATUS)?;
+        if self.fuel.is_some() {
+            writeln!(&mut self.writer, "    except OutOfFuel:")?;
+            writeln!(&mut self.writer, "        return {}", OUT_OF_FUEL_STATUS)?;
+        }
+        writeln!(&mut self.writer, "    except OSError:")?;
+        writeln!(&mut self.writer, "        return 74")?;
+        writeln!(&mut self.writer, "    return {}", if self.exit_cell { "cell" } else { "0" })?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "if __name__ == \"__main__\":")?;
+        writeln!(&mut self.writer, "    sys.exit(main())")
+    }
+
+    // the code of the current function
+    fn code(&mut self) -> &mut Vec<u8> {
+        self.code.last_mut().unwrap()
+    }
+
+    fn write_tab(&mut self) -> io::Result<()> {
+        let tab = "    ".repeat(self.current_tab);
+        write!(self.code(), "{}", tab)
+    }
+
+    // the loop is run by calling a function defined like `read_byte`
+    fn push_function_loop(&mut self, sub: &Vec<Atom>) -> io::Result<()> {
+        let name = format!("loop{}", self.function_count);
+        self.function_count += 1;
+        self.write_tab()?;
+        writeln!(self.code(), "{}()", name)?;
+
+        let (tab, nesting) = (self.current_tab, self.nesting);
+        self.current_tab = 2;
+        self.nesting = 0;
+        self.code.push(Vec::new());
+        self.push_loop(sub)?;
+        let code = self.code.pop().unwrap();
+        self.current_tab = tab;
+        self.nesting = nesting;
+
+        let variables = if self.fuel.is_some() { "ptr, fuel" } else { "ptr" };
+        writeln!(&mut self.functions)?;
+        writeln!(&mut self.functions, "    def {}():", name)?;
+        writeln!(&mut self.functions, "        nonlocal {}", variables)?;
+        self.functions.extend_from_slice(&code);
+        Ok(())
+    }
+}
+
+impl<W: Write> Backend for PythonBackend<W> {
+    type Payload = ();
+    type Error = io::Error;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        let image = self.image.take().unwrap_or_default();
+        if self.io != IoOptions::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the Python backend only supports byte I/O"));
+        }
+        if self.tape_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        if image.cells.len() > self.tape_size || image.ptr >= self.tape_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "tape image doesn't fit in the tape"));
+        }
+
+        writeln!(&mut self.writer, "#!/usr/bin/env python3")?;
+        writeln!(&mut self.writer, "# generated by bfc")?;
+        writeln!(&mut self.writer, "import sys")?;
+        if self.fuel.is_some() {
+            writeln!(&mut self.writer)?;
+            writeln!(&mut self.writer)?;
+            writeln!(&mut self.writer, "class OutOfFuel(Exception):")?;
+            writeln!(&mut self.writer, "    pass")?;
+        }
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "def run(reader, writer):")?;
+        writeln!(&mut self.writer, "    \"\"\"Runs the program on binary streams and returns the current cell.\"\"\"")?;
+        writeln!(&mut self.writer, "    # output is flushed before reading")?;
+        writeln!(&mut self.writer, "    def read_byte():")?;
+        writeln!(&mut self.writer, "        writer.flush()")?;
+        writeln!(&mut self.writer, "        byte = reader.read(1)")?;
+        writeln!(&mut self.writer, "        if not byte:")?;
+        writeln!(&mut self.writer, "            raise EOFError(\"end of input\")")?;
+        writeln!(&mut self.writer, "        return byte[0]")?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "    write = writer.write")?;
+        writeln!(&mut self.writer, "    tape = bytearray({})", self.tape_size)?;
+        if !image.cells.is_empty() {
+            writeln!(&mut self.writer, "    tape[:{}] = bytes([", image.cells.len())?;
+            for line in image.cells.chunks(16) {
+                let cells: Vec<String> = line.iter().map(|cell| cell.to_string()).collect();
+                writeln!(&mut self.writer, "        {},", cells.join(", "))?;
+            }
+            writeln!(&mut self.writer, "    ])")?;
+        }
+        writeln!(&mut self.writer, "    ptr = {}", image.ptr)?;
+        if let Some(fuel) = self.fuel {
+            writeln!(&mut self.writer, "    fuel = {}", fuel)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<(), Self::Error> {
+        if !self.functions.is_empty() {
+            self.writer.write_all(&self.functions)?;
+            writeln!(&mut self.writer)?;
+        }
+        self.writer.write_all(&self.code[0])?;
+        writeln!(&mut self.writer, "    writer.flush()")?;
+        writeln!(&mut self.writer, "    return tape[ptr]")?;
+        self.write_main()
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "ptr = {}", index)
+    }
+
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "tape[{}] = {}", index, value as u8)
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "tape[{0}] = (tape[{0}] + {1}) & 255", index, inc as u8)
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "write(bytes((tape[{}],)))", index)
+    }
+
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "tape[{}] = read_byte()", index)
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(self.code(), "tape[{0}] = (tape[{0}] + tape[ptr] * {1}) & 255",
+                 index, factor as u8)
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        if self.nesting == MAX_NESTING {
+            return self.push_function_loop(sub);
+        }
+        self.write_tab()?;
+        writeln!(self.code(), "while tape[ptr]:")?;
+        self.current_tab += 1;
+        self.nesting += 1;
+        if self.fuel.is_some() {
+            self.write_tab()?;
+            writeln!(self.code(), "if fuel == 0:")?;
+            self.write_tab()?;
+            writeln!(self.code(), "    raise OutOfFuel(\"out of fuel\")")?;
+            self.write_tab()?;
+            writeln!(self.code(), "fuel -= 1")?;
+        } else if sub.is_empty() {
+            // the body of a loop can't be empty
+            self.write_tab()?;
+            writeln!(self.code(), "pass")?;
+        }
+        self.push_atoms(sub)?;
+        self.current_tab -= 1;
+        self.nesting -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Output;
+
+    use ir::{self, Atom};
+    use backend::{self, PythonBackend};
+
+    // runs the script on `input`, or returns `None` when Python isn't
+    // installed
+    fn run(ir: &Vec<Atom>, fuel: Option<u64>, exit_cell: bool, input: &[u8]) -> Option<Output> {
+        let mut code = Vec::new();
+        let mut backend = PythonBackend::new(&mut code);
+        if exit_cell {
+            backend = backend.with_exit_cell();
+        }
+        if let Some(fuel) = fuel {
+            backend = backend.with_fuel(fuel);
+        }
+        backend::use_backend(backend, ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        backend::run_tool("python3", &["-c".as_ref(), code.as_ref()], input)
+    }
+
+    #[test]
+    fn generated_code() {
+        let ir = ir::build_ir(b"[]<-.").unwrap();
+        let mut code = Vec::new();
+        let backend = PythonBackend::new(&mut code)
+            .with_tape_size(100)
+            .with_exit_cell();
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("    while tape[ptr]:\n        pass\n"));
+        assert!(code.contains("    ptr = (ptr + 99) % 100\n"));
+        assert!(code.contains("    tape[ptr] = (tape[ptr] + 255) & 255\n"));
+        assert!(code.contains("    return cell\n"));
+        assert!(!code.contains("OutOfFuel"));
+    }
+
+    #[test]
+    fn statuses() {
+        backend::check_programs(|ir, fuel, input| run(ir, fuel, false, input));
+    }
+
+    #[test]
+    fn deep_loops() {
+        // the innermost loops are two functions deep
+        let program = format!("+[{}>+++.<-{}>", "[".repeat(44), "]".repeat(45));
+        let ir = ir::build_ir(program.as_bytes()).unwrap();
+        for &fuel in &[None, Some(100)] {
+            let mut code = Vec::new();
+            let mut backend = PythonBackend::new(&mut code);
+            if let Some(fuel) = fuel {
+                backend = backend.with_fuel(fuel);
+            }
+            backend::use_backend(backend, &ir).unwrap();
+            let code = String::from_utf8(code).unwrap();
+            assert!(code.contains("\n    def loop1():\n        nonlocal ptr"));
+            assert!(!code.contains("loop2"));
+            assert!(code.contains(&format!("{}loop0()\n", "    ".repeat(21))));
+
+            if let Some(output) = run(&ir, fuel, true, b"") {
+                assert_eq!(output.stdout, [3], "{}", String::from_utf8_lossy(&output.stderr));
+                assert_eq!(output.status.code(), Some(3));
+            }
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::io::{self, Write};
+
+use ir::{Atom, Value};
+use backend::{Backend, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+const MEM_SIZE: usize = 30000;
+// message of the error returned when the fuel runs out
+const OUT_OF_FUEL: &str = "out of fuel";
+
+// The program runs in a function reading and writing bytes, like the
+// interpreter its `u8` cells wrap around, its pointer wraps around the ends of
+// the tape and reading past the end of the input is an `UnexpectedEof` error.
+// Without a function name, a `main` runs it on the standard input and output
+// and exits with the statuses of the interpreter.
+#[derive(Debug, Clone)]
+pub struct RustBackend<W: Write> {
+    writer: W,
+    current_tab: usize,
+    tape_size: usize,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    exit_cell: bool,
+    fuel: Option<u64>,
+    function_name: Option<String>,
+}
+
+impl<W: Write> RustBackend<W> {
+    pub fn new(writer: W) -> Self {
+        RustBackend {
+            writer,
+            current_tab: 1,
+            tape_size: MEM_SIZE,
+            io: IoOptions::default(),
+            image: None,
+            exit_cell: false,
+            fuel: None,
+            function_name: None,
+        }
+    }
+
+    // only byte I/O is supported for now
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    // `main` exits with the current cell instead of 0
+    pub fn with_exit_cell(mut self) -> Self {
+        self.exit_cell = true;
+        self
+    }
+
+    // running more than `fuel` loop iterations is an error, `main` exits with
+    // `OUT_OF_FUEL_STATUS`
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // `pub fn name<R: Read, W: Write>(input: R, output: W) -> io::Result<u8>`
+    // returning the current cell, instead of a program
+    pub fn with_function<S: Into<String>>(mut self, name: S) -> Self {
+        self.function_name = Some(name.into());
+        self
+    }
+
+    // the offsets are taken modulo the tape size so that indices never
+    // overflow
+    fn index(&self, offset: isize) -> String {
+        match offset.rem_euclid(self.tape_size as isize) {
+            0 => String::from("ptr"),
+            offset => format!("(ptr + {}) % {}", offset, self.tape_size),
+        }
+    }
+
+    fn write_main(&mut self) -> io::Result<()> {
+        let status = if self.exit_cell { "Ok(cell) => cell as i32" } else { "Ok(_) => 0" };
+        writeln!(&mut self.writer, "fn main() {{")?;
+        writeln!(&mut self.writer, "    let stdin = io::stdin();")?;
+        writeln!(&mut self.writer, "    let stdout = io::stdout();")?;
+        writeln!(&mut self.writer, "    let output = io::BufWriter::new(stdout.lock());")?;
+        writeln!(&mut self.writer, "    let status = match run(stdin.lock(), output) {{")?;
+        writeln!(&mut self.writer, "        {},", status)?;
+        writeln!(&mut self.writer, "        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {},",
+                 EOF_STATUS)?;
+        if self.fuel.is_some() {
+            writeln!(&mut self.writer, "        Err(ref err) if err.to_string() == {:?} => {},",
+                     OUT_OF_FUEL, OUT_OF_FUEL_STATUS)?;
+        }
+        writeln!(&mut self.writer, "        Err(_) => 74,")?;
+        writeln!(&mut self.writer, "    }};")?;
+        writeln!(&mut self.writer, "    process::exit(status);")?;
+        writeln!(&mut self.writer, "}}")?;
+        writeln!(&mut self.writer)
+    }
+
+    fn write_tab(&mut self) -> io::Result<()> {
+        write!(&mut self.writer, "{}", "    ".repeat(self.current_tab))
+    }
+}
+
+impl<W: Write> Backend for RustBackend<W> {
+    type Payload = ();
+    type Error = io::Error;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        let image = self.image.take().unwrap_or_default();
+        if self.io != IoOptions::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the Rust backend only supports byte I/O"));
+        }
+        if self.tape_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        if image.cells.len() > self.tape_size || image.ptr >= self.tape_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "tape image doesn't fit in the tape"));
+        }
+
+        writeln!(&mut self.writer, "// generated by bfc")?;
+        writeln!(&mut self.writer, "use std::io::{{self, Read, Write}};")?;
+        let name = match self.function_name {
+            Some(ref name) => format!("pub fn {}", name),
+            None => {
+                writeln!(&mut self.writer, "use std::process;")?;
+                writeln!(&mut self.writer)?;
+                self.write_main()?;
+                String::from("fn run")
+            },
+        };
+        if self.function_name.is_some() {
+            writeln!(&mut self.writer)?;
+        }
+
+        // programs may not use their input or output
+        writeln!(&mut self.writer, "#[allow(unused_mut, unused_variables, dead_code)]")?;
+        writeln!(&mut self.writer, "{}<R: Read, W: Write>(mut input: R, mut output: W) \
+                                    -> io::Result<u8> {{", name)?;
+        writeln!(&mut self.writer, "    // output is flushed before reading")?;
+        writeln!(&mut self.writer, "    fn read_byte<R: Read, W: Write>(input: &mut R, output: &mut W) \
+                                    -> io::Result<u8> {{")?;
+        writeln!(&mut self.writer, "        output.flush()?;")?;
+        writeln!(&mut self.writer, "        match input.bytes().next() {{")?;
+        writeln!(&mut self.writer, "            Some(byte) => byte,")?;
+        writeln!(&mut self.writer, "            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, \
+                                    \"end of input\")),")?;
+        writeln!(&mut self.writer, "        }}")?;
+        writeln!(&mut self.writer, "    }}")?;
+        writeln!(&mut self.writer)?;
+        writeln!(&mut self.writer, "    let mut tape = vec![0u8; {}];", self.tape_size)?;
+        if !image.cells.is_empty() {
+            writeln!(&mut self.writer, "    tape[..{}].copy_from_slice(&[", image.cells.len())?;
+            for line in image.cells.chunks(16) {
+                let cells: Vec<String> = line.iter().map(|cell| cell.to_string()).collect();
+                writeln!(&mut self.writer, "        {},", cells.join(", "))?;
+            }
+            writeln!(&mut self.writer, "    ]);")?;
+        }
+        writeln!(&mut self.writer, "    let mut ptr: usize = {};", image.ptr)?;
+        if let Some(fuel) = self.fuel {
+            writeln!(&mut self.writer, "    let mut fuel: u64 = {};", fuel)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<(), Self::Error> {
+        writeln!(&mut self.writer, "    output.flush()?;")?;
+        writeln!(&mut self.writer, "    Ok(tape[ptr])")?;
+        writeln!(&mut self.writer, "}}")
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "ptr = {};", index)
+    }
+
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] = {};", index, value as u8)
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{0}] = tape[{0}].wrapping_add({1});", index, inc as u8)
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "output.write_all(&[tape[{}]])?;", index)
+    }
+
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{}] = read_byte(&mut input, &mut output)?;", index)
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        let index = self.index(offset);
+        self.write_tab()?;
+        writeln!(&mut self.writer, "tape[{0}] = tape[{0}].wrapping_add(tape[ptr].wrapping_mul({1}));",
+                 index, factor as u8)
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        self.write_tab()?;
+        writeln!(&mut self.writer, "while tape[ptr] != 0 {{")?;
+        self.current_tab += 1;
+        if self.fuel.is_some() {
+            self.write_tab()?;
+            writeln!(&mut self.writer, "if fuel == 0 {{")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "    return Err(io::Error::new(io::ErrorKind::Other, {:?}));",
+                     OUT_OF_FUEL)?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "}}")?;
+            self.write_tab()?;
+            writeln!(&mut self.writer, "fuel -= 1;")?;
+        }
+        self.push_atoms(sub)?;
+        self.current_tab -= 1;
+        self.write_tab()?;
+        writeln!(&mut self.writer, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Output;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::{env, fs, process};
+
+    use ir::{self, Atom};
+    use opt;
+    use backend::{self, IoEncoding, IoOptions, RustBackend};
+
+    static PROGRAMS: AtomicUsize = AtomicUsize::new(0);
+
+    // builds the program with rustc and runs it on `input`, or returns
+    // `None` when rustc isn't installed
+    fn run(ir: &Vec<Atom>, tape_size: usize, fuel: Option<u64>, input: &[u8])
+        -> Option<Output> {
+        let id = PROGRAMS.fetch_add(1, Ordering::SeqCst);
+        let exe_path = env::temp_dir().join(format!("bfc-rust-test-{}-{}", process::id(), id));
+        let rs_path = exe_path.with_extension("rs");
+        let mut backend = RustBackend::new(fs::File::create(&rs_path).unwrap())
+            .with_tape_size(tape_size);
+        if let Some(fuel) = fuel {
+            backend = backend.with_fuel(fuel);
+        }
+        backend::use_backend(backend, ir).unwrap();
+
+        let args = [rs_path.as_os_str(), "-o".as_ref(), exe_path.as_os_str()];
+        let output = backend::run_tool("rustc", &args, b"");
+        let _ = fs::remove_file(&rs_path);
+        let output = output?;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let output = backend::run_tool(exe_path.to_str().unwrap(), &[], input);
+        let _ = fs::remove_file(&exe_path);
+        output
+    }
+
+    #[test]
+    fn generated_code() {
+        let ir = opt::run_opts(ir::build_ir(b"<+++[->++<]>.,").unwrap());
+        let mut code = Vec::new();
+        let backend = RustBackend::new(&mut code)
+            .with_tape_size(8)
+            .with_function("program");
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("pub fn program<R: Read, W: Write>(mut input: R, mut output: W)"));
+        assert!(!code.contains("fn main"));
+        assert!(code.contains("    let mut tape = vec![0u8; 8];\n    let mut ptr: usize = 0;\n"));
+        // the pointer wraps around the tape and the cells around 256
+        assert!(code.contains("    ptr = (ptr + 7) % 8;\n"));
+        assert!(code.contains("tape[(ptr + 1) % 8].wrapping_add(tape[ptr].wrapping_mul(2));"));
+        assert!(code.contains("tape[(ptr + 1) % 8] = read_byte(&mut input, &mut output)?;"));
+
+        let io = IoOptions { encoding: IoEncoding::Decimal, translate_newlines: false };
+        let backend = RustBackend::new(Vec::new()).with_io(io);
+        assert!(backend::use_backend(backend, &ir).is_err());
+    }
+
+    #[test]
+    fn statuses() {
+        backend::check_programs(|ir, fuel, input| run(ir, 30000, fuel, input));
+    }
+
+    #[test]
+    fn tape_ends() {
+        // moves and offsets both wrap around the tape, the multiplication
+        // reaches the last cell from the first one
+        let programs: [(&[u8], &[u8]); 2] = [
+            (b"-<+++++>>>>.<<<<<.>>.", &[5, 0, 255]),
+            (b"+++[-<++>]<.", &[6]),
+        ];
+        for &(program, expected) in &programs {
+            let ir = ir::build_ir(program).unwrap();
+            for ir in [opt::run_opts(ir.clone()), ir] {
+                match run(&ir, 4, None, b"") {
+                    Some(output) => assert_eq!(output.stdout, expected),
+                    None => return,
+                }
+            }
+        }
+    }
+}
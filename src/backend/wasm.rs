@@ -0,0 +1,687 @@
+use std::io;
+
+use ir::{Atom, Value};
+use backend::{Backend, IoOptions, TapeImage, EOF_STATUS, OUT_OF_FUEL_STATUS};
+
+const MEM_SIZE: usize = 30000;
+const PAGE_SIZE: usize = 65536;
+
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+
+// How the module reads and writes bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WasmIo {
+    // `fd_read` and `fd_write` of `wasi_snapshot_preview1` on stdin and
+    // stdout, the program is the exported `_start`
+    Wasi,
+    // `env.read() -> i32`, negative at EOF, and `env.write(i32)`, the
+    // program is the exported `run() -> i32`
+    Imports,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instr {
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    I32Load(u32),
+    I32Load8U(u32),
+    I32Store8(u32),
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32LtS,
+    I32LtU,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I64Eqz,
+    I64Sub,
+}
+
+impl Instr {
+    fn encode(self, out: &mut Vec<u8>) {
+        match self {
+            Instr::Block => out.extend_from_slice(&[0x02, 0x40]),
+            Instr::Loop => out.extend_from_slice(&[0x03, 0x40]),
+            Instr::If => out.extend_from_slice(&[0x04, 0x40]),
+            Instr::End => out.push(0x0B),
+            Instr::Br(depth) => leb_op(out, 0x0C, depth),
+            Instr::BrIf(depth) => leb_op(out, 0x0D, depth),
+            Instr::Return => out.push(0x0F),
+            Instr::Call(function) => leb_op(out, 0x10, function),
+            Instr::Drop => out.push(0x1A),
+            Instr::Select => out.push(0x1B),
+            Instr::LocalGet(local) => leb_op(out, 0x20, local),
+            Instr::LocalSet(local) => leb_op(out, 0x21, local),
+            Instr::LocalTee(local) => leb_op(out, 0x22, local),
+            Instr::I32Load(offset) => mem_op(out, 0x28, 2, offset),
+            Instr::I32Load8U(offset) => mem_op(out, 0x2D, 0, offset),
+            Instr::I32Store8(offset) => mem_op(out, 0x3A, 0, offset),
+            Instr::I32Const(value) => {
+                out.push(0x41);
+                write_sleb(out, value as i64);
+            },
+            Instr::I64Const(value) => {
+                out.push(0x42);
+                write_sleb(out, value);
+            },
+            Instr::I32Eqz => out.push(0x45),
+            Instr::I32Eq => out.push(0x46),
+            Instr::I32LtS => out.push(0x48),
+            Instr::I32LtU => out.push(0x49),
+            Instr::I32Add => out.push(0x6A),
+            Instr::I32Sub => out.push(0x6B),
+            Instr::I32Mul => out.push(0x6C),
+            Instr::I64Eqz => out.push(0x50),
+            Instr::I64Sub => out.push(0x7D),
+        }
+    }
+
+    fn text(self, function_names: &[&str], local_names: &[&str]) -> String {
+        let mem = |name: &str, offset: u32| match offset {
+            0 => name.to_owned(),
+            _ => format!("{} offset={}", name, offset),
+        };
+        match self {
+            Instr::Block => "block".to_owned(),
+            Instr::Loop => "loop".to_owned(),
+            Instr::If => "if".to_owned(),
+            Instr::End => "end".to_owned(),
+            Instr::Br(depth) => format!("br {}", depth),
+            Instr::BrIf(depth) => format!("br_if {}", depth),
+            Instr::Return => "return".to_owned(),
+            Instr::Call(function) => format!("call ${}", function_names[function as usize]),
+            Instr::Drop => "drop".to_owned(),
+            Instr::Select => "select".to_owned(),
+            Instr::LocalGet(local) => format!("local.get ${}", local_names[local as usize]),
+            Instr::LocalSet(local) => format!("local.set ${}", local_names[local as usize]),
+            Instr::LocalTee(local) => format!("local.tee ${}", local_names[local as usize]),
+            Instr::I32Load(offset) => mem("i32.load", offset),
+            Instr::I32Load8U(offset) => mem("i32.load8_u", offset),
+            Instr::I32Store8(offset) => mem("i32.store8", offset),
+            Instr::I32Const(value) => format!("i32.const {}", value),
+            Instr::I64Const(value) => format!("i64.const {}", value),
+            Instr::I32Eqz => "i32.eqz".to_owned(),
+            Instr::I32Eq => "i32.eq".to_owned(),
+            Instr::I32LtS => "i32.lt_s".to_owned(),
+            Instr::I32LtU => "i32.lt_u".to_owned(),
+            Instr::I32Add => "i32.add".to_owned(),
+            Instr::I32Sub => "i32.sub".to_owned(),
+            Instr::I32Mul => "i32.mul".to_owned(),
+            Instr::I64Eqz => "i64.eqz".to_owned(),
+            Instr::I64Sub => "i64.sub".to_owned(),
+        }
+    }
+}
+
+// the types are the same in every module, only some of them are used
+const TYPES: [(&[u8], &[u8]); 4] = [
+    // `fd_read` and `fd_write`
+    (&[I32, I32, I32, I32], &[I32]),
+    // `write` and `proc_exit`
+    (&[I32], &[]),
+    // `read` and `run`
+    (&[], &[I32]),
+    // `_start`
+    (&[], &[]),
+];
+
+#[derive(Debug, Clone)]
+struct Import {
+    module: &'static str,
+    name: &'static str,
+    type_index: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    name: &'static str,
+    type_index: u32,
+    params: Vec<&'static str>,
+    locals: Vec<(&'static str, u8)>,
+    body: Vec<Instr>,
+}
+
+impl Function {
+    fn new(name: &'static str, type_index: u32) -> Self {
+        Function {
+            name,
+            type_index,
+            params: Vec::new(),
+            locals: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+// A WebAssembly module with the tape in its exported memory, as emitted by
+// `WasmBackend`.
+#[derive(Debug, Clone)]
+pub struct WasmModule {
+    // address of the first cell
+    pub tape_address: u32,
+    imports: Vec<Import>,
+    functions: Vec<Function>,
+    exports: Vec<(&'static str, u32)>,
+    pages: u32,
+    data: Vec<(u32, Vec<u8>)>,
+}
+
+impl WasmModule {
+    fn function_names(&self) -> Vec<&'static str> {
+        self.imports.iter().map(|import| import.name)
+            .chain(self.functions.iter().map(|function| function.name))
+            .collect()
+    }
+
+    // the binary format, as loaded by runtimes
+    pub fn binary(&self) -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+        let mut types = Vec::new();
+        write_uleb(&mut types, TYPES.len() as u64);
+        for &(params, results) in &TYPES {
+            types.push(0x60);
+            write_bytes(&mut types, params);
+            write_bytes(&mut types, results);
+        }
+        write_section(&mut out, 1, &types);
+
+        let mut imports = Vec::new();
+        write_uleb(&mut imports, self.imports.len() as u64);
+        for import in &self.imports {
+            write_bytes(&mut imports, import.module.as_bytes());
+            write_bytes(&mut imports, import.name.as_bytes());
+            imports.push(0x00);
+            write_uleb(&mut imports, import.type_index as u64);
+        }
+        write_section(&mut out, 2, &imports);
+
+        let mut functions = Vec::new();
+        write_uleb(&mut functions, self.functions.len() as u64);
+        for function in &self.functions {
+            write_uleb(&mut functions, function.type_index as u64);
+        }
+        write_section(&mut out, 3, &functions);
+
+        let mut memory = vec![1, 0x00];
+        write_uleb(&mut memory, self.pages as u64);
+        write_section(&mut out, 5, &memory);
+
+        // the memory is exported along with the functions
+        let mut exports = Vec::new();
+        write_uleb(&mut exports, self.exports.len() as u64 + 1);
+        write_bytes(&mut exports, b"memory");
+        exports.extend_from_slice(&[0x02, 0x00]);
+        for &(name, function) in &self.exports {
+            write_bytes(&mut exports, name.as_bytes());
+            exports.push(0x00);
+            write_uleb(&mut exports, function as u64);
+        }
+        write_section(&mut out, 7, &exports);
+
+        let mut code = Vec::new();
+        write_uleb(&mut code, self.functions.len() as u64);
+        for function in &self.functions {
+            let mut body = Vec::new();
+            write_uleb(&mut body, function.locals.len() as u64);
+            for &(_, ty) in &function.locals {
+                body.extend_from_slice(&[1, ty]);
+            }
+            for &instr in &function.body {
+                instr.encode(&mut body);
+            }
+            body.push(0x0B);
+            write_bytes(&mut code, &body);
+        }
+        write_section(&mut out, 10, &code);
+
+        if self.data.is_empty() {
+            return out;
+        }
+        let mut data = Vec::new();
+        write_uleb(&mut data, self.data.len() as u64);
+        for &(offset, ref bytes) in &self.data {
+            data.push(0x00);
+            Instr::I32Const(offset as i32).encode(&mut data);
+            data.push(0x0B);
+            write_bytes(&mut data, bytes);
+        }
+        write_section(&mut out, 11, &data);
+        out
+    }
+
+    // the text format, with the same instructions as the binary
+    pub fn text(&self) -> String {
+        let type_name = |ty: u8| if ty == I64 { "i64" } else { "i32" };
+        let function_names = self.function_names();
+        let mut out = String::from("(module\n");
+
+        for (index, &(params, results)) in TYPES.iter().enumerate() {
+            out.push_str(&format!("  (type $t{} (func", index));
+            if !params.is_empty() {
+                let params: Vec<&str> = params.iter().map(|&ty| type_name(ty)).collect();
+                out.push_str(&format!(" (param {})", params.join(" ")));
+            }
+            if !results.is_empty() {
+                let results: Vec<&str> = results.iter().map(|&ty| type_name(ty)).collect();
+                out.push_str(&format!(" (result {})", results.join(" ")));
+            }
+            out.push_str("))\n");
+        }
+        for import in &self.imports {
+            out.push_str(&format!("  (import \"{}\" \"{}\" (func ${} (type $t{})))\n",
+                                  import.module, import.name, import.name, import.type_index));
+        }
+        out.push_str(&format!("  (memory (export \"memory\") {})\n", self.pages));
+
+        for (index, function) in self.functions.iter().enumerate() {
+            let index = (self.imports.len() + index) as u32;
+            out.push_str(&format!("  (func ${}", function.name));
+            if let Some(&(name, _)) = self.exports.iter().find(|&&(_, export)| export == index) {
+                out.push_str(&format!(" (export \"{}\")", name));
+            }
+            out.push_str(&format!(" (type $t{})", function.type_index));
+            let (params, results) = TYPES[function.type_index as usize];
+            for (name, &ty) in function.params.iter().zip(params) {
+                out.push_str(&format!(" (param ${} {})", name, type_name(ty)));
+            }
+            for &ty in results {
+                out.push_str(&format!(" (result {})", type_name(ty)));
+            }
+            out.push('\n');
+            for &(name, ty) in &function.locals {
+                out.push_str(&format!("    (local ${} {})\n", name, type_name(ty)));
+            }
+
+            let local_names: Vec<&str> = function.params.iter().cloned()
+                .chain(function.locals.iter().map(|&(name, _)| name))
+                .collect();
+            let mut depth = 2;
+            for &instr in &function.body {
+                if instr == Instr::End {
+                    depth -= 1;
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push_str(&instr.text(&function_names, &local_names));
+                out.push('\n');
+                if let Instr::Block | Instr::Loop | Instr::If = instr {
+                    depth += 1;
+                }
+            }
+            out.push_str("  )\n");
+        }
+
+        for &(offset, ref bytes) in &self.data {
+            let bytes: String = bytes.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+            out.push_str(&format!("  (data (i32.const {}) \"{}\")\n", offset, bytes));
+        }
+        out.push_str(")\n");
+        out
+    }
+}
+
+// The cells are bytes at the start of the memory, the pointer is their index
+// and like in the Rust backend, the offsets are taken modulo the tape size.
+// Reading at EOF stops the program like the fuel running out.
+#[derive(Debug, Clone)]
+pub struct WasmBackend {
+    wasm_io: WasmIo,
+    tape_size: usize,
+    io: IoOptions,
+    image: Option<TapeImage>,
+    exit_cell: bool,
+    fuel: Option<u64>,
+    // functions called by the program
+    read_fn: u32,
+    write_fn: u32,
+    body: Vec<Instr>,
+}
+
+// locals of `run`
+const PTR: u32 = 0;
+const BYTE: u32 = 1;
+const INDEX: u32 = 2;
+const FUEL: u32 = 3;
+// what `run` returns instead of the current cell when stopped early
+const OUT_OF_FUEL_RESULT: i32 = -1;
+const EOF_RESULT: i32 = -2;
+
+impl WasmBackend {
+    pub fn new(wasm_io: WasmIo) -> Self {
+        WasmBackend {
+            wasm_io,
+            tape_size: MEM_SIZE,
+            io: IoOptions::default(),
+            image: None,
+            exit_cell: false,
+            fuel: None,
+            read_fn: 0,
+            write_fn: 0,
+            body: Vec::new(),
+        }
+    }
+
+    // only byte I/O is supported for now
+    pub fn with_io(mut self, io: IoOptions) -> Self {
+        self.io = io;
+        self
+    }
+
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    pub fn with_image(mut self, image: TapeImage) -> Self {
+        self.image = Some(image);
+        self
+    }
+
+    // `_start` exits with the current cell
+    pub fn with_exit_cell(mut self) -> Self {
+        self.exit_cell = true;
+        self
+    }
+
+    // `run` returns -1 instead of running more than `fuel` loop iterations,
+    // `_start` then exits with `OUT_OF_FUEL_STATUS`. Likewise, `run` returns
+    // -2 at the end of the input and `_start` exits with `EOF_STATUS`.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    // the I/O scratch space of WASI follows the tape, aligned for its iovec
+    fn scratch(&self) -> i32 {
+        ((self.tape_size + 15) & !15) as i32
+    }
+
+    // pushes the index of a cell, which is also its address, the pointer
+    // and the offset being both below the tape size, the sum wraps at most
+    // once
+    fn cell(&mut self, offset: isize) {
+        self.body.push(Instr::LocalGet(PTR));
+        let size = self.tape_size as i32;
+        match offset.rem_euclid(self.tape_size as isize) {
+            0 => {},
+            offset => self.body.extend_from_slice(&[
+                Instr::I32Const(offset as i32), Instr::I32Add, Instr::LocalTee(INDEX),
+                Instr::LocalGet(INDEX), Instr::I32Const(size), Instr::I32Sub,
+                Instr::LocalGet(INDEX), Instr::I32Const(size), Instr::I32LtU,
+                Instr::Select,
+            ]),
+        }
+    }
+
+    fn wasi_functions(&self) -> Vec<Function> {
+        let scratch = self.scratch();
+        let (iovec, byte, count) = (scratch, scratch + 8, scratch + 12);
+
+        // the byte read or -1 when nothing was
+        let mut read = Function::new("read", 2);
+        read.body = vec![
+            Instr::I32Const(0), Instr::I32Const(iovec), Instr::I32Const(1),
+            Instr::I32Const(count), Instr::Call(0), Instr::Drop,
+            Instr::I32Const(byte), Instr::I32Load8U(0),
+            Instr::I32Const(-1),
+            Instr::I32Const(count), Instr::I32Load(0),
+            Instr::Select,
+        ];
+
+        let mut write = Function::new("write", 1);
+        write.params = vec!["byte"];
+        write.body = vec![
+            Instr::I32Const(byte), Instr::LocalGet(0), Instr::I32Store8(0),
+            Instr::I32Const(1), Instr::I32Const(iovec), Instr::I32Const(1),
+            Instr::I32Const(count), Instr::Call(1), Instr::Drop,
+        ];
+
+        let mut start = Function::new("_start", 3);
+        start.locals = vec![("status", I32)];
+        start.body = vec![Instr::Call(5), Instr::LocalSet(0)];
+        let mut stops = vec![(EOF_RESULT, EOF_STATUS)];
+        if self.fuel.is_some() {
+            stops.push((OUT_OF_FUEL_RESULT, OUT_OF_FUEL_STATUS));
+        }
+        for (result, status) in stops {
+            start.body.extend_from_slice(&[
+                Instr::LocalGet(0), Instr::I32Const(result), Instr::I32Eq, Instr::If,
+                Instr::I32Const(status), Instr::Call(2),
+                Instr::End,
+            ]);
+        }
+        if self.exit_cell {
+            start.body.extend_from_slice(&[Instr::LocalGet(0), Instr::Call(2)]);
+        }
+        vec![read, write, start]
+    }
+}
+
+impl Default for WasmBackend {
+    fn default() -> Self {
+        WasmBackend::new(WasmIo::Wasi)
+    }
+}
+
+impl Backend for WasmBackend {
+    type Payload = WasmModule;
+    type Error = io::Error;
+
+    fn initialize(&mut self) -> Result<(), Self::Error> {
+        if self.io != IoOptions::default() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "the WebAssembly backend only supports byte I/O"));
+        }
+        if self.tape_size == 0 || self.tape_size > i32::MAX as usize - PAGE_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid tape size"));
+        }
+        let ptr = self.image.as_ref().map_or(0, |image| image.ptr);
+        let cells = self.image.as_ref().map_or(0, |image| image.cells.len());
+        if cells > self.tape_size || ptr >= self.tape_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "tape image doesn't fit in the tape"));
+        }
+
+        // imported functions come first
+        let (read_fn, write_fn) = match self.wasm_io {
+            WasmIo::Wasi => (3, 4),
+            WasmIo::Imports => (0, 1),
+        };
+        self.read_fn = read_fn;
+        self.write_fn = write_fn;
+        self.body.push(Instr::I32Const(ptr as i32));
+        self.body.push(Instr::LocalSet(PTR));
+        if let Some(fuel) = self.fuel {
+            self.body.push(Instr::I64Const(fuel as i64));
+            self.body.push(Instr::LocalSet(FUEL));
+        }
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<Self::Payload, Self::Error> {
+        self.cell(0);
+        self.body.push(Instr::I32Load8U(0));
+        let image = self.image.take().unwrap_or_default();
+
+        let mut run = Function::new("run", 2);
+        run.locals.push(("ptr", I32));
+        run.locals.push(("byte", I32));
+        run.locals.push(("index", I32));
+        if self.fuel.is_some() {
+            run.locals.push(("fuel", I64));
+        }
+        run.body = self.body.clone();
+
+        let mut data = Vec::new();
+        if !image.cells.is_empty() {
+            data.push((0, image.cells));
+        }
+        let (imports, functions, exports) = match self.wasm_io {
+            WasmIo::Wasi => {
+                let wasi = |name, type_index| Import {
+                    module: "wasi_snapshot_preview1",
+                    name,
+                    type_index,
+                };
+                let imports = vec![wasi("fd_read", 0), wasi("fd_write", 0), wasi("proc_exit", 1)];
+                let mut functions = self.wasi_functions();
+                functions.insert(2, run);
+
+                // the iovec of the byte read or written
+                let scratch = self.scratch();
+                let mut iovec = Vec::new();
+                iovec.extend_from_slice(&(scratch + 8).to_le_bytes());
+                iovec.extend_from_slice(&1i32.to_le_bytes());
+                data.push((scratch as u32, iovec));
+                (imports, functions, vec![("_start", 6)])
+            },
+            WasmIo::Imports => {
+                let env = |name, type_index| Import { module: "env", name, type_index };
+                (vec![env("read", 2), env("write", 1)], vec![run], vec![("run", 2)])
+            },
+        };
+
+        Ok(WasmModule {
+            tape_address: 0,
+            imports,
+            functions,
+            exports,
+            pages: (self.scratch() as usize + 16).div_ceil(PAGE_SIZE) as u32,
+            data,
+        })
+    }
+
+    fn push_move_ptr(&mut self, offset: isize) -> Result<(), Self::Error> {
+        self.cell(offset);
+        self.body.push(Instr::LocalSet(PTR));
+        Ok(())
+    }
+
+    fn push_set_value(&mut self, value: Value, offset: isize) -> Result<(), Self::Error> {
+        self.cell(offset);
+        self.body.push(Instr::I32Const(value as i8 as i32));
+        self.body.push(Instr::I32Store8(0));
+        Ok(())
+    }
+
+    fn push_inc_value(&mut self, inc: Value, offset: isize) -> Result<(), Self::Error> {
+        self.cell(offset);
+        self.cell(offset);
+        self.body.extend_from_slice(&[
+            Instr::I32Load8U(0), Instr::I32Const(inc as i8 as i32), Instr::I32Add,
+            Instr::I32Store8(0),
+        ]);
+        Ok(())
+    }
+
+    fn push_print(&mut self, offset: isize) -> Result<(), Self::Error> {
+        self.cell(offset);
+        let write_fn = self.write_fn;
+        self.body.push(Instr::I32Load8U(0));
+        self.body.push(Instr::Call(write_fn));
+        Ok(())
+    }
+
+    fn push_read(&mut self, offset: isize) -> Result<(), Self::Error> {
+        self.body.extend_from_slice(&[
+            Instr::Call(self.read_fn), Instr::LocalSet(BYTE),
+            Instr::LocalGet(BYTE), Instr::I32Const(0), Instr::I32LtS, Instr::If,
+            Instr::I32Const(EOF_RESULT), Instr::Return,
+            Instr::End,
+        ]);
+        self.cell(offset);
+        self.body.extend_from_slice(&[Instr::LocalGet(BYTE), Instr::I32Store8(0)]);
+        Ok(())
+    }
+
+    fn push_multiply(&mut self, factor: Value, offset: isize) -> Result<(), Self::Error> {
+        self.cell(offset);
+        self.cell(offset);
+        self.body.extend_from_slice(&[
+            Instr::I32Load8U(0),
+            Instr::LocalGet(PTR), Instr::I32Load8U(0),
+            Instr::I32Const(factor as i8 as i32), Instr::I32Mul,
+            Instr::I32Add, Instr::I32Store8(0),
+        ]);
+        Ok(())
+    }
+
+    fn push_loop(&mut self, sub: &Vec<Atom>) -> Result<(), Self::Error> {
+        self.body.extend_from_slice(&[
+            Instr::Block, Instr::Loop,
+            Instr::LocalGet(PTR), Instr::I32Load8U(0), Instr::I32Eqz, Instr::BrIf(1),
+        ]);
+        if self.fuel.is_some() {
+            self.body.extend_from_slice(&[
+                Instr::LocalGet(FUEL), Instr::I64Eqz, Instr::If,
+                Instr::I32Const(OUT_OF_FUEL_RESULT), Instr::Return,
+                Instr::End,
+                Instr::LocalGet(FUEL), Instr::I64Const(1), Instr::I64Sub, Instr::LocalSet(FUEL),
+            ]);
+        }
+        self.push_atoms(sub)?;
+        self.body.extend_from_slice(&[Instr::Br(0), Instr::End, Instr::End]);
+        Ok(())
+    }
+}
+
+fn leb_op(out: &mut Vec<u8>, opcode: u8, value: u32) {
+    out.push(opcode);
+    write_uleb(out, value as u64);
+}
+
+fn mem_op(out: &mut Vec<u8>, opcode: u8, align: u8, offset: u32) {
+    out.push(opcode);
+    out.push(align);
+    write_uleb(out, offset as u64);
+}
+
+fn write_uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// a vector of bytes, prefixed by its length
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_uleb(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_bytes(out, contents);
+}
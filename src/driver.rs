@@ -1,15 +1,28 @@
 extern crate brainfuck;
 extern crate clap;
+extern crate num_bigint;
 
-use std::fs::File;
-use std::io::{self, Read};
-use std::path::Path;
-use std::ffi::CString;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::ffi::{CString, OsStr};
+use std::num::Wrapping;
+use std::process::{self, Command};
 
 use clap::{Arg, App};
+use num_bigint::BigInt;
 
 use brainfuck::{ir, opt, backend};
-use ir::Atom;
+use ir::{Atom, AtomSpan, CellType};
+use backend::{IoEncoding, IoOptions, TapeImage, ImageFormat, Profile};
+use backend::{EOF_STATUS, OUT_OF_FUEL_STATUS};
+use backend::{CBackendOptions, CCellType, CStandard, WasmIo, WasmModule};
+use backend::llvm::{LLVMBrainfuckModule, OptLevel, OutputFormat, TargetOptions, EOF_ERROR,
+                     OUT_OF_FUEL_ERROR};
+use backend::interpreter::{InterpreterError, Tape, VecTape, SparseTape, MmapTape};
 
 fn main() {
     let matches = App::new("Brainfuck Compiler")
@@ -24,8 +37,21 @@ fn main() {
              .short("t")
              .long("type")
              .takes_value(true)
-             .possible_values(&["c", "interpreter", "jit"])
-             .requires_if("c", "OUTPUT"))
+             .possible_values(&["c", "c-exe", "rust", "wasm", "js", "python", "jvm", "interpreter", "jit", "obj", "asm", "bc", "ll", "exe"])
+             .requires_ifs(&[
+                 ("c", "OUTPUT"),
+                 ("c-exe", "OUTPUT"),
+                 ("rust", "OUTPUT"),
+                 ("wasm", "OUTPUT"),
+                 ("js", "OUTPUT"),
+                 ("python", "OUTPUT"),
+                 ("jvm", "OUTPUT"),
+                 ("obj", "OUTPUT"),
+                 ("asm", "OUTPUT"),
+                 ("bc", "OUTPUT"),
+                 ("ll", "OUTPUT"),
+                 ("exe", "OUTPUT"),
+             ]))
         .arg(Arg::with_name("INPUT")
              .help("Input file")
              .required(true)
@@ -37,46 +63,504 @@ fn main() {
             .help("Output file")
             .short("o")
             .takes_value(true))
+        .arg(Arg::with_name("cells")
+             .help("Choose cell type (bignum is interpreter only)")
+             .long("cells")
+             .takes_value(true)
+             .possible_values(&["i8", "bignum"]))
+        .arg(Arg::with_name("io")
+             .help("Choose how cells are read and printed")
+             .long("io")
+             .takes_value(true)
+             .possible_values(&["bytes", "unicode", "decimal"]))
+        .arg(Arg::with_name("crlf")
+             .long("crlf")
+             .help("Read CRLF and CR line endings as LF"))
+        .arg(Arg::with_name("tape")
+             .help("Choose the interpreter tape (mmap keeps it in TAPE_FILE)")
+             .long("tape")
+             .takes_value(true)
+             .possible_values(&["dense", "sparse", "mmap"])
+             .requires_if("mmap", "TAPE_FILE"))
+        .arg(Arg::with_name("TAPE_FILE")
+             .help("File backing the mmap tape")
+             .long("tape-file")
+             .takes_value(true))
+        .arg(Arg::with_name("cc")
+             .help("C compiler building executables, $CC or the first of cc, clang and gcc \
+                    by default, $CFLAGS are passed to it")
+             .long("cc")
+             .takes_value(true))
+        .arg(Arg::with_name("tape-image")
+             .help("Initialize the tape from a file")
+             .long("tape-image")
+             .takes_value(true))
+        .arg(Arg::with_name("image-format")
+             .help("Format of the tape image (hex images can set the pointer)")
+             .long("image-format")
+             .takes_value(true)
+             .possible_values(&["binary", "hex"]))
+        .arg(Arg::with_name("tape-ptr")
+             .help("Starting position of the pointer")
+             .long("tape-ptr")
+             .takes_value(true))
+        .arg(Arg::with_name("tape-dump")
+             .help("Dump the tape and pointer as hex at exit, also when the run fails")
+             .long("tape-dump")
+             .takes_value(true))
+        .arg(Arg::with_name("tape-size")
+             .help("Number of cells of the tapes of the compiled backends (30000 by default)")
+             .long("tape-size")
+             .takes_value(true))
+        .arg(Arg::with_name("wrap-tape")
+             .long("wrap-tape")
+             .help("Wrap the LLVM tape pointer around the ends of the tape, like the interpreter"))
+        .arg(Arg::with_name("llvm-opt")
+             .help("LLVM optimization level (2 with -O, 0 otherwise)")
+             .long("llvm-opt")
+             .takes_value(true)
+             .possible_values(&["0", "1", "2", "3", "s"]))
+        .arg(Arg::with_name("codegen-opt")
+             .help("LLVM code generation level")
+             .long("codegen-opt")
+             .takes_value(true)
+             .possible_values(&["0", "1", "2", "3"]))
+        .arg(Arg::with_name("passes")
+             .help("Comma separated LLVM passes to run instead of the optimization level")
+             .long("passes")
+             .takes_value(true))
+        .arg(Arg::with_name("time-passes")
+             .long("time-passes")
+             .help("Report the time taken by each LLVM pass"))
+        .arg(Arg::with_name("target")
+             .help("Target triple of the LLVM output (host by default)")
+             .long("target")
+             .takes_value(true))
+        .arg(Arg::with_name("cpu")
+             .help("Target CPU of the LLVM output")
+             .long("cpu")
+             .takes_value(true))
+        .arg(Arg::with_name("features")
+             .help("Target features of the LLVM output, e.g. +sse4.2,-avx")
+             .long("features")
+             .takes_value(true))
+        .arg(Arg::with_name("debug-info")
+             .short("g")
+             .help("Emit LLVM debug info or C #line directives, atoms are only located without -O"))
+        .arg(Arg::with_name("tiered")
+             .help("JIT compile the loops the interpreter enters this many times, with i8 cells")
+             .long("tiered")
+             .takes_value(true))
+        .arg(Arg::with_name("profile-generate")
+             .help("Save the loop counts of an interpreter run to a profile")
+             .long("profile-generate")
+             .takes_value(true))
+        .arg(Arg::with_name("profile-use")
+             .help("Optimize loops with a profile of the same program and -O")
+             .long("profile-use")
+             .takes_value(true))
+        .arg(Arg::with_name("fuel")
+             .help("Stop with exit status 152 after this many loop iterations, in every backend")
+             .long("fuel")
+             .takes_value(true))
+        .arg(Arg::with_name("c-cell-type")
+             .help("C type of the cells of the C backend, also the typed array of the JavaScript tape")
+             .long("c-cell-type")
+             .takes_value(true)
+             .possible_values(&["i8", "u8", "i16", "u16", "i32", "u32"]))
+        .arg(Arg::with_name("c-heap-tape")
+             .long("c-heap-tape")
+             .help("Allocate the C tape with calloc instead of a static array"))
+        .arg(Arg::with_name("c-std")
+             .help("C standard of the C backend output")
+             .long("c-std")
+             .takes_value(true)
+             .possible_values(&["c89", "c99", "c11"]))
+        .arg(Arg::with_name("c-indent")
+             .help("Indent the C output with this many spaces instead of tabs")
+             .long("c-indent")
+             .takes_value(true))
+        .arg(Arg::with_name("c-function")
+             .help("Name of the C function running the program instead of main")
+             .long("c-function")
+             .takes_value(true))
+        .arg(Arg::with_name("c-library")
+             .long("c-library")
+             .requires("c-function")
+             .help("Emit a reentrant C function with I/O callbacks and its header next to OUTPUT"))
+        .arg(Arg::with_name("rust-function")
+             .help("Emit a public Rust function with this name instead of a program")
+             .long("rust-function")
+             .takes_value(true))
+        .arg(Arg::with_name("wasm-io")
+             .help("I/O of the WebAssembly module, WASI or imported env.read and env.write")
+             .long("wasm-io")
+             .takes_value(true)
+             .possible_values(&["wasi", "imports"]))
+        .arg(Arg::with_name("wat")
+             .help("Also write the WebAssembly module as text to this file")
+             .long("wat")
+             .takes_value(true))
+        .arg(Arg::with_name("js-cli")
+             .long("js-cli")
+             .help("Also write a Node script running the JavaScript module to OUTPUT with the \
+                    extension cli.mjs"))
+        .arg(Arg::with_name("jvm-class")
+             .help("Name of the JVM class, the file name of OUTPUT without its extension by default")
+             .long("jvm-class")
+             .takes_value(true))
+        .arg(Arg::with_name("exit-cell")
+             .long("exit-cell")
+             .help("Exit with the value of the current cell at exit (every backend \
+                    exits with 66 on EOF and 152 out of fuel, interpreter errors \
+                    exit with 70 out of bounds, 74 on I/O errors and 75 at the \
+                    loop limit)"))
         .get_matches();
 
     let path = matches.value_of("INPUT").unwrap();
     let buf = slurp_file(path).unwrap();
-    let mut ir = ir::build_ir(&buf).unwrap();
+    let (mut ir, spans) = ir::build_ir_with_spans(&buf).unwrap();
+    let cells = match matches.value_of("cells") {
+        Some("bignum") => CellType::BigInt,
+        _ => CellType::I8,
+    };
     let opt = matches.is_present("opt");
     if opt {
-        ir = opt::run_opts(ir);
+        ir = opt::run_opts_with_cells(ir, cells);
     }
     
     if matches.is_present("ir") {
         println!("{:#?}", ir);
     }
 
+    let io = IoOptions {
+        encoding: match matches.value_of("io") {
+            Some("unicode") => IoEncoding::Unicode,
+            Some("decimal") => IoEncoding::Decimal,
+            _ => IoEncoding::Bytes,
+        },
+        translate_newlines: matches.is_present("crlf"),
+    };
+
+    let mut image = match matches.value_of("tape-image") {
+        Some(path) => {
+            let format = match matches.value_of("image-format") {
+                Some("hex") => ImageFormat::Hex,
+                _ => ImageFormat::Binary,
+            };
+            match TapeImage::load(path, format) {
+                Ok(image) => Some(image),
+                Err(err) => {
+                    eprintln!("Error while loading tape image: {}", err);
+                    process::exit(1);
+                },
+            }
+        },
+        None => None,
+    };
+    if let Some(ptr) = matches.value_of("tape-ptr") {
+        match ptr.parse() {
+            Ok(ptr) => image.get_or_insert_with(TapeImage::default).ptr = ptr,
+            Err(_) => {
+                eprintln!("Invalid tape pointer: {}", ptr);
+                process::exit(1);
+            },
+        }
+    }
+    let profile = matches.value_of("profile-use").map(|path| {
+        match Profile::load(path) {
+            Ok(ref profile) if !profile.matches(&ir) => {
+                eprintln!("Profile {} was recorded on another program or -O", path);
+                process::exit(1);
+            },
+            Ok(profile) => profile,
+            Err(err) => {
+                eprintln!("Error while loading profile: {}", err);
+                process::exit(1);
+            },
+        }
+    });
+    let fuel = matches.value_of("fuel").map(|fuel| {
+        fuel.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid fuel: {}", fuel);
+            process::exit(1);
+        })
+    });
+    let interpreter_options = InterpreterOptions {
+        fuel,
+        profile: matches.value_of("profile-generate"),
+        tiering: matches.value_of("tiered").map(|threshold| {
+            threshold.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid tiering threshold: {}", threshold);
+                process::exit(1);
+            })
+        }),
+    };
+    if interpreter_options.tiering.is_some() && interpreter_options.profile.is_some() {
+        eprintln!("Tiering doesn't profile the native loops");
+        process::exit(1);
+    }
+    let exit_cell = matches.is_present("exit-cell");
+    let llvm_options = LLVMOptions {
+        level: match matches.value_of("llvm-opt") {
+            Some("0") => OptLevel::O0,
+            Some("1") => OptLevel::O1,
+            Some("2") => OptLevel::O2,
+            Some("3") => OptLevel::O3,
+            Some("s") => OptLevel::Size,
+            _ if opt => OptLevel::O2,
+            _ => OptLevel::O0,
+        },
+        codegen_level: matches.value_of("codegen-opt").map_or(3, |level| level.parse().unwrap()),
+        passes: matches.value_of("passes")
+            .map(|passes| passes.split(',').map(String::from).collect()),
+        time_passes: matches.is_present("time-passes"),
+        target: TargetOptions {
+            triple: matches.value_of("target").map(String::from),
+            cpu: matches.value_of("cpu").unwrap_or("").to_owned(),
+            features: matches.value_of("features").unwrap_or("").to_owned(),
+        },
+        tape_size: matches.value_of("tape-size").map(|size| {
+            size.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid tape size: {}", size);
+                process::exit(1);
+            })
+        }),
+        wrap_tape: matches.is_present("wrap-tape"),
+        debug_info: if matches.is_present("debug-info") {
+            Some((path.to_owned(), spans.clone()))
+        } else {
+            None
+        },
+        profile: profile.clone(),
+        fuel,
+        linker: matches.value_of("cc").map(String::from),
+    };
+    let c_options = COptions {
+        backend: CBackendOptions {
+            tape_size: llvm_options.tape_size.unwrap_or(CBackendOptions::default().tape_size),
+            cell_type: match matches.value_of("c-cell-type") {
+                Some("u8") => CCellType::U8,
+                Some("i16") => CCellType::I16,
+                Some("u16") => CCellType::U16,
+                Some("i32") => CCellType::I32,
+                Some("u32") => CCellType::U32,
+                _ => CCellType::I8,
+            },
+            heap_tape: matches.is_present("c-heap-tape"),
+            standard: match matches.value_of("c-std") {
+                Some("c89") => CStandard::C89,
+                Some("c11") => CStandard::C11,
+                _ => CStandard::C99,
+            },
+            indent: match matches.value_of("c-indent") {
+                Some(width) => " ".repeat(width.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid indentation: {}", width);
+                    process::exit(1);
+                })),
+                None => String::from("\t"),
+            },
+            function_name: matches.value_of("c-function").map(String::from),
+            library: matches.is_present("c-library"),
+        },
+        exit_cell,
+        profile: profile.clone(),
+        fuel,
+        source: if matches.is_present("debug-info") {
+            Some((path.to_owned(), buf.clone(), spans))
+        } else {
+            None
+        },
+    };
+    let tape = TapeOptions {
+        image,
+        dump: matches.value_of("tape-dump"),
+    };
+
     match matches.value_of("type") {
+        kind if matches.is_present("debug-info") && !is_c_or_llvm(kind) => {
+            eprintln!("Debug info is only emitted by the C and LLVM backends");
+            process::exit(1);
+        },
+        kind if profile.is_some() && !is_c_or_llvm(kind) => {
+            eprintln!("Profiles are only used by the C and LLVM backends");
+            process::exit(1);
+        },
         Some("interpreter") | None => {
-            let interpreter_backend = backend::Interpreter::new(
-                io::stdin(),
-                io::stdout(),
-                None
-            );
-            if let Err(err) = backend::use_backend(interpreter_backend, &ir) {
-                println!("Interpreting finished with error: {:?}", err);
+            let result = match (cells, matches.value_of("tape")) {
+                (CellType::I8, Some("sparse")) => {
+                    interpret(&ir, SparseTape::<Wrapping<i8>>::default(), io, tape, &interpreter_options)
+                },
+                (CellType::I8, Some("mmap")) => {
+                    let path = matches.value_of("TAPE_FILE").unwrap();
+                    MmapTape::open(path)
+                        .map_err(InterpreterError::IOError)
+                        .and_then(|mmap| interpret(&ir, mmap, io, tape, &interpreter_options))
+                },
+                (CellType::I8, _) => {
+                    interpret(&ir, VecTape::<Wrapping<i8>>::default(), io, tape, &interpreter_options)
+                },
+                (CellType::BigInt, Some("sparse")) => {
+                    interpret(&ir, SparseTape::<BigInt>::default(), io, tape, &interpreter_options)
+                },
+                (CellType::BigInt, Some("mmap")) => {
+                    eprintln!("Memory-mapped tapes only support i8 cells");
+                    process::exit(1);
+                },
+                (CellType::BigInt, _) => {
+                    interpret(&ir, VecTape::<BigInt>::default(), io, tape, &interpreter_options)
+                },
+            };
+            match result {
+                Ok(cell) if exit_cell => process::exit(cell as i32),
+                Ok(_) => {},
+                Err(err) => {
+                    eprintln!("Interpreting finished with error: {:?}", err);
+                    process::exit(err.exit_status());
+                },
             }
         },
+        Some(_) if interpreter_options.profile.is_some() => {
+            eprintln!("Profiles are only generated by the interpreter");
+            process::exit(1);
+        },
+        Some(_) if interpreter_options.tiering.is_some() => {
+            eprintln!("Only the interpreter has tiers");
+            process::exit(1);
+        },
+        Some(_) if cells != CellType::I8 => {
+            eprintln!("Bignum cells are only supported by the interpreter");
+            process::exit(1);
+        },
         Some("c") => {
             let output_path = matches.value_of("OUTPUT").unwrap();
-            if let Err(err) = write_c(output_path, &ir) {
-                println!("Error while writing C file: {}", err);
+            if c_options.backend.library {
+                let header_path = Path::new(output_path).with_extension("h");
+                let result = File::create(&header_path).and_then(|file| {
+                    backend::c::write_library_header(file, &c_options.backend)
+                });
+                if let Err(err) = result {
+                    eprintln!("Error while writing C header: {}", err);
+                    process::exit(1);
+                }
+            }
+            let result = File::create(output_path)
+                .and_then(|file| write_c(file, &ir, io, tape, c_options));
+            if let Err(err) = result {
+                eprintln!("Error while writing C file: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("c-exe") if c_options.backend.library => {
+            eprintln!("C libraries can't be built as executables");
+            process::exit(1);
+        },
+        Some("c-exe") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            let result = c_exe(&ir, io, tape, c_options, output_path, matches.value_of("cc"));
+            if let Err(err) = result {
+                eprintln!("Error while building executable: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("rust") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            let result = write_rust(output_path, &ir, io, tape, &llvm_options, &c_options,
+                                    matches.value_of("rust-function"));
+            if let Err(err) = result {
+                eprintln!("Error while writing Rust file: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("wasm") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            let wasm_io = match matches.value_of("wasm-io") {
+                Some("imports") => WasmIo::Imports,
+                _ => WasmIo::Wasi,
+            };
+            let result = build_wasm(&ir, io, tape, wasm_io, &llvm_options, &c_options)
+                .and_then(|module| {
+                    fs::write(output_path, module.binary())?;
+                    match matches.value_of("wat") {
+                        Some(text_path) => fs::write(text_path, module.text()),
+                        None => Ok(()),
+                    }
+                });
+            if let Err(err) = result {
+                eprintln!("Error while writing WebAssembly module: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("js") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            let mut result = write_js(output_path, &ir, io, tape, &llvm_options, &c_options);
+            if result.is_ok() && matches.is_present("js-cli") {
+                let output_path = Path::new(output_path);
+                let module_path = format!("./{}", output_path.file_name().unwrap().to_string_lossy());
+                result = File::create(output_path.with_extension("cli.mjs")).and_then(|file| {
+                    backend::js::write_node_cli(file, &module_path, exit_cell)
+                });
+            }
+            if let Err(err) = result {
+                eprintln!("Error while writing JavaScript module: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("python") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            if let Err(err) = write_python(output_path, &ir, io, tape, &llvm_options, &c_options) {
+                eprintln!("Error while writing Python file: {}", err);
+                process::exit(1);
+            }
+        },
+        Some("jvm") => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            let class_name = match matches.value_of("jvm-class") {
+                Some(name) => name.to_owned(),
+                None => Path::new(output_path).file_stem()
+                    .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+            };
+            let result = build_class(&ir, io, tape, class_name, &llvm_options, &c_options)
+                .and_then(|class| fs::write(output_path, class));
+            if let Err(err) = result {
+                eprintln!("Error while writing class file: {}", err);
+                process::exit(1);
             }
         },
         Some("jit") => {
-            if let Err(err) = llvm_jit(&ir, opt) {
-                println!("LLVM Error: {:?}", err);
+            match llvm_jit(&ir, io, tape, llvm_options) {
+                Ok(cell) if exit_cell => process::exit(cell as i32),
+                Ok(_) => {},
+                Err(ref err) if err.as_bytes() == OUT_OF_FUEL_ERROR.as_bytes() => {
+                    eprintln!("JIT stopped: {}", OUT_OF_FUEL_ERROR);
+                    process::exit(OUT_OF_FUEL_STATUS);
+                },
+                Err(ref err) if err.as_bytes() == EOF_ERROR.as_bytes() => {
+                    eprintln!("JIT stopped: {}", EOF_ERROR);
+                    process::exit(EOF_STATUS);
+                },
+                Err(err) => {
+                    eprintln!("LLVM Error: {:?}", err);
+                    process::exit(1);
+                },
             }
-        }
-        _ => unreachable!()
+        },
+        Some(kind) => {
+            let output_path = matches.value_of("OUTPUT").unwrap();
+            if let Err(err) = llvm_aot(&ir, io, tape, llvm_options, kind, output_path, exit_cell) {
+                eprintln!("LLVM Error: {}", err);
+                process::exit(1);
+            }
+        },
     }
 }
 
+// the backends taking debug info and profiles
+fn is_c_or_llvm(kind: Option<&str>) -> bool {
+    matches!(kind, Some("c") | Some("c-exe") | Some("jit") | Some("obj") | Some("asm") |
+                   Some("bc") | Some("ll") | Some("exe"))
+}
+
 fn slurp_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
     let mut file = File::open(path)?;
     let mut buf = Vec::new();
@@ -84,19 +568,543 @@ fn slurp_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
     Ok(buf)
 }
 
-fn write_c<P: AsRef<Path>>(path: P, ir: &Vec<ir::Atom>) -> io::Result<()> {
-    let output_file = File::create(path)?;
-    let c_backend = backend::CBackend::new(output_file);
+struct TapeOptions<'a> {
+    image: Option<TapeImage>,
+    dump: Option<&'a str>,
+}
+
+struct InterpreterOptions<'a> {
+    fuel: Option<u64>,
+    profile: Option<&'a str>,
+    tiering: Option<usize>,
+}
+
+fn interpret<T: Tape>(ir: &Vec<Atom>, tape: T, io: IoOptions, options: TapeOptions,
+                      interpreter_options: &InterpreterOptions) -> Result<u8, InterpreterError> {
+    let mut interpreter_backend = backend::Interpreter::with_tape(
+        io::stdin(),
+        io::stdout(),
+        tape,
+        None
+    ).with_io(io);
+    if let Some(image) = options.image {
+        interpreter_backend = interpreter_backend.with_image(image);
+    }
+    if let Some(dump) = options.dump {
+        interpreter_backend = interpreter_backend.with_dump(dump);
+    }
+    if let Some(profile) = interpreter_options.profile {
+        interpreter_backend = interpreter_backend.with_profile(profile);
+    }
+    if let Some(fuel) = interpreter_options.fuel {
+        interpreter_backend = interpreter_backend.with_fuel(fuel);
+    }
+    if let Some(threshold) = interpreter_options.tiering {
+        interpreter_backend = interpreter_backend.with_tiering(threshold);
+    }
+    backend::use_backend(interpreter_backend, ir)
+}
+
+struct COptions {
+    backend: CBackendOptions,
+    exit_cell: bool,
+    profile: Option<Profile>,
+    fuel: Option<u64>,
+    // source path, text and spans
+    source: Option<(String, Vec<u8>, Vec<AtomSpan>)>,
+}
+
+fn write_c(output_file: File, ir: &Vec<ir::Atom>, io: IoOptions, tape: TapeOptions,
+           options: COptions) -> io::Result<()> {
+    let mut c_backend = backend::CBackend::new(output_file)
+        .with_options(options.backend)
+        .with_io(io);
+    if let Some(image) = tape.image {
+        c_backend = c_backend.with_image(image);
+    }
+    if let Some(dump) = tape.dump {
+        c_backend = c_backend.with_dump(dump);
+    }
+    if options.exit_cell {
+        c_backend = c_backend.with_exit_cell();
+    }
+    if let Some(profile) = options.profile {
+        c_backend = c_backend.with_profile(profile);
+    }
+    if let Some(fuel) = options.fuel {
+        c_backend = c_backend.with_fuel(fuel);
+    }
+    if let Some((source_path, source, spans)) = options.source {
+        c_backend = c_backend.with_source(source_path, source, spans);
+    }
     backend::use_backend(c_backend, ir)
 }
 
-fn llvm_jit(ir: &Vec<Atom>, opt: bool) -> Result<(), CString> {
-    let llvm_backend = backend::LLVMBackend::new();
-    let mut llvm_brainfuck_mod = backend::use_backend(llvm_backend, ir)?;
-    if opt {
-        llvm_brainfuck_mod.optimize();
+// shares the tape size with LLVM and the fuel and exit cell with C
+fn write_rust<P: AsRef<Path>>(path: P, ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions,
+                             llvm_options: &LLVMOptions, c_options: &COptions,
+                             function_name: Option<&str>) -> io::Result<()> {
+    if tape.dump.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "the Rust backend doesn't dump the tape"));
+    }
+    let output_file = File::create(path)?;
+    let mut rust_backend = backend::RustBackend::new(output_file).with_io(io);
+    if let Some(size) = llvm_options.tape_size {
+        rust_backend = rust_backend.with_tape_size(size);
+    }
+    if let Some(image) = tape.image {
+        rust_backend = rust_backend.with_image(image);
+    }
+    if c_options.exit_cell {
+        rust_backend = rust_backend.with_exit_cell();
+    }
+    if let Some(fuel) = c_options.fuel {
+        rust_backend = rust_backend.with_fuel(fuel);
+    }
+    if let Some(name) = function_name {
+        rust_backend = rust_backend.with_function(name);
+    }
+    backend::use_backend(rust_backend, ir)
+}
+
+// like the Rust backend, shares the tape size with LLVM and the fuel and exit
+// cell with C
+fn build_wasm(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, wasm_io: WasmIo,
+              llvm_options: &LLVMOptions, c_options: &COptions) -> io::Result<WasmModule> {
+    if tape.dump.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "the WebAssembly backend doesn't dump the tape"));
+    }
+    let mut wasm_backend = backend::WasmBackend::new(wasm_io).with_io(io);
+    if let Some(size) = llvm_options.tape_size {
+        wasm_backend = wasm_backend.with_tape_size(size);
+    }
+    if let Some(image) = tape.image {
+        wasm_backend = wasm_backend.with_image(image);
+    }
+    if c_options.exit_cell {
+        wasm_backend = wasm_backend.with_exit_cell();
+    }
+    if let Some(fuel) = c_options.fuel {
+        wasm_backend = wasm_backend.with_fuel(fuel);
+    }
+    backend::use_backend(wasm_backend, ir)
+}
+
+// shares the tape size with LLVM and the cell type and fuel with C
+fn write_js<P: AsRef<Path>>(path: P, ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions,
+                           llvm_options: &LLVMOptions, c_options: &COptions) -> io::Result<()> {
+    if tape.dump.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "the JavaScript backend doesn't dump the tape"));
+    }
+    let output_file = File::create(path)?;
+    let mut js_backend = backend::JsBackend::new(output_file)
+        .with_io(io)
+        .with_cell_type(c_options.backend.cell_type);
+    if let Some(size) = llvm_options.tape_size {
+        js_backend = js_backend.with_tape_size(size);
+    }
+    if let Some(image) = tape.image {
+        js_backend = js_backend.with_image(image);
+    }
+    if let Some(fuel) = c_options.fuel {
+        js_backend = js_backend.with_fuel(fuel);
+    }
+    backend::use_backend(js_backend, ir)
+}
+
+// like the Rust backend, shares the tape size with LLVM and the fuel and exit
+// cell with C
+fn write_python<P: AsRef<Path>>(path: P, ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions,
+                               llvm_options: &LLVMOptions, c_options: &COptions)
+                               -> io::Result<()> {
+    if tape.dump.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "the Python backend doesn't dump the tape"));
+    }
+    let output_file = File::create(path)?;
+    let mut python_backend = backend::PythonBackend::new(output_file).with_io(io);
+    if let Some(size) = llvm_options.tape_size {
+        python_backend = python_backend.with_tape_size(size);
+    }
+    if let Some(image) = tape.image {
+        python_backend = python_backend.with_image(image);
+    }
+    if c_options.exit_cell {
+        python_backend = python_backend.with_exit_cell();
+    }
+    if let Some(fuel) = c_options.fuel {
+        python_backend = python_backend.with_fuel(fuel);
+    }
+    backend::use_backend(python_backend, ir)
+}
+
+// like the Rust backend, shares the tape size with LLVM and the fuel and exit
+// cell with C
+fn build_class(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, class_name: String,
+               llvm_options: &LLVMOptions, c_options: &COptions) -> io::Result<Vec<u8>> {
+    if tape.dump.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "the JVM backend doesn't dump the tape"));
+    }
+    let mut jvm_backend = backend::JvmBackend::new(class_name).with_io(io);
+    if let Some(size) = llvm_options.tape_size {
+        jvm_backend = jvm_backend.with_tape_size(size);
+    }
+    if let Some(image) = tape.image {
+        jvm_backend = jvm_backend.with_image(image);
+    }
+    if c_options.exit_cell {
+        jvm_backend = jvm_backend.with_exit_cell();
+    }
+    if let Some(fuel) = c_options.fuel {
+        jvm_backend = jvm_backend.with_fuel(fuel);
+    }
+    backend::use_backend(jvm_backend, ir)
+}
+
+struct LLVMOptions {
+    level: OptLevel,
+    codegen_level: u32,
+    passes: Option<Vec<String>>,
+    time_passes: bool,
+    target: TargetOptions,
+    tape_size: Option<usize>,
+    wrap_tape: bool,
+    // source path and spans
+    debug_info: Option<(String, Vec<AtomSpan>)>,
+    profile: Option<Profile>,
+    fuel: Option<u64>,
+    // C compiler linking executables
+    linker: Option<String>,
+}
+
+fn llvm_compile(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, options: &LLVMOptions)
+    -> Result<LLVMBrainfuckModule, CString> {
+    let mut llvm_backend = backend::LLVMBackend::new()
+        .with_io(io)
+        .with_target(options.target.clone());
+    if let Some(size) = options.tape_size {
+        llvm_backend = llvm_backend.with_tape_size(size);
+    }
+    if options.wrap_tape {
+        llvm_backend = llvm_backend.with_wrap();
+    }
+    if let Some((ref source, ref spans)) = options.debug_info {
+        llvm_backend = llvm_backend.with_debug_info(source.as_str(), spans.clone());
+    }
+    if let Some(ref profile) = options.profile {
+        llvm_backend = llvm_backend.with_profile(profile.clone());
+    }
+    if let Some(fuel) = options.fuel {
+        llvm_backend = llvm_backend.with_fuel(fuel);
+    }
+    if let Some(image) = tape.image {
+        llvm_backend = llvm_backend.with_image(image);
+    }
+    if let Some(dump) = tape.dump {
+        llvm_backend = llvm_backend.with_dump(dump);
+    }
+    backend::use_backend(llvm_backend, ir)
+}
+
+fn llvm_optimize(llvm_brainfuck_mod: &mut LLVMBrainfuckModule, options: &LLVMOptions)
+    -> Result<(), CString> {
+    llvm_brainfuck_mod.set_codegen_level(options.codegen_level);
+    let timings = match options.passes {
+        Some(ref passes) => llvm_brainfuck_mod.run_passes(passes)?,
+        None => llvm_brainfuck_mod.optimize_level(options.level),
+    };
+
+    if options.time_passes {
+        for timing in timings {
+            let micros = timing.duration.as_secs() * 1_000_000
+                + u64::from(timing.duration.subsec_nanos()) / 1_000;
+            eprintln!("[time] {}: {} us", timing.pass, micros);
+        }
     }
-    eprintln!("[info] Compilation done.");
-    llvm_brainfuck_mod.jit_run();
     Ok(())
 }
+
+fn llvm_jit(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, options: LLVMOptions)
+    -> Result<u8, CString> {
+    let mut llvm_brainfuck_mod = llvm_compile(ir, io, tape, &options)?;
+    llvm_optimize(&mut llvm_brainfuck_mod, &options)?;
+    eprintln!("[info] Compilation done.");
+    llvm_brainfuck_mod.jit_run()
+}
+
+// `kind` is one of the ahead-of-time output types, executables are linked by
+// the system C compiler
+fn llvm_aot(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, options: LLVMOptions,
+            kind: &str, output_path: &str, exit_cell: bool) -> Result<(), String> {
+    let llvm_error = |err: CString| err.to_string_lossy().into_owned();
+
+    let mut llvm_brainfuck_mod = llvm_compile(ir, io, tape, &options)
+        .map_err(llvm_error)?;
+    llvm_brainfuck_mod.add_main(exit_cell);
+    llvm_optimize(&mut llvm_brainfuck_mod, &options).map_err(llvm_error)?;
+
+    let format = match kind {
+        "asm" => OutputFormat::Assembly,
+        "bc" => OutputFormat::Bitcode,
+        "ll" => OutputFormat::LlvmIr,
+        _ => OutputFormat::Object,
+    };
+    if kind != "exe" {
+        return llvm_brainfuck_mod.write_output(output_path, format).map_err(llvm_error);
+    }
+
+    let (object_path, _) = create_temp_file("o")
+        .map_err(|err| format!("could not create object file: {}", err))?;
+    let cc = options.linker.as_deref();
+    let result = llvm_brainfuck_mod.write_output(&object_path, format)
+        .map_err(llvm_error)
+        .and_then(|()| {
+            run_c_compiler(cc, &[object_path.as_os_str(), "-o".as_ref(), output_path.as_ref()])
+                .map_err(|err| format!("linking failed: {}", err))
+        });
+    let _ = fs::remove_file(&object_path);
+    result
+}
+
+// creates a file of the temporary directory with a random name, so that it
+// can't be guessed and created beforehand by someone else
+fn create_temp_file(extension: &str) -> io::Result<(PathBuf, File)> {
+    loop {
+        let suffix = RandomState::new().build_hasher().finish();
+        let name = format!("bfc-{}-{:016x}.{}", process::id(), suffix, extension);
+        let path = env::temp_dir().join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn c_exe(ir: &Vec<Atom>, io: IoOptions, tape: TapeOptions, options: COptions,
+         output_path: &str, cc: Option<&str>) -> Result<(), String> {
+    let (c_path, c_file) = create_temp_file("c")
+        .map_err(|err| format!("could not create C file: {}", err))?;
+    let result = write_c(c_file, ir, io, tape, options)
+        .map_err(|err| format!("could not write C file: {}", err))
+        .and_then(|()| {
+            run_c_compiler(cc, &[c_path.as_os_str(), "-o".as_ref(), output_path.as_ref()])
+        });
+    let _ = fs::remove_file(&c_path);
+    result
+}
+
+// runs `cc`, `$CC` or the first of cc, clang and gcc found, with `$CFLAGS`
+// before `args`, the diagnostics of the compiler go to stderr
+fn run_c_compiler(cc: Option<&str>, args: &[&OsStr]) -> Result<(), String> {
+    let candidates: Vec<String> = match cc.map(String::from).or_else(|| env::var("CC").ok()) {
+        Some(cc) => vec![cc],
+        None => ["cc", "clang", "gcc"].iter().map(|cc| cc.to_string()).collect(),
+    };
+    let cflags = env::var("CFLAGS").unwrap_or_default();
+
+    for candidate in &candidates {
+        // `$CC` may come with its own flags
+        let mut words = candidate.split_whitespace();
+        let program = match words.next() {
+            Some(program) => program,
+            None => continue,
+        };
+        let output = Command::new(program)
+            .args(words)
+            .args(cflags.split_whitespace())
+            .args(args)
+            .output();
+        match output {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(format!("could not run {}: {}", program, err)),
+            Ok(output) => {
+                let _ = io::stderr().write_all(&output.stdout);
+                let _ = io::stderr().write_all(&output.stderr);
+                if output.status.success() {
+                    return Ok(());
+                }
+                return Err(format!("{} failed ({})", program, output.status));
+            },
+        }
+    }
+    Err(format!("no C compiler found, tried {}", candidates.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // `$CC` and `$CFLAGS` are only changed by one test at a time
+    static COMPILER_ENV: Mutex<()> = Mutex::new(());
+
+    fn llvm_options() -> LLVMOptions {
+        LLVMOptions {
+            level: OptLevel::O1,
+            codegen_level: 3,
+            passes: None,
+            time_passes: false,
+            target: TargetOptions::default(),
+            tape_size: None,
+            wrap_tape: false,
+            debug_info: None,
+            profile: None,
+            fuel: None,
+            linker: None,
+        }
+    }
+
+    fn no_tape() -> TapeOptions<'static> {
+        TapeOptions { image: None, dump: None }
+    }
+
+    // runs the executable on `input`, returns its output and exit status
+    fn run_exe(path: &Path, input: &[u8]) -> (Vec<u8>, Option<i32>) {
+        let mut child = Command::new(path)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        (output.stdout, output.status.code())
+    }
+
+    #[test]
+    fn llvm_aot_outputs() {
+        let _env = COMPILER_ENV.lock().unwrap();
+        let ir = ir::build_ir(b",+.").unwrap();
+        let (path, _) = create_temp_file("out").unwrap();
+        let output_path = path.to_str().unwrap();
+        let elf = cfg!(target_os = "linux");
+
+        for &kind in &["ll", "bc", "asm", "obj"] {
+            llvm_aot(&ir, IoOptions::default(), no_tape(), llvm_options(), kind, output_path,
+                     false).unwrap();
+            let output = fs::read(&path).unwrap();
+            let text = String::from_utf8_lossy(&output);
+            match kind {
+                "ll" => assert!(text.contains("define i32 @main()")),
+                "bc" => assert!(output.starts_with(b"BC\xC0\xDE")),
+                "asm" => assert!(text.contains("brainfuck:") && text.contains("main:")),
+                _ => assert!(!elf || output.starts_with(b"\x7FELF")),
+            }
+        }
+
+        // executables are linked by the C compiler, when there is one
+        for &(exit_cell, status) in &[(false, 0), (true, i32::from(b'b'))] {
+            let result = llvm_aot(&ir, IoOptions::default(), no_tape(), llvm_options(), "exe",
+                                  output_path, exit_cell);
+            match result {
+                Err(ref err) if err.contains("no C compiler found") => break,
+                result => result.unwrap(),
+            }
+            assert_eq!(run_exe(&path, b"a"), (b"b".to_vec(), Some(status)));
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn llvm_targets() {
+        let ir = ir::build_ir(b"+.").unwrap();
+        let (path, _) = create_temp_file("s").unwrap();
+        let output_path = path.to_str().unwrap();
+        let arm = |cpu: &str, features: &str| {
+            let target = TargetOptions {
+                triple: Some(String::from("armv7-unknown-linux-gnueabihf")),
+                cpu: cpu.to_owned(),
+                features: features.to_owned(),
+            };
+            LLVMOptions { target, ..llvm_options() }
+        };
+
+        // LLVM may be built without the ARM target
+        let result = llvm_aot(&ir, IoOptions::default(), no_tape(), arm("", ""), "ll",
+                              output_path, false);
+        if result.is_ok() {
+            let code = fs::read_to_string(&path).unwrap();
+            assert!(code.contains("target triple = \"armv7-unknown-linux-gnueabihf\""));
+
+            for &(cpu, features, directive) in &[
+                ("", "", "\t.fpu\tvfpv3\n"),
+                ("cortex-a9", "", "\t.cpu\tcortex-a9\n"),
+                ("", "+neon", "\t.fpu\tneon\n"),
+            ] {
+                llvm_aot(&ir, IoOptions::default(), no_tape(), arm(cpu, features), "asm",
+                         output_path, false).unwrap();
+                assert!(fs::read_to_string(&path).unwrap().contains(directive), "{}", directive);
+            }
+
+            // the JIT only runs code for the host
+            let module = llvm_compile(&ir, IoOptions::default(), no_tape(), &arm("", ""));
+            assert!(module.unwrap().jit_run().is_err());
+        }
+
+        let invalid = TargetOptions {
+            triple: Some(String::from("not-a-triple")),
+            ..TargetOptions::default()
+        };
+        let options = LLVMOptions { target: invalid, ..llvm_options() };
+        assert!(llvm_aot(&ir, IoOptions::default(), no_tape(), options, "obj", output_path,
+                         false).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn c_compilers() {
+        let _env = COMPILER_ENV.lock().unwrap();
+        let saved: Vec<_> = ["CC", "CFLAGS"].iter().map(|var| (var, env::var_os(var))).collect();
+        env::remove_var("CC");
+        env::remove_var("CFLAGS");
+
+        let (exe_path, _) = create_temp_file("exe").unwrap();
+        let output_path = exe_path.to_str().unwrap();
+        let c_options = || COptions {
+            backend: CBackendOptions::default(),
+            exit_cell: true,
+            profile: None,
+            fuel: None,
+            source: None,
+        };
+        let ir = ir::build_ir(b",+.").unwrap();
+        let c_exe = |cc| c_exe(&ir, IoOptions::default(), no_tape(), c_options(), output_path, cc);
+
+        // `--cc` comes before `$CC`
+        env::set_var("CC", "bfc-missing-cc");
+        assert_eq!(c_exe(None).unwrap_err(), "no C compiler found, tried bfc-missing-cc");
+        assert_eq!(c_exe(Some("bfc-other-cc")).unwrap_err(),
+                   "no C compiler found, tried bfc-other-cc");
+        env::remove_var("CC");
+
+        // `$CC` and `$CFLAGS` may both add flags
+        if c_exe(Some("cc")).is_ok() {
+            assert_eq!(run_exe(&exe_path, b"a"), (b"b".to_vec(), Some(i32::from(b'b'))));
+
+            let (c_path, mut c_file) = create_temp_file("c").unwrap();
+            c_file.write_all(b"#if !defined(BFC_CC) || !defined(BFC_CFLAGS)\n\
+                               #error missing flags\n\
+                               #endif\n\
+                               int main(void) { return 0; }\n").unwrap();
+            let args = [c_path.as_os_str(), "-o".as_ref(), exe_path.as_os_str()];
+            assert!(run_c_compiler(None, &args).is_err());
+            env::set_var("CFLAGS", "-DBFC_CFLAGS");
+            assert!(run_c_compiler(Some("cc -DBFC_CC"), &args).is_ok());
+            env::set_var("CC", "cc -DBFC_CC");
+            assert!(run_c_compiler(None, &args).is_ok());
+            let _ = fs::remove_file(&c_path);
+        }
+
+        for (var, value) in saved {
+            match value {
+                Some(value) => env::set_var(var, value),
+                None => env::remove_var(var),
+            }
+        }
+        let _ = fs::remove_file(&exe_path);
+    }
+}
@@ -1,78 +1,158 @@
+// constants are kept wide enough for unbounded cells, backends with
+// fixed-size cells truncate them to their own width
+pub type Value = i64;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Atom {
     MovePtr(isize),
-    SetValue(i8, isize),
-    IncValue(i8, isize),
+    SetValue(Value, isize),
+    IncValue(Value, isize),
     Print(isize),
     Read(isize),
-    Multiply(i8, isize), // factor, offset
+    Multiply(Value, isize), // factor, offset
     Loop(Vec<Atom>),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellType {
+    I8,
+    BigInt,
+}
+
+impl CellType {
+    pub fn wraps(self) -> bool {
+        match self {
+            CellType::I8 => true,
+            CellType::BigInt => false,
+        }
+    }
+
+    pub fn normalize(self, value: Value) -> Value {
+        match self {
+            CellType::I8 => value as i8 as Value,
+            CellType::BigInt => value,
+        }
+    }
+
+    // None if the result can't be represented by a `Value`
+    pub fn add(self, a: Value, b: Value) -> Option<Value> {
+        match self {
+            CellType::I8 => Some(self.normalize(a.wrapping_add(b))),
+            CellType::BigInt => a.checked_add(b),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ParenError {
     RightMissing(usize),
     LeftMissing(usize),
 }
 
+// Position of an atom in the source, `line` and `column` are the ones of
+// `start` and begin at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+// Spans of a freshly built IR, with the same order and nesting as the atoms,
+// the optimizations don't keep them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtomSpan {
+    pub span: Span,
+    pub sub: Vec<AtomSpan>,
+}
+
+pub fn spans_match(ir: &[Atom], spans: &[AtomSpan]) -> bool {
+    ir.len() == spans.len() && ir.iter().zip(spans).all(|(atom, span)| {
+        match *atom {
+            Atom::Loop(ref sub) => spans_match(sub, &span.sub),
+            _ => span.sub.is_empty(),
+        }
+    })
+}
+
 struct IRBuilder {
     ir: Vec<Atom>,
-    loops: Vec<(usize, Vec<Atom>)>,
+    spans: Vec<AtomSpan>,
+    loops: Vec<(Span, Vec<Atom>, Vec<AtomSpan>)>,
 }
 
 impl IRBuilder {
     fn new() -> Self {
         IRBuilder {
             ir: Vec::new(),
+            spans: Vec::new(),
             loops: Vec::new(),
         }
     }
 
-    fn push_atom(&mut self, atom: Atom) {
-        if let Some(&mut (_, ref mut current_loop)) = self.loops.last_mut() {
+    fn push_atom(&mut self, atom: Atom, span: AtomSpan) {
+        if let Some(&mut (_, ref mut current_loop, ref mut spans)) = self.loops.last_mut() {
             current_loop.push(atom);
+            spans.push(span);
         } else {
             self.ir.push(atom);
+            self.spans.push(span);
         }
     }
 
-    fn start_loop(&mut self, pos: usize) {
-        self.loops.push((pos, Vec::new()));
+    fn start_loop(&mut self, span: Span) {
+        self.loops.push((span, Vec::new(), Vec::new()));
     }
 
     fn end_loop(&mut self, pos: usize) -> Result<(), ParenError> {
-        if let Some((_, last_loop)) = self.loops.pop() {
-            self.push_atom(Atom::Loop(last_loop));
+        if let Some((span, last_loop, spans)) = self.loops.pop() {
+            let span = Span { end: pos + 1, ..span };
+            self.push_atom(Atom::Loop(last_loop), AtomSpan { span, sub: spans });
             Ok(())
         } else {
             Err(ParenError::LeftMissing(pos))
         }
     }
 
-    fn collect(self) -> Result<Vec<Atom>, ParenError> {
-        if let Some(&(pos, _)) = self.loops.first() {
-            Err(ParenError::RightMissing(pos))
+    fn collect(self) -> Result<(Vec<Atom>, Vec<AtomSpan>), ParenError> {
+        if let Some(&(span, _, _)) = self.loops.first() {
+            Err(ParenError::RightMissing(span.start))
         } else {
-            Ok(self.ir)
+            Ok((self.ir, self.spans))
         }
     }
 }
 
 pub fn build_ir(input: &[u8]) -> Result<Vec<Atom>, ParenError> {
+    build_ir_with_spans(input).map(|(ir, _)| ir)
+}
+
+pub fn build_ir_with_spans(input: &[u8]) -> Result<(Vec<Atom>, Vec<AtomSpan>), ParenError> {
     let mut ir_builder = IRBuilder::new();
+    let (mut line, mut column) = (1, 1);
 
     for (pos, c) in input.into_iter().enumerate() {
+        let span = Span { start: pos, end: pos + 1, line, column };
+        let leaf = AtomSpan { span, sub: Vec::new() };
         match *c {
-            b'+' => ir_builder.push_atom(Atom::IncValue(1, 0)),
-            b'-' => ir_builder.push_atom(Atom::IncValue(-1, 0)),
-            b'<' => ir_builder.push_atom(Atom::MovePtr(-1)),
-            b'>' => ir_builder.push_atom(Atom::MovePtr(1)),
-            b'.' => ir_builder.push_atom(Atom::Print(0)),
-            b',' => ir_builder.push_atom(Atom::Read(0)),
-            b'[' => ir_builder.start_loop(pos),
+            b'+' => ir_builder.push_atom(Atom::IncValue(1, 0), leaf),
+            b'-' => ir_builder.push_atom(Atom::IncValue(-1, 0), leaf),
+            b'<' => ir_builder.push_atom(Atom::MovePtr(-1), leaf),
+            b'>' => ir_builder.push_atom(Atom::MovePtr(1), leaf),
+            b'.' => ir_builder.push_atom(Atom::Print(0), leaf),
+            b',' => ir_builder.push_atom(Atom::Read(0), leaf),
+            b'[' => ir_builder.start_loop(span),
             b']' => ir_builder.end_loop(pos)?,
             _ => {}
         }
+
+        if *c == b'\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
     } 
     ir_builder.collect()
 }
@@ -1,7 +1,12 @@
 extern crate itertools;
 #[cfg(test)]
 extern crate quickcheck;
+#[cfg(test)]
+extern crate wasmi;
 extern crate llvm_sys as llvm;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate memmap;
 
 pub mod ir;
 pub mod opt;
@@ -10,9 +15,16 @@ pub mod backend;
 #[cfg(test)]
 mod tests {
     use super::{ir, backend, opt};
-    use ir::Atom;
+    use ir::{Atom, CellType};
+    use backend::{IoEncoding, IoOptions, TapeImage, Profile, LoopCounts};
+    use backend::{CBackendOptions, CCellType, CStandard};
+    use backend::llvm::{OptLevel, OutputFormat};
+    use backend::interpreter::{Tape, VecTape, SparseTape, MmapTape};
+    use num_bigint::BigInt;
     use quickcheck::{quickcheck, TestResult};
     use std::io::Cursor;
+    use std::num::Wrapping;
+    use std::{env, fs, process};
 
     const LOOP_LIMIT: usize = 255 * 4;
 
@@ -34,6 +46,45 @@ mod tests {
         }
     }
 
+    fn get_tape_output<T: Tape>(ir: &Vec<Atom>, tape: T) -> Result<Vec<u8>, String> {
+        let mut output_buf = Cursor::new(Vec::<u8>::new());
+
+        let result = {
+            let interpreter = backend::Interpreter::with_tape(
+                Cursor::new(vec![]),
+                &mut output_buf,
+                tape,
+                Some(LOOP_LIMIT)
+            );
+            backend::use_backend(interpreter, ir)
+        };
+
+        match result {
+            Ok(_) => Ok(output_buf.into_inner()),
+            Err(err) => Err(format!("{:?}", err)),
+        }
+    }
+
+    fn get_bignum_output(ir: &Vec<Atom>, input: &Vec<u8>, io: IoOptions)
+        -> Result<Vec<u8>, String> {
+        let mut output_buf = Cursor::new(Vec::<u8>::new());
+
+        let result = {
+            let interpreter = backend::Interpreter::with_tape(
+                Cursor::new(input),
+                &mut output_buf,
+                VecTape::<BigInt>::default(),
+                Some(LOOP_LIMIT)
+            ).with_io(io);
+            backend::use_backend(interpreter, ir)
+        };
+
+        match result {
+            Ok(_) => Ok(output_buf.into_inner()),
+            Err(err) => Err(format!("{:?}", err)),
+        }
+    }
+
     #[test]
     fn quickcheck_opt_no_change() {
         fn opt_no_change(prog: Vec<u8>, input: Vec<u8>) -> TestResult {
@@ -75,4 +126,590 @@ mod tests {
 
         quickcheck(opt_idempotent as fn(Vec<u8>) -> TestResult);
     }
+
+    #[test]
+    fn quickcheck_bignum_opt_no_change() {
+        fn opt_no_change(prog: Vec<u8>, input: Vec<u8>) -> TestResult {
+            let ir = if let Ok(ir) = ir::build_ir(&prog) {
+                ir
+            } else {
+                return TestResult::discard();
+            };
+
+            let opt_ir = opt::run_opts_with_cells(ir.clone(), CellType::BigInt);
+            let io = IoOptions { encoding: IoEncoding::Decimal, translate_newlines: false };
+            let normal_output = get_bignum_output(&ir, &input, io);
+            let opt_output = get_bignum_output(&opt_ir, &input, io);
+
+            TestResult::from_bool(normal_output == opt_output)
+        }
+
+        quickcheck(opt_no_change as fn(Vec<u8>, Vec<u8>) -> TestResult);
+    }
+
+    #[test]
+    fn bignum_cells_dont_wrap() {
+        let prog = "-.>++++++++++++++++[>++++++++++++++++<-]>+.";
+        let ir = opt::run_opts_with_cells(
+            ir::build_ir(prog.as_bytes()).unwrap(),
+            CellType::BigInt
+        );
+
+        let io = IoOptions { encoding: IoEncoding::Decimal, translate_newlines: false };
+        let output = get_bignum_output(&ir, &vec![], io);
+        assert_eq!(output, Ok(b"-1\n257\n".to_vec()));
+
+        let output = get_bignum_output(&ir, &vec![], IoOptions::default());
+        assert_eq!(output, Ok(vec![255, 1]));
+    }
+
+    #[test]
+    fn io_encodings() {
+        let ir = ir::build_ir(b",.,.,.,.").unwrap();
+        let io = IoOptions { encoding: IoEncoding::Unicode, translate_newlines: false };
+        let input = "é€😀\u{FFFD}".as_bytes().to_vec();
+        assert_eq!(get_bignum_output(&ir, &input, io), Ok(input));
+
+        let ir = ir::build_ir(b",.,.,.").unwrap();
+        let output = get_bignum_output(&ir, &b"\xffa\xe2\x82".to_vec(), io);
+        assert_eq!(output, Ok("\u{FFFD}a\u{FFFD}".as_bytes().to_vec()));
+
+        let ir = ir::build_ir(b",.,.,.,.").unwrap();
+        let io = IoOptions { encoding: IoEncoding::Decimal, translate_newlines: false };
+        let input = b" 12\n-3 \t4x\n99999999999999999999 ".to_vec();
+        let output = get_bignum_output(&ir, &input, io);
+        assert_eq!(output, Ok(b"12\n-3\n4\n99999999999999999999\n".to_vec()));
+
+        let ir = ir::build_ir(b",.,.,.,.,.").unwrap();
+        let io = IoOptions { encoding: IoEncoding::Bytes, translate_newlines: true };
+        let output = get_bignum_output(&ir, &b"a\r\nb\rc".to_vec(), io);
+        assert_eq!(output, Ok(b"a\nb\nc".to_vec()));
+    }
+
+    #[test]
+    fn tapes() {
+        let ir = ir::build_ir(b"<++++++[<++++++++>-]<.>>.").unwrap();
+
+        let output = get_tape_output(&ir, VecTape::<Wrapping<i8>>::default());
+        assert_eq!(output, Ok(vec![b'0', 0]));
+
+        let output = get_tape_output(&ir, SparseTape::<Wrapping<i8>>::default());
+        assert_eq!(output, Ok(vec![b'0', 0]));
+
+        let far_ir = vec![
+            Atom::MovePtr(isize::MAX),
+            Atom::IncValue(1, 0),
+            Atom::MovePtr(isize::MAX),
+            Atom::Print(-isize::MAX),
+        ];
+        let output = get_tape_output(&far_ir, SparseTape::<Wrapping<i8>>::default());
+        assert_eq!(output, Ok(vec![1]));
+
+        let path = env::temp_dir().join(format!("bfc-tape-test-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let output = get_tape_output(&ir, MmapTape::open(&path).unwrap());
+        assert_eq!(output, Ok(vec![b'0', 0]));
+        let output = get_tape_output(&ir, MmapTape::open(&path).unwrap());
+        assert_eq!(output, Ok(vec![b'`', 0]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tape_images() {
+        let image = TapeImage::parse_hex("ptr 1\n41 42 # comment\n4344").unwrap();
+        assert_eq!(image, TapeImage { cells: b"ABCD".to_vec(), ptr: 1 });
+        assert!(TapeImage::parse_hex("4").is_err());
+
+        let ir = ir::build_ir(b".>>+.<").unwrap();
+        let path = env::temp_dir().join(format!("bfc-dump-test-{}", process::id()));
+        let mut output = Vec::new();
+        {
+            let tape = SparseTape::<Wrapping<i8>>::default();
+            let interpreter = backend::Interpreter::with_tape(Cursor::new(vec![]), &mut output,
+                                                              tape, None)
+                .with_image(image)
+                .with_dump(&path);
+            backend::use_backend(interpreter, &ir).unwrap();
+        }
+        assert_eq!(output, b"BE");
+
+        let dump = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(dump, "ptr 2\n41 42 43 45\n");
+        let image = TapeImage::parse_hex(&dump).unwrap();
+        assert_eq!(image, TapeImage { cells: b"ABCE".to_vec(), ptr: 2 });
+
+        // dumped when the input runs out, refused once the pointer wrapped
+        let ir = ir::build_ir(b"+>++,").unwrap();
+        let interpreter = backend::Interpreter::new(Cursor::new(vec![]), Vec::new(), None)
+            .with_dump(&path);
+        assert!(backend::use_backend(interpreter, &ir).is_err());
+        let dump = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(dump.starts_with("ptr 1\n01 02 00"));
+
+        let ir = ir::build_ir(b"<+").unwrap();
+        let tape = SparseTape::<Wrapping<i8>>::default();
+        let interpreter = backend::Interpreter::with_tape(Cursor::new(vec![]), Vec::new(),
+                                                          tape, None)
+            .with_dump(&path);
+        let err = backend::use_backend(interpreter, &ir).unwrap_err();
+        assert_eq!(err.exit_status(), 74);
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn exit_status() {
+        let ir = ir::build_ir(b"+++>-").unwrap();
+        let interpreter = backend::Interpreter::new(Cursor::new(vec![]), Vec::new(), None);
+        assert_eq!(backend::use_backend(interpreter, &ir).unwrap(), 255);
+
+        let ir = ir::build_ir(b",").unwrap();
+        let interpreter = backend::Interpreter::new(Cursor::new(vec![]), Vec::new(), None);
+        let err = backend::use_backend(interpreter, &ir).unwrap_err();
+        assert_eq!(err.exit_status(), 66);
+
+        // compiled programs stop the same way at EOF
+        let ir = ir::build_ir(b"+.,+.").unwrap();
+        let mut module = backend::use_backend(backend::LLVMBackend::new(), &ir).unwrap();
+        let mut output = Vec::new();
+        let err = module.run_with(Cursor::new(vec![]), &mut output).unwrap_err();
+        assert_eq!(err.to_str().unwrap(), backend::llvm::EOF_ERROR);
+        assert_eq!(output, [1]);
+
+        let exe_path = env::temp_dir().join(format!("bfc-eof-test-{}", process::id()));
+        let c_path = exe_path.with_extension("c");
+        let mut code = Vec::new();
+        backend::use_backend(backend::CBackend::new(&mut code), &ir).unwrap();
+        fs::write(&c_path, code).unwrap();
+        let args = [c_path.as_os_str(), "-o".as_ref(), exe_path.as_os_str()];
+        if let Some(output) = backend::run_tool("cc", &args, b"") {
+            assert!(output.status.success());
+            let output = backend::run_tool(exe_path.to_str().unwrap(), &[], b"").unwrap();
+            fs::remove_file(&exe_path).unwrap();
+            assert_eq!(output.status.code(), Some(backend::EOF_STATUS));
+            assert_eq!(output.stdout, [1]);
+        }
+        fs::remove_file(&c_path).unwrap();
+    }
+
+    #[test]
+    fn loop_profiles() {
+        let ir = ir::build_ir(b"++[>+++[>++++[>+<-]<-]<-]>>>.").unwrap();
+        let path = env::temp_dir().join(format!("bfc-profile-test-{}", process::id()));
+        let mut output = Vec::new();
+        {
+            let interpreter = backend::Interpreter::new(Cursor::new(vec![]), &mut output, None)
+                .with_profile(&path);
+            backend::use_backend(interpreter, &ir).unwrap();
+        }
+        assert_eq!(output, [24]);
+
+        let profile = Profile::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let counts: Vec<_> = profile.loops.iter().map(|c| (c.entries, c.iterations)).collect();
+        assert_eq!(counts, [(1, 2), (2, 6), (6, 24)]);
+        assert!(profile.matches(&ir));
+        assert!(!profile.matches(&opt::run_opts(ir.clone())));
+        assert_eq!(LoopCounts { entries: 250, iterations: 1000 }.unroll_count(), 4);
+        assert_eq!(LoopCounts { entries: 1, iterations: 999 }.unroll_count(), 1);
+
+        let backend = backend::LLVMBackend::new().with_profile(profile.clone());
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        module.optimize_level(OptLevel::O3);
+        let mut output = Vec::new();
+        module.run_with(Cursor::new(vec![]), &mut output).unwrap();
+        assert_eq!(output, [24]);
+
+        let mut code = Vec::new();
+        backend::use_backend(backend::CBackend::new(&mut code).with_profile(profile), &ir)
+            .unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("while(__builtin_expect(*ptr != 0, 1)) {"));
+    }
+
+    #[test]
+    fn tiered_execution() {
+        // the loop moves two cells to the left, past the start of the tape
+        let ir = ir::build_ir(b"[-<<+.>>]").unwrap();
+        let backend = backend::LLVMBackend::new()
+            .with_tape_size(4)
+            .with_wrap()
+            .with_external_tape();
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        let mut tape = [0, 2, 0, 9];
+        let mut index = 1;
+        let mut output = Vec::new();
+        module.run_on_tape(Cursor::new(vec![]), &mut output, &mut tape, &mut index, None).unwrap();
+        assert_eq!(output, [10, 11]);
+        assert_eq!((tape, index), ([0, 0, 0, 11], 1));
+        assert!(module.run_with(Cursor::new(vec![]), Vec::new()).is_err());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/bf_ex/squares.bf");
+        let ir = opt::run_opts(ir::build_ir(&fs::read(path).unwrap()).unwrap());
+        let expected = get_output(&ir, &vec![]).unwrap();
+        let mut output = Vec::new();
+        {
+            let interpreter = backend::Interpreter::new(Cursor::new(vec![]), &mut output, None)
+                .with_tiering(1);
+            backend::use_backend(interpreter, &ir).unwrap();
+        }
+        assert_eq!(output, expected);
+
+        // loops reading input stay interpreted
+        let ir = ir::build_ir(b"+[,.]").unwrap();
+        let interpreter = backend::Interpreter::new(Cursor::new(b"ab".to_vec()), Vec::new(), None)
+            .with_tiering(0);
+        let err = backend::use_backend(interpreter, &ir).unwrap_err();
+        assert_eq!(err.exit_status(), 66);
+
+        let interpreter = backend::Interpreter::new(Cursor::new(vec![]), Vec::new(), Some(10))
+            .with_tiering(1);
+        let err = backend::use_backend(interpreter, &ir).unwrap_err();
+        assert_eq!(err.exit_status(), 64);
+
+        // the native loops run out of fuel where the interpreter does,
+        // whichever loops are compiled by then
+        let ir = opt::run_opts(ir::build_ir(&fs::read(path).unwrap()).unwrap());
+        let run_with_fuel = |tiering: Option<usize>| {
+            let mut output = Vec::new();
+            let result = {
+                let mut interpreter = backend::Interpreter::new(Cursor::new(vec![]),
+                                                                &mut output, None)
+                    .with_fuel(2000);
+                if let Some(threshold) = tiering {
+                    interpreter = interpreter.with_tiering(threshold);
+                }
+                backend::use_backend(interpreter, &ir)
+            };
+            (result.unwrap_err().exit_status(), output)
+        };
+        let (status, output) = run_with_fuel(None);
+        assert_eq!(status, backend::OUT_OF_FUEL_STATUS);
+        assert!(output.len() < expected.len());
+        assert_eq!(run_with_fuel(Some(1)), (status, output));
+    }
+
+    #[test]
+    fn fuel() {
+        let ir = ir::build_ir(b"+++[-.]").unwrap();
+        for &(fuel, ran_out) in &[(3, false), (2, true)] {
+            let mut output = Vec::new();
+            let result = {
+                let interpreter = backend::Interpreter::new(Cursor::new(vec![]), &mut output, None)
+                    .with_fuel(fuel);
+                backend::use_backend(interpreter, &ir)
+            };
+            assert_eq!(result.is_err(), ran_out);
+            if let Err(err) = result {
+                assert_eq!(err.exit_status(), backend::OUT_OF_FUEL_STATUS);
+            }
+
+            let backend = backend::LLVMBackend::new().with_fuel(fuel);
+            let mut module = backend::use_backend(backend, &ir).unwrap();
+            let mut jit_output = Vec::new();
+            let result = module.run_with(Cursor::new(vec![]), &mut jit_output);
+            assert_eq!(jit_output, output);
+            assert_eq!(result.is_err(), ran_out);
+            if let Err(err) = result {
+                assert_eq!(err.to_str().unwrap(), backend::llvm::OUT_OF_FUEL_ERROR);
+            }
+        }
+
+        // the index of an external tape is written back when the fuel runs out
+        let ir = ir::build_ir(b">++[-]").unwrap();
+        let backend = backend::LLVMBackend::new()
+            .with_tape_size(4)
+            .with_external_tape()
+            .with_fuel(1);
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        let (mut tape, mut index) = ([0; 4], 0);
+        assert!(module.run_on_tape(Cursor::new(vec![]), Vec::new(), &mut tape, &mut index, None)
+            .is_err());
+        assert_eq!((tape, index), ([0, 1, 0, 0], 1));
+
+        // the fuel of the caller is shared by the runs
+        let ir = ir::build_ir(b"[-.]").unwrap();
+        let backend = backend::LLVMBackend::new()
+            .with_tape_size(4)
+            .with_external_tape()
+            .with_external_fuel();
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        let (mut tape, mut index, mut fuel) = ([3, 0, 0, 0], 0, 5);
+        let mut output = Vec::new();
+        let result = module.run_on_tape(Cursor::new(vec![]), &mut output, &mut tape, &mut index,
+                                        Some(&mut fuel));
+        assert_eq!((result, fuel), (Ok(0), 2));
+        tape[0] = 3;
+        let result = module.run_on_tape(Cursor::new(vec![]), &mut output, &mut tape, &mut index,
+                                        Some(&mut fuel));
+        assert_eq!(result.unwrap_err().to_str().unwrap(), backend::llvm::OUT_OF_FUEL_ERROR);
+        assert_eq!((output, tape[0], fuel), (vec![2, 1, 0, 2, 1], 1, 0));
+        assert!(module.run_on_tape(Cursor::new(vec![]), Vec::new(), &mut tape, &mut index, None)
+            .is_err());
+
+        // the C function returns the status rather than exiting
+        let mut code = Vec::new();
+        let c_backend = backend::CBackend::new(&mut code).with_fuel(1);
+        backend::use_backend(c_backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("if (!bf_fuel--) {\n\t\t\tbf_status = 152;\n\t\t\tgoto bf_exit;"));
+    }
+
+    #[test]
+    fn c_options() {
+        let ir = ir::build_ir(b"+[>-]").unwrap();
+        let options = CBackendOptions {
+            tape_size: 16,
+            cell_type: CCellType::U32,
+            heap_tape: true,
+            standard: CStandard::C89,
+            indent: String::from("  "),
+            function_name: Some(String::from("bf_run")),
+            library: false,
+        };
+        let mut code = Vec::new();
+        let backend = backend::CBackend::new(&mut code)
+            .with_options(options.clone())
+            .with_image(TapeImage { cells: vec![1, 255], ptr: 1 });
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(!code.contains("stdint.h") && !code.contains("_Static_assert"));
+        assert!(code.contains("  1, 255,\n"));
+        assert!(code.contains("static unsigned long *memory;"));
+        assert!(code.contains("int bf_run(void) {\n  int bf_status;"));
+        assert!(code.contains("    *(ptr + 0) += (unsigned long)-1;"));
+        assert!(code.contains("  free(memory);"));
+
+        let mut code = Vec::new();
+        let c11_options = CBackendOptions { standard: CStandard::C11, ..options.clone() };
+        backend::use_backend(backend::CBackend::new(&mut code).with_options(c11_options), &ir)
+            .unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("_Static_assert(BF_TAPE_SIZE <= SIZE_MAX / sizeof *ptr, "));
+
+        let backend = backend::CBackend::new(Vec::new())
+            .with_options(options)
+            .with_image(TapeImage { cells: vec![0; 17], ptr: 0 });
+        assert!(backend::use_backend(backend, &ir).is_err());
+    }
+
+    #[test]
+    fn c_library() {
+        let ir = ir::build_ir(b",[.,]").unwrap();
+        let options = CBackendOptions {
+            function_name: Some(String::from("cat")),
+            library: true,
+            ..CBackendOptions::default()
+        };
+        let mut header = Vec::new();
+        backend::c::write_library_header(&mut header, &options).unwrap();
+        let header = String::from_utf8(header).unwrap();
+        assert!(header.contains("#define CAT_TAPE_SIZE 30000"));
+        assert!(header.ends_with("int cat(bf_state *st);\n#endif\n"));
+
+        let mut code = Vec::new();
+        let backend = backend::CBackend::new(&mut code).with_options(options.clone());
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(!code.contains("getchar") && !code.contains("putchar"));
+        assert!(code.contains("\tst->write(st->ctx, (uint8_t)*(ptr + 0));"));
+        assert!(code.contains("bf_exit:\n\tst->ptr = ptr - memory;\n\treturn bf_status;\n}\n"));
+
+        let backend = backend::CBackend::new(Vec::new()).with_options(options).with_fuel(10);
+        assert!(backend::use_backend(backend, &ir).is_err());
+    }
+
+    #[test]
+    fn c_source_lines() {
+        let source = b"+\ncopy [->+<]".to_vec();
+        let (ir, spans) = ir::build_ir_with_spans(&source).unwrap();
+        let mut code = Vec::new();
+        let backend = backend::CBackend::new(&mut code)
+            .with_source("copy.bf", source.clone(), spans.clone());
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(code.contains("#line 1 \"copy.bf\"\n\t*(ptr + 0) += 1;"));
+        assert!(code.contains("\t/* [->+<] */\n#line 2 \"copy.bf\"\n\twhile(*ptr) {"));
+
+        // optimized IR isn't located
+        let ir = opt::run_opts(ir);
+        let mut code = Vec::new();
+        let backend = backend::CBackend::new(&mut code).with_source("copy.bf", source, spans);
+        backend::use_backend(backend, &ir).unwrap();
+        let code = String::from_utf8(code).unwrap();
+        assert!(!code.contains("#line"));
+        assert!(code.contains("\t\tint8_t *cell_p1 = ptr + 1;\n\t\t*cell_p1 += *(ptr) * 1;"));
+    }
+
+    #[test]
+    fn wasm_backend() {
+        // returns the current cell and the output
+        fn run_wasm(module: &backend::WasmModule, input: Vec<u8>) -> (i32, Vec<u8>) {
+            let engine = wasmi::Engine::default();
+            let module = wasmi::Module::new(&engine, &module.binary()[..]).unwrap();
+            let mut store = wasmi::Store::new(&engine, (input, Vec::new()));
+            let mut linker = wasmi::Linker::new(&engine);
+            linker.func_wrap("env", "read", |mut caller: wasmi::Caller<(Vec<u8>, Vec<u8>)>| {
+                let input = &mut caller.data_mut().0;
+                if input.is_empty() { -1 } else { input.remove(0) as i32 }
+            }).unwrap();
+            linker.func_wrap("env", "write", |mut caller: wasmi::Caller<(Vec<u8>, Vec<u8>)>,
+                                              byte: i32| {
+                caller.data_mut().1.push(byte as u8);
+            }).unwrap();
+            let instance = linker.instantiate(&mut store, &module).unwrap()
+                .start(&mut store).unwrap();
+            let run = instance.get_typed_func::<(), i32>(&store, "run").unwrap();
+            let cell = run.call(&mut store, ()).unwrap();
+            (cell, store.into_data().1)
+        }
+
+        // the multiplication accesses the cell left of the start, which is
+        // the last one
+        let ir = opt::run_opts(ir::build_ir(b"[-<+>],[.-]+,").unwrap());
+        let backend = backend::WasmBackend::new(backend::WasmIo::Imports);
+        let module = backend::use_backend(backend, &ir).unwrap();
+        assert_eq!(module.tape_address, 0);
+        assert_eq!(run_wasm(&module, vec![3, 7]), (7, vec![3, 2, 1]));
+        assert_eq!(run_wasm(&module, vec![3]), (-2, vec![3, 2, 1]));
+        assert!(module.text().contains("(func $run (export \"run\") (type $t2) (result i32)"));
+
+        let backend = backend::WasmBackend::new(backend::WasmIo::Imports).with_fuel(2);
+        let module = backend::use_backend(backend, &ir).unwrap();
+        assert_eq!(run_wasm(&module, vec![3]), (-1, vec![3, 2]));
+
+        // the pointer wraps around both ends of the tape
+        let ir = ir::build_ir(b"-<+++++>>>>.<<<<<.>>.").unwrap();
+        for ir in [ir.clone(), opt::run_opts(ir)] {
+            let backend = backend::WasmBackend::new(backend::WasmIo::Imports).with_tape_size(4);
+            let module = backend::use_backend(backend, &ir).unwrap();
+            assert_eq!(run_wasm(&module, vec![]), (255, vec![5, 0, 255]));
+        }
+    }
+
+    #[test]
+    fn llvm_host_io() {
+        let ir = ir::build_ir(b",.,.,.,.,.[-]+++").unwrap();
+        let backend = backend::LLVMBackend::new();
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+
+        let mut output = Vec::new();
+        let cell = module.run_with(Cursor::new(b"hello".to_vec()), &mut output).unwrap();
+        assert_eq!(output, b"hello");
+        assert_eq!(cell, 3);
+
+        // a CR ending a run doesn't skip the LF starting the next one
+        let ir = ir::build_ir(b",.,.").unwrap();
+        let io = IoOptions { encoding: IoEncoding::Bytes, translate_newlines: true };
+        let backend = backend::LLVMBackend::new().with_io(io);
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        for &(input, expected) in &[(b"a\r", b"a\n"), (b"\nb", b"\nb")] {
+            let mut output = Vec::new();
+            module.run_with(Cursor::new(input.to_vec()), &mut output).unwrap();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn llvm_parallel_jit() {
+        let threads: Vec<_> = (0..4).map(|thread| {
+            std::thread::spawn(move || {
+                for i in 0..8u8 {
+                    let code = format!("{}.", "+".repeat((b'a' + thread * 8 + i) as usize));
+                    let ir = ir::build_ir(code.as_bytes()).unwrap();
+                    let backend = backend::LLVMBackend::new();
+                    let mut module = backend::use_backend(backend, &ir).unwrap();
+                    module.optimize();
+
+                    let mut output = Vec::new();
+                    module.run_with(Cursor::new(vec![]), &mut output).unwrap();
+                    assert_eq!(output, [b'a' + thread * 8 + i]);
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn source_spans() {
+        let (ir, spans) = ir::build_ir_with_spans(b"+ ,\n [.[-]]").unwrap();
+        assert!(ir::spans_match(&ir, &spans));
+        let span = |start, end, line, column| ir::Span { start, end, line, column };
+        assert_eq!(spans[1].span, span(2, 3, 1, 3));
+        assert_eq!(spans[2].span, span(5, 11, 2, 2));
+        assert_eq!(spans[2].sub[0].span, span(6, 7, 2, 3));
+        let (combined, combined_spans) = ir::build_ir_with_spans(b"++").unwrap();
+        assert!(!ir::spans_match(&opt::run_opts(combined), &combined_spans));
+
+        let backend = backend::LLVMBackend::new().with_debug_info("test.bf", spans);
+        let mut module = backend::use_backend(backend, &ir).unwrap();
+        let path = env::temp_dir().join(format!("bfc-debug-test-{}.ll", process::id()));
+        module.write_output(&path, OutputFormat::LlvmIr).unwrap();
+        let code = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(code.contains("!dbg !"));
+        assert!(code.contains("distinct !DICompileUnit(language: DW_LANG_C"));
+        assert!(code.contains("!DILocation(line: 2, column: 3, scope: "));
+        assert!(code.contains("!DILocalVariable(name: \"ptr\""));
+        let mut output = Vec::new();
+        module.run_with(Cursor::new(b"a".to_vec()), &mut output).unwrap();
+        assert_eq!(output, b"a");
+    }
+
+    #[test]
+    fn llvm_passes() {
+        let ir = ir::build_ir(b",[->++<]>.").unwrap();
+        let path = env::temp_dir().join(format!("bfc-passes-test-{}.ll", process::id()));
+        let allocas = |module: &mut backend::llvm::LLVMBrainfuckModule| {
+            module.write_output(&path, OutputFormat::LlvmIr).unwrap();
+            fs::read_to_string(&path).unwrap().matches(" = alloca ").count()
+        };
+
+        for &level in &[OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3, OptLevel::Size] {
+            let mut module = backend::use_backend(backend::LLVMBackend::new(), &ir).unwrap();
+            let timings = module.optimize_level(level);
+            let passes: Vec<_> = timings.iter().map(|timing| timing.pass.as_str()).collect();
+            assert_eq!(passes, level.passes());
+            let mut output = Vec::new();
+            module.run_with(Cursor::new(vec![3]), &mut output).unwrap();
+            assert_eq!(output, [6]);
+        }
+
+        // nothing runs when a pass is unknown
+        let mut module = backend::use_backend(backend::LLVMBackend::new(), &ir).unwrap();
+        let unoptimized = allocas(&mut module);
+        let err = module.run_passes(&["mem2reg", "nope"]).unwrap_err();
+        assert_eq!(err.to_str().unwrap(), "unknown pass `nope`");
+        assert_eq!(allocas(&mut module), unoptimized);
+
+        let timings = module.run_passes(&["mem2reg", "instcombine"]).unwrap();
+        assert_eq!(timings.len(), 2);
+        assert_eq!((timings[0].pass.as_str(), timings[1].pass.as_str()),
+                   ("mem2reg", "instcombine"));
+        assert!(allocas(&mut module) < unoptimized);
+        let _ = fs::remove_file(&path);
+
+        module.set_codegen_level(0);
+        let mut output = Vec::new();
+        module.run_with(Cursor::new(vec![3]), &mut output).unwrap();
+        assert_eq!(output, [6]);
+    }
+
+    #[test]
+    fn llvm_wrapping_tape() {
+        let ir = ir::build_ir(b"<<+.>>>>>>++.<[-<+>]<.").unwrap();
+        for &size in &[5, 16, 30000] {
+            let expected = get_tape_output(&ir, VecTape::<Wrapping<i8>>::new(size)).unwrap();
+
+            let backend = backend::LLVMBackend::new().with_tape_size(size).with_wrap();
+            let mut module = backend::use_backend(backend, &ir).unwrap();
+            let mut output = Vec::new();
+            module.run_with(Cursor::new(vec![]), &mut output).unwrap();
+            assert_eq!(output, expected);
+        }
+    }
 }
@@ -1,66 +1,83 @@
 use itertools::Itertools;
 
-use ir::Atom;
+use ir::{Atom, CellType, Value};
 use ir::Atom::*;
 
 const OPT_N_RUN: usize = 2;
 
-pub fn run_opts(mut ir: Vec<Atom>) -> Vec<Atom> {
-    let opts = [
+type Pass = fn(Vec<Atom>, CellType) -> Vec<Atom>;
+
+pub fn run_opts(ir: Vec<Atom>) -> Vec<Atom> {
+    run_opts_with_cells(ir, CellType::I8)
+}
+
+pub fn run_opts_with_cells(mut ir: Vec<Atom>, cells: CellType) -> Vec<Atom> {
+    let opts: [Pass; 10] = [
         combine,
-        clean,
+        |ir, _| clean(ir),
         zero_loops,
-        offset_op,
-        reorder,
+        |ir, _| offset_op(ir),
+        |ir, _| reorder(ir),
         add_multiply,
-        add_reset_after_loop,
+        |ir, _| add_reset_after_loop(ir),
         combine,
-        remove_reset_after_loop,
-        clean
+        |ir, _| remove_reset_after_loop(ir),
+        |ir, _| clean(ir)
     ];
 
     for _ in 0..OPT_N_RUN {
         for opt in &opts {
-            ir = opt(ir);
+            ir = opt(ir, cells);
         }
     }
     
     ir
 }
 
-fn combine(ir: Vec<Atom>) -> Vec<Atom> {
-    fn combiner(a: Atom, b: Atom) -> Result<Atom, (Atom, Atom)> {
+fn combine(ir: Vec<Atom>, cells: CellType) -> Vec<Atom> {
+    let combiner = |a: Atom, b: Atom| -> Result<Atom, (Atom, Atom)> {
         match (a, b) {
             (MovePtr(av), MovePtr(bv)) => Ok(MovePtr(av.wrapping_add(bv))),
             (IncValue(av, o1), IncValue(bv, o2)) if o1 == o2 => {
-                Ok(IncValue(av.wrapping_add(bv), o1))
+                match cells.add(av, bv) {
+                    Some(value) => Ok(IncValue(value, o1)),
+                    None => Err((IncValue(av, o1), IncValue(bv, o2))),
+                }
             },
             (IncValue(_, o1), SetValue(sv, o2)) if o1 == o2 => {
                 Ok(SetValue(sv, o1))
             },
             (SetValue(sv, o1), IncValue(ov, o2)) if o1 == o2 => {
-                Ok(SetValue(sv.wrapping_add(ov), o1))
+                match cells.add(sv, ov) {
+                    Some(value) => Ok(SetValue(value, o1)),
+                    None => Err((SetValue(sv, o1), IncValue(ov, o2))),
+                }
             },
             (SetValue(_, o1), SetValue(sv, o2)) if o1 == o2 => {
                 Ok(SetValue(sv, o1))
             },
             (a, b) => Err((a, b))
         }
-    }
+    };
 
     ir.into_iter().map(|atom| {
         if let Loop(sub) = atom {
-            Loop(combine(sub))
+            Loop(combine(sub, cells))
         } else {
             atom
         }
     }).coalesce(combiner).collect()
 }
 
-fn zero_loops(ir: Vec<Atom>) -> Vec<Atom> {
+// `[-]` only terminates for every starting value when cells wrap around
+fn zero_loops(ir: Vec<Atom>, cells: CellType) -> Vec<Atom> {
+    if !cells.wraps() {
+        return ir;
+    }
+
     ir.into_iter().map(|atom| {
         if let Atom::Loop(sub) = atom {
-            let new_sub = zero_loops(sub);
+            let new_sub = zero_loops(sub, cells);
             if new_sub == [Atom::IncValue(-1, 0)] {
                 Atom::SetValue(0, 0)
             } else {
@@ -162,15 +179,18 @@ fn reorder(ir: Vec<Atom>) -> Vec<Atom> {
     new_ir
 }
 
-fn add_multiply(ir: Vec<Atom>) -> Vec<Atom> {
+// same as `zero_loops`, a loop decrementing its counter by one only
+// terminates for every starting value when cells wrap around
+fn add_multiply(ir: Vec<Atom>, cells: CellType) -> Vec<Atom> {
     // really returns a Vec<Atom> to be directly extended in upper "loop"
-    fn work_on_loop(loop_content: Vec<Atom>) -> Vec<Atom> {
-        use std::collections::HashMap;
+    fn work_on_loop(loop_content: Vec<Atom>, cells: CellType) -> Vec<Atom> {
+        // ordered so that the IR, which keys profiles, is deterministic
+        use std::collections::BTreeMap;
 
         let save = loop_content.clone();
 
         let mut total_ptr_offset = 0isize;
-        let mut increments = HashMap::new();
+        let mut increments: BTreeMap<isize, Value> = BTreeMap::new();
         for atom in loop_content {
             match atom {
                 MovePtr(offset) => {
@@ -179,8 +199,8 @@ fn add_multiply(ir: Vec<Atom>) -> Vec<Atom> {
                 IncValue(inc, offset) => {
                     let old = increments.entry(
                         offset.wrapping_add(total_ptr_offset)
-                    ).or_insert(0i8);
-                    *old = old.wrapping_add(inc);
+                    ).or_insert(0);
+                    *old = cells.normalize(old.wrapping_add(inc));
                 }
                 _ => return vec![Atom::Loop(save)]
             }
@@ -205,11 +225,15 @@ fn add_multiply(ir: Vec<Atom>) -> Vec<Atom> {
         return vec![Atom::Loop(save)]
     }
 
+    if !cells.wraps() {
+        return ir;
+    }
+
     let mut new_ir = Vec::with_capacity(ir.len());
     for atom in ir {
         if let Atom::Loop(sub) = atom {
-            let sub = add_multiply(sub);
-            new_ir.extend(work_on_loop(sub));
+            let sub = add_multiply(sub, cells);
+            new_ir.extend(work_on_loop(sub, cells));
         } else {
             new_ir.push(atom);
         }